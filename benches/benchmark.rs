@@ -7,7 +7,8 @@ extern crate nacs;
 
 use criterion::*;
 
-use std::sync::Arc;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
 
 use tempdir::TempDir;
 
@@ -17,13 +18,45 @@ use rocksdb::rocksdb::{DBIterator, Snapshot, Writable};
 use rocksdb::{ReadOptions, SeekKey, WriteBatch, WriteOptions, DB};
 
 use nacs::{
-    default_test_db_with_path, drain_data, Scanner, ScannerConfig, ValueType, DIST_QT1_KEY,
-    DIST_QT3_KEY,
+    default_test_db_with_path, drain_data, drain_data_with_config, DrainConfig, KeyMode, LockCheck,
+    ScanStats, Scanner, ScannerConfig, ValueType, WriteRatios, DIST_QT1_KEY, DIST_QT3_KEY,
 };
 
-use nacs::{forward_batch_scan, forward_scan};
+use nacs::cache::{CacheState, CachedDb, Warmup};
+use nacs::harness::{ScanHarness, SeekMode};
+use nacs::results::{format_bound, ResultFile, RunParams, RunRecord};
+use nacs::strategy::{ScanStrategy, StrategyRegistry};
+use profiler::perf::CounterValues;
 use std::rc::Rc;
-use std::cell::RefCell;
+
+/// Every scan is benchmarked with and without the seeks of `Scanner::new`.
+const SEEK_MODES: [SeekMode; 2] = [SeekMode::Excluded, SeekMode::Included];
+
+/// The ids of the seek-included scans end with `, seek included`.
+fn seek_suffix(seek_mode: SeekMode) -> &'static str {
+    match seek_mode {
+        SeekMode::Excluded => "",
+        SeekMode::Included => ", seek included",
+    }
+}
+
+/// The state is chosen by `BENCH_CACHE_STATE`, the ids of the states other than the default
+/// `warm-block` end with the state.
+fn cache_suffix(cache_state: CacheState) -> String {
+    match cache_state {
+        CacheState::WarmBlockCache => String::new(),
+        state => format!(", cache {}", state),
+    }
+}
+
+/// If the db is reopened before every iteration, there can't be more scanners at a time.
+fn batch_size(cache_state: CacheState) -> BatchSize {
+    if cache_state.reopens() {
+        BatchSize::PerIteration
+    } else {
+        BatchSize::SmallInput
+    }
+}
 
 fn bench_scan(c: &mut Criterion) {
     // handle config here
@@ -40,78 +73,436 @@ fn bench_scan(c: &mut Criterion) {
         ValueType::LongLongValue,
     ];
 
-    let mut common_write_vec = Vec::with_capacity(100 * 1024 * 1024);
-    let mut common_write_vec = Rc::new(RefCell::new(common_write_vec));
+    let mut records = vec![];
+
+    let cache_state = CacheState::from_env();
+    let cache_suffix = cache_suffix(cache_state);
+    let batch_size = batch_size(cache_state);
 
     for rocks_size in test_rocks_size {
         for defaultcf_value_length in &allow_values {
             let temp_dir = TempDir::new_in("data", "data").unwrap();
             println!("{:?}", temp_dir.path());
-            let mut db = default_test_db_with_path(temp_dir.path());
-            drain_data(&mut db, rocks_size, defaultcf_value_length.clone());
-            let db = Arc::new(db);
-            let cur_db = db.clone();
-            let cfg = common_cfg.clone();
-            let batch_size = BatchSize::SmallInput;
+            let mut db = default_test_db_with_path(temp_dir.path()).unwrap();
+            drain_data(&mut db, rocks_size, defaultcf_value_length.clone()).unwrap();
+            let db = CachedDb::new(db, temp_dir.path(), cache_state).unwrap();
+            let harness = Rc::new(ScanHarness::new(db, common_cfg.clone()));
 
+            let strategies: Vec<Rc<dyn ScanStrategy>> = StrategyRegistry::builtin()
+                .sweep_all()
+                .into_iter()
+                .map(Rc::from)
+                .collect();
 
             // 预热
-            println!("预热开始");
-            for _ in 0..50 {
-                let scanner = Scanner::new(cur_db.clone(), common_cfg.clone());
-                forward_scan(scanner, rocks_size / 2);
-                let scanner = Scanner::new(cur_db.clone(), common_cfg.clone());
-                forward_batch_scan(scanner, 128, rocks_size / 2, &mut (*common_write_vec).borrow_mut());
-            }
-            println!("预热完毕");
+            let warmup_runs = if cache_state.needs_warmup() {
+                println!("预热开始");
+                let report = Warmup::new().run(|| {
+                    let mut elapsed = Duration::default();
+                    let mut first = None;
+                    for strategy in &strategies {
+                        let cfg = strategy.scanner_config(harness.cfg());
+                        let (stats, time) = harness
+                            .measure_with(SeekMode::Excluded, &cfg, |scanner, write_cache| {
+                                strategy.run(scanner, rocks_size / 2, write_cache).unwrap()
+                            })
+                            .unwrap();
+                        // Every strategy reading every version reads the same rows.
+                        if strategy.reads_every_version() {
+                            assert_eq!(
+                                *first.get_or_insert(stats.rows),
+                                stats.rows,
+                                "{}",
+                                strategy.id()
+                            );
+                        }
+                        elapsed += time;
+                    }
+                    elapsed
+                });
+                println!("预热完毕: {}", report);
+                report.runs
+            } else {
+                0
+            };
 
             let vl = defaultcf_value_length.value();
 
+            for &seek_mode in &SEEK_MODES {
+                for strategy in &strategies {
+                    let cfg = strategy.scanner_config(harness.cfg());
+                    let params = RunParams {
+                        rows: rocks_size,
+                        value_type: format!("{:?}", defaultcf_value_length),
+                        variant: strategy.name().to_owned(),
+                        batch_size: strategy.param("batch_size"),
+                        lower: format_bound(&harness.cfg().lower_bound),
+                        upper: format_bound(&harness.cfg().upper_bound),
+                        warmup: warmup_runs,
+                        cache_state: cache_state.to_string(),
+                        seek_mode: seek_mode.to_string(),
+                        strategy: strategy.id(),
+                        lock_check: cfg.lock_check.to_string(),
+                        dataset: String::new(),
+                    };
+                    // e.g. `forward_batch_scan_20000_64_128`, `_seek` if the seeks are measured.
+                    let mut name = format!("{}_{}_{}", strategy.name(), rocks_size, vl);
+                    for (_, value) in strategy.params() {
+                        name.push_str(&format!("_{}", value));
+                    }
+                    if seek_mode == SeekMode::Included {
+                        name.push_str("_seek");
+                    }
+                    let record = record_run(
+                        &name,
+                        params,
+                        &harness,
+                        &cfg,
+                        seek_mode,
+                        |scanner, write_cache| {
+                            strategy.run(scanner, rocks_size / 2, write_cache).unwrap()
+                        },
+                    );
 
-            let c = c.bench_function(
-                &format!(
-                    "forward_scan(rocks db data size {}, value length {})",
-                    rocks_size, vl
-                ),
-                move |b| {
-                    b.iter_batched(
-                        || (Scanner::new(cur_db.clone(), cfg.clone())),
-                        |scanner| forward_scan(scanner, black_box(rocks_size / 2)),
-                        batch_size,
-                    )
-                },
+                    // e.g. `forward_batch_scan(rocks db data size 20000, value length 64)/rows/128`
+                    let group = format!(
+                        "{}(rocks db data size {}, value length {}{}{})",
+                        strategy.name(),
+                        rocks_size,
+                        vl,
+                        cache_suffix,
+                        seek_suffix(seek_mode)
+                    );
+                    let mut id = "rows".to_owned();
+                    for (_, value) in strategy.params() {
+                        id.push_str(&format!("/{}", value));
+                    }
+                    let harness = harness.clone();
+                    let strategy = strategy.clone();
+                    let cfg = cfg.clone();
+                    c.bench(
+                        &group,
+                        Benchmark::new(id, move |b| {
+                            b.iter_batched_ref(
+                                || harness.setup_with(seek_mode, &cfg).unwrap(),
+                                |input| {
+                                    harness.scan(input, |scanner, write_cache| {
+                                        strategy
+                                            .run(scanner, black_box(rocks_size / 2), write_cache)
+                                            .unwrap()
+                                    })
+                                },
+                                batch_size,
+                            )
+                        })
+                        .throughput(scan_throughput(record.rows)),
+                    );
+                    records.push(record);
+                }
+            }
+        }
+    }
+
+    save_records(records, "benchmark");
+}
+
+/// Runs of a scan to average the hardware counters over.
+const COUNTER_RUNS: u64 = 10;
+
+/// Times the scan phase of `COUNTER_RUNS` runs of `scan` and prints their hardware counters,
+/// which are `n/a` without the `perf` feature.
+fn record_run(
+    name: &str,
+    params: RunParams,
+    harness: &ScanHarness,
+    cfg: &ScannerConfig,
+    seek_mode: SeekMode,
+    mut scan: impl FnMut(&mut Scanner, &mut Vec<u8>) -> ScanStats,
+) -> RunRecord {
+    let mut stats = ScanStats::default();
+    let mut samples = Vec::with_capacity(COUNTER_RUNS as usize);
+    let mut counters: Option<CounterValues> = None;
+    for _ in 0..COUNTER_RUNS {
+        let mut input = harness.setup_with(seek_mode, cfg).unwrap();
+        let region = profiler::perf::region(name);
+        let start = Instant::now();
+        stats = harness.scan(&mut input, &mut scan);
+        samples.push(start.elapsed());
+        let values = region.finish().values;
+        harness.teardown(input);
+        match counters {
+            Some(ref mut c) => c.add(&values),
+            None => counters = Some(values),
+        }
+    }
+    println!("{}: {}", name, counters.unwrap().per(COUNTER_RUNS));
+    RunRecord::new(name, params, &stats, &samples)
+}
+
+/// The throughput of a scan in rows, which criterion counts in `u32`. Criterion takes one
+/// throughput per benchmark, the bytes read are kept in the run records.
+fn scan_throughput(rows: u64) -> Throughput {
+    Throughput::Elements(u32::try_from(rows).expect("too many rows for a throughput"))
+}
+
+/// Saves the run records of a bench function as `results/{prefix}-{timestamp}.json`.
+fn save_records(records: Vec<RunRecord>, prefix: &str) {
+    match (ResultFile { records }).save("results", prefix) {
+        Ok(path) => println!("results saved to {}", path.display()),
+        Err(e) => eprintln!("failed to save results: {}", e),
+    }
+}
+
+/// Benchmarks each of `scans`, a strategy id with the lock check of its scanner, on the dataset
+/// of `drain_cfg` through a `ScanHarness`, like `bench_scan`: in the cache state chosen by
+/// `BENCH_CACHE_STATE` and with and without the seeks, returning the run record of every scan.
+/// `label` tells the datasets apart in the ids, e.g. `versions 8, writes mixed`, the ids of the
+/// scans checking the locks end with the lock check.
+fn bench_dataset(
+    c: &mut Criterion,
+    label: &str,
+    drain_cfg: &DrainConfig,
+    scans: &[(&str, LockCheck)],
+) -> Vec<RunRecord> {
+    let registry = StrategyRegistry::builtin();
+    let cache_state = CacheState::from_env();
+    let limit = drain_cfg.data_scale / 2;
+
+    let temp_dir = TempDir::new_in("data", "data").unwrap();
+    let mut db = default_test_db_with_path(temp_dir.path()).unwrap();
+    drain_data_with_config(&mut db, drain_cfg).unwrap();
+    let db = CachedDb::new(db, temp_dir.path(), cache_state).unwrap();
+    let common_cfg = ScannerConfig::new(
+        Some(DIST_QT1_KEY.clone().to_vec()),
+        Some(DIST_QT3_KEY.clone().to_vec()),
+    )
+    .key_mode(drain_cfg.key_mode);
+    let harness = Rc::new(ScanHarness::new(db, common_cfg));
+
+    let scans: Vec<(Rc<dyn ScanStrategy>, ScannerConfig)> = scans
+        .iter()
+        .map(|&(id, lock_check)| {
+            let strategy: Rc<dyn ScanStrategy> = Rc::from(registry.parse_id(id).unwrap());
+            let cfg = strategy.scanner_config(&harness.cfg().clone().lock_check(lock_check));
+            (strategy, cfg)
+        })
+        .collect();
+    let warmup_runs = if cache_state.needs_warmup() {
+        let report = Warmup::new().run(|| {
+            let mut elapsed = Duration::default();
+            for (strategy, cfg) in &scans {
+                let (_, time) = harness
+                    .measure_with(SeekMode::Excluded, cfg, |scanner, write_cache| {
+                        strategy.run(scanner, limit, write_cache).unwrap()
+                    })
+                    .unwrap();
+                elapsed += time;
+            }
+            elapsed
+        });
+        report.runs
+    } else {
+        0
+    };
+
+    let vl = drain_cfg.value_type.value();
+    let mut records = vec![];
+    for &seek_mode in &SEEK_MODES {
+        for (strategy, cfg) in &scans {
+            let params = RunParams {
+                rows: drain_cfg.data_scale,
+                value_type: format!("{:?}", drain_cfg.value_type),
+                variant: strategy.name().to_owned(),
+                batch_size: strategy.param("batch_size"),
+                lower: format_bound(&harness.cfg().lower_bound),
+                upper: format_bound(&harness.cfg().upper_bound),
+                warmup: warmup_runs,
+                cache_state: cache_state.to_string(),
+                seek_mode: seek_mode.to_string(),
+                strategy: strategy.id(),
+                lock_check: cfg.lock_check.to_string(),
+                dataset: label.to_owned(),
+            };
+            // e.g. `mvcc_scan_20000_64_versions-8_writes-mixed`, `_lock-report` if the locks
+            // are checked and `_seek` if the seeks are measured.
+            let mut name = format!(
+                "{}_{}_{}_{}",
+                strategy.id(),
+                drain_cfg.data_scale,
+                vl,
+                label.replace(", ", "_").replace(' ', "-")
+            );
+            if cfg.lock_check != LockCheck::Off {
+                name.push_str(&format!("_lock-{}", cfg.lock_check));
+            }
+            if seek_mode == SeekMode::Included {
+                name.push_str("_seek");
+            }
+            let record = record_run(
+                &name,
+                params,
+                &harness,
+                cfg,
+                seek_mode,
+                |scanner, write_cache| strategy.run(scanner, limit, write_cache).unwrap(),
             );
 
-            let scan_batch_size = vec![64, 128, 256];
-
-            let cur_db = db.clone();
-            let cfg = common_cfg.clone();
-
-            let cur_write_vec = common_write_vec.clone();
-            c.bench_function_over_inputs(
-                &format!(
-                    "forward_batch_scan(rocks db data size {}, value length {})",
-                    rocks_size, vl
-                ),
-                move |b, &cnt| {
-                    b.iter_batched(
-                        || (Scanner::new(cur_db.clone(), cfg.clone()), cur_write_vec.clone()),
-                        |(scanner, mut v)| {
-                            forward_batch_scan(
-                                scanner,
-                                black_box(cnt),
-                                black_box(rocks_size / 2),
-                                &mut (*v).borrow_mut(),
-                            )
+            let lock_suffix = match cfg.lock_check {
+                LockCheck::Off => String::new(),
+                lock_check => format!(", lock check {}", lock_check),
+            };
+            // e.g. `mvcc_scan(rocks db data size 20000, value length 64, versions 8, writes mixed)/rows`
+            let group = format!(
+                "{}(rocks db data size {}, value length {}, {}{}{}{})",
+                strategy.name(),
+                drain_cfg.data_scale,
+                vl,
+                label,
+                lock_suffix,
+                cache_suffix(cache_state),
+                seek_suffix(seek_mode)
+            );
+            let mut id = "rows".to_owned();
+            for (_, value) in strategy.params() {
+                id.push_str(&format!("/{}", value));
+            }
+            let harness = harness.clone();
+            let strategy = strategy.clone();
+            let cfg = cfg.clone();
+            c.bench(
+                &group,
+                Benchmark::new(id, move |b| {
+                    b.iter_batched_ref(
+                        || harness.setup_with(seek_mode, &cfg).unwrap(),
+                        |input| {
+                            harness.scan(input, |scanner, write_cache| {
+                                strategy
+                                    .run(scanner, black_box(limit), write_cache)
+                                    .unwrap()
+                            })
                         },
-                        batch_size,
+                        batch_size(cache_state),
                     )
-                },
-                scan_batch_size,
+                })
+                .throughput(scan_throughput(record.rows)),
             );
+            records.push(record);
+        }
+    }
+    records
+}
+
+/// Compares scanning keys written by `Key::from_encoded_slice` with keys written by
+/// `Key::from_raw`, which have to be memcomparable-decoded during the scan.
+fn bench_raw_key_scan(c: &mut Criterion) {
+    let mut records = vec![];
+    for &key_mode in &[KeyMode::Encoded, KeyMode::Raw] {
+        let drain_cfg = DrainConfig::new(20000, ValueType::MiddleValue).key_mode(key_mode);
+        let label = format!("key mode {:?}", key_mode);
+        records.extend(bench_dataset(
+            c,
+            &label,
+            &drain_cfg,
+            &[("forward_scan", LockCheck::Off)],
+        ));
+    }
+    save_records(records, "benchmark_raw_key");
+}
+
+/// Compares the lockstep `forward_scan` with looking up the `CF_DEFAULT` row of every put, on
+/// datasets with one and with many versions of every key, and on one with every write type,
+/// which `forward_scan` can't scan.
+fn bench_lookup_scan(c: &mut Criterion) {
+    let drain_cfg = DrainConfig::new(20000, ValueType::MiddleValue);
+    let datasets = vec![
+        ("puts", 1, drain_cfg.clone(), "forward_scan"),
+        ("puts", 8, drain_cfg.clone(), "forward_scan"),
+        (
+            "mixed",
+            8,
+            drain_cfg.write_ratios(WriteRatios::new(2, 1, 1, 1)),
+            "write_only_scan",
+        ),
+    ];
+
+    let mut records = vec![];
+    for (writes, versions, drain_cfg, baseline) in datasets {
+        let scans = [baseline, "get_lookup_scan", "seek_lookup_scan"];
+        records.extend(bench_dataset(
+            c,
+            &format!("versions {}, writes {}", versions, writes),
+            &drain_cfg.versions_per_key(versions),
+            &scans
+                .iter()
+                .map(|&id| (id, LockCheck::Off))
+                .collect::<Vec<_>>(),
+        ));
+    }
+    save_records(records, "benchmark_lookup");
+}
+
+/// Compares `mvcc_scan` with `write_only_scan`, which reads every version, on datasets of
+/// many versions with puts only, with every write type, and with a range of mostly deleted
+/// keys in the scanned tables.
+fn bench_mvcc_scan(c: &mut Criterion) {
+    let drain_cfg = DrainConfig::new(20000, ValueType::MiddleValue).versions_per_key(8);
+    let datasets = vec![
+        ("puts", drain_cfg.clone()),
+        (
+            "mixed",
+            drain_cfg.clone().write_ratios(WriteRatios::new(2, 1, 1, 1)),
+        ),
+        (
+            "tombstones",
+            drain_cfg
+                .clone()
+                .ratios_in(5..25, WriteRatios::new(1, 8, 0, 1)),
+        ),
+    ];
+
+    let mut records = vec![];
+    for (writes, drain_cfg) in datasets {
+        records.extend(bench_dataset(
+            c,
+            &format!("versions {}, writes {}", drain_cfg.versions_per_key, writes),
+            &drain_cfg,
+            &[
+                ("write_only_scan", LockCheck::Off),
+                ("mvcc_scan", LockCheck::Off),
+            ],
+        ));
+    }
+    save_records(records, "benchmark_mvcc");
+}
+
+/// The overhead of checking `CF_LOCK`, on a dataset without locks and on datasets with pending
+/// locks, which fail the scans of `LockCheck::Fail` and so are only reported.
+fn bench_lock_check(c: &mut Criterion) {
+    let mut records = vec![];
+    for &pending_locks in &[0.0, 0.01, 0.1] {
+        let drain_cfg =
+            DrainConfig::new(20000, ValueType::MiddleValue).pending_locks(pending_locks);
+        let checks = if pending_locks > 0.0 {
+            vec![LockCheck::Off, LockCheck::Report]
+        } else {
+            vec![LockCheck::Off, LockCheck::Report, LockCheck::Fail]
+        };
+        let mut scans = vec![];
+        for id in &["forward_scan", "forward_batch_scan_256", "mvcc_scan"] {
+            scans.extend(checks.iter().map(|&lock_check| (*id, lock_check)));
         }
+        let label = format!("pending locks {}", pending_locks);
+        records.extend(bench_dataset(c, &label, &drain_cfg, &scans));
     }
+    save_records(records, "benchmark_lock_check");
 }
 
-criterion_group!(benches, bench_scan);
+criterion_group!(
+    benches,
+    bench_scan,
+    bench_raw_key_scan,
+    bench_lookup_scan,
+    bench_mvcc_scan,
+    bench_lock_check
+);
 criterion_main!(benches);
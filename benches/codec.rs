@@ -0,0 +1,263 @@
+extern crate criterion;
+
+extern crate nacs;
+
+use criterion::*;
+
+use nacs::tikv_code::bytes::{self, BytesEncoder};
+use nacs::tikv_code::key::Key;
+use nacs::tikv_code::number::{self, NumberEncoder};
+
+/// Key lengths to decode, 19 is the length of a record key.
+const KEY_LENGTHS: [usize; 6] = [8, 19, 32, 64, 128, 256];
+
+/// Values whose var-int encodings take 1, 3, 5, 8 and 10 bytes.
+const VAR_VALUES: [u64; 5] = [0x7f, 1 << 20, 1 << 30, 1 << 50, std::u64::MAX];
+
+/// Values encoded per iteration by the fixed-width number benchmarks.
+const NUMBER_BATCH: usize = 1024;
+
+fn encoded_key(len: usize, desc: bool) -> Vec<u8> {
+    let key: Vec<u8> = (0..len).map(|i| i as u8).collect();
+    if desc {
+        bytes::encode_bytes_desc(&key)
+    } else {
+        bytes::encode_bytes(&key)
+    }
+}
+
+fn bench_decode_bytes(c: &mut Criterion) {
+    for &desc in &[false, true] {
+        let decode_into = if desc {
+            bytes::decode_bytes_desc_into
+        } else {
+            bytes::decode_bytes_into
+        };
+
+        let benchmark = ParameterizedBenchmark::new(
+            "decode_bytes",
+            move |b, &len| {
+                let encoded = encoded_key(len, desc);
+                b.iter(|| bytes::decode_bytes(&mut black_box(encoded.as_slice()), desc).unwrap())
+            },
+            KEY_LENGTHS.to_vec(),
+        )
+        .with_function("decode_bytes_into", move |b, &len| {
+            let encoded = encoded_key(len, desc);
+            let mut buf = Vec::with_capacity(len);
+            b.iter(|| {
+                buf.clear();
+                decode_into(&mut black_box(encoded.as_slice()), &mut buf).unwrap();
+            })
+        })
+        .throughput(|&len| Throughput::Bytes(len as u32));
+
+        let order = if desc { "desc" } else { "asc" };
+        c.bench(&format!("memcomparable_decode_{}", order), benchmark);
+    }
+}
+
+fn numbers() -> Vec<i64> {
+    (0..NUMBER_BATCH as i64)
+        .map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15u64 as i64))
+        .collect()
+}
+
+fn bench_number(c: &mut Criterion) {
+    for &desc in &[false, true] {
+        let (encode_i64, decode_i64): (fn(&mut Vec<u8>, i64) -> _, fn(&mut &[u8]) -> _) = if desc {
+            (
+                <Vec<u8> as NumberEncoder>::encode_i64_desc,
+                number::decode_i64_desc,
+            )
+        } else {
+            (<Vec<u8> as NumberEncoder>::encode_i64, number::decode_i64)
+        };
+        let (encode_f64, decode_f64): (fn(&mut Vec<u8>, f64) -> _, fn(&mut &[u8]) -> _) = if desc {
+            (
+                <Vec<u8> as NumberEncoder>::encode_f64_desc,
+                number::decode_f64_desc,
+            )
+        } else {
+            (<Vec<u8> as NumberEncoder>::encode_f64, number::decode_f64)
+        };
+
+        let values = numbers();
+        let mut encoded_i64 = Vec::with_capacity(NUMBER_BATCH * number::I64_SIZE);
+        let mut encoded_f64 = Vec::with_capacity(NUMBER_BATCH * number::F64_SIZE);
+        for &v in &values {
+            encode_i64(&mut encoded_i64, v).unwrap();
+            encode_f64(&mut encoded_f64, v as f64).unwrap();
+        }
+
+        let order = if desc { "desc" } else { "asc" };
+        let group = format!("number_{}", order);
+        let benchmark = Benchmark::new("encode_i64", move |b| {
+            let mut buf = Vec::with_capacity(NUMBER_BATCH * number::I64_SIZE);
+            b.iter(|| {
+                buf.clear();
+                for &v in &values {
+                    encode_i64(&mut buf, black_box(v)).unwrap();
+                }
+            })
+        })
+        .with_function("decode_i64", move |b| {
+            b.iter(|| {
+                let mut data = black_box(encoded_i64.as_slice());
+                while !data.is_empty() {
+                    black_box(decode_i64(&mut data).unwrap());
+                }
+            })
+        })
+        .with_function("encode_f64", move |b| {
+            let values: Vec<f64> = numbers().into_iter().map(|v| v as f64).collect();
+            let mut buf = Vec::with_capacity(NUMBER_BATCH * number::F64_SIZE);
+            b.iter(|| {
+                buf.clear();
+                for &v in &values {
+                    encode_f64(&mut buf, black_box(v)).unwrap();
+                }
+            })
+        })
+        .with_function("decode_f64", move |b| {
+            b.iter(|| {
+                let mut data = black_box(encoded_f64.as_slice());
+                while !data.is_empty() {
+                    black_box(decode_f64(&mut data).unwrap());
+                }
+            })
+        })
+        .throughput(Throughput::Elements(NUMBER_BATCH as u32));
+        c.bench(&group, benchmark);
+    }
+}
+
+fn bench_var_number(c: &mut Criterion) {
+    let benchmark = ParameterizedBenchmark::new(
+        "encode_var_u64",
+        |b, &v| {
+            let mut buf = Vec::with_capacity(number::MAX_VAR_U64_LEN);
+            b.iter(|| {
+                buf.clear();
+                buf.encode_var_u64(black_box(v)).unwrap();
+            })
+        },
+        VAR_VALUES.to_vec(),
+    )
+    .with_function("decode_var_u64", |b, &v| {
+        let mut encoded = vec![];
+        encoded.encode_var_u64(v).unwrap();
+        b.iter(|| number::decode_var_u64(&mut black_box(encoded.as_slice())).unwrap())
+    })
+    .with_function("encode_var_i64", |b, &v| {
+        let mut buf = Vec::with_capacity(number::MAX_VAR_I64_LEN);
+        b.iter(|| {
+            buf.clear();
+            buf.encode_var_i64(black_box((v >> 1) as i64)).unwrap();
+        })
+    })
+    .with_function("decode_var_i64", |b, &v| {
+        let mut encoded = vec![];
+        encoded.encode_var_i64((v >> 1) as i64).unwrap();
+        b.iter(|| number::decode_var_i64(&mut black_box(encoded.as_slice())).unwrap())
+    });
+    c.bench("var_number", benchmark);
+}
+
+fn bench_encode_bytes(c: &mut Criterion) {
+    let benchmark = ParameterizedBenchmark::new(
+        "encode_bytes",
+        |b, &len| {
+            let key: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            b.iter(|| bytes::encode_bytes(black_box(&key)))
+        },
+        KEY_LENGTHS.to_vec(),
+    )
+    .with_function("encode_bytes_desc", |b, &len| {
+        let key: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        b.iter(|| bytes::encode_bytes_desc(black_box(&key)))
+    })
+    .with_function("encode_compact_bytes", |b, &len| {
+        let key: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        let mut buf = Vec::with_capacity(len + number::MAX_VAR_I64_LEN);
+        b.iter(|| {
+            buf.clear();
+            buf.encode_compact_bytes(black_box(&key)).unwrap();
+        })
+    })
+    .with_function("decode_compact_bytes", |b, &len| {
+        let key: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        let mut encoded = vec![];
+        encoded.encode_compact_bytes(&key).unwrap();
+        b.iter(|| bytes::decode_compact_bytes(&mut black_box(encoded.as_slice())).unwrap())
+    })
+    .throughput(|&len| Throughput::Bytes(len as u32));
+    c.bench("memcomparable_encode", benchmark);
+}
+
+fn bench_append_ts(c: &mut Criterion) {
+    let benchmark = ParameterizedBenchmark::new(
+        "append_ts",
+        |b, &len| {
+            let key = Key::from_encoded(encoded_key(len, false));
+            b.iter_batched(
+                || key.clone(),
+                |key| key.append_ts(black_box(42)),
+                BatchSize::SmallInput,
+            )
+        },
+        KEY_LENGTHS.to_vec(),
+    );
+    c.bench("key", benchmark);
+}
+
+/// Compares `is_user_key_eq` against a plain slice comparison for equal keys and keys that
+/// differ in their last or first byte.
+fn bench_user_key_eq(c: &mut Criterion) {
+    for &(case, diff_at_end) in &[
+        ("equal", None),
+        ("diff_last", Some(true)),
+        ("diff_first", Some(false)),
+    ] {
+        let user_key_pair = move |len: usize| {
+            let user_key = encoded_key(len, false);
+            let mut other = user_key.clone();
+            match diff_at_end {
+                Some(true) => *other.last_mut().unwrap() ^= 1,
+                Some(false) => other[0] ^= 1,
+                None => {}
+            }
+            let ts_key = Key::from_encoded(other).append_ts(42).into_encoded();
+            (ts_key, user_key)
+        };
+
+        let benchmark = ParameterizedBenchmark::new(
+            "is_user_key_eq",
+            move |b, &len| {
+                let (ts_key, user_key) = user_key_pair(len);
+                b.iter(|| Key::is_user_key_eq(black_box(&ts_key), black_box(&user_key)))
+            },
+            KEY_LENGTHS.to_vec(),
+        )
+        .with_function("slice_eq", move |b, &len| {
+            let (ts_key, user_key) = user_key_pair(len);
+            b.iter(|| {
+                let (ts_key, user_key) = (black_box(&ts_key), black_box(&user_key));
+                ts_key.len() == user_key.len() + number::U64_SIZE
+                    && ts_key[..user_key.len()] == user_key[..]
+            })
+        });
+        c.bench(&format!("user_key_eq_{}", case), benchmark);
+    }
+}
+
+criterion_group!(
+    benches,
+    bench_decode_bytes,
+    bench_encode_bytes,
+    bench_number,
+    bench_var_number,
+    bench_append_ts,
+    bench_user_key_eq
+);
+criterion_main!(benches);
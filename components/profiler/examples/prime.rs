@@ -25,6 +25,22 @@
 //! ```
 //!
 //! You must not run example via `valgrind cargo run ...`. The framework won't detect Callgrind!
+//!
+//! ### Run using the sampling profiler
+//!
+//! ```bash
+//! cargo build --features "sampling" --example prime
+//! ../../target/debug/examples/prime
+//! ```
+//!
+//! It writes `prime.profile.pb`, `prime.profile.collapsed` and `prime.profile.svg`.
+//!
+//! The time spent by each thread counting is written to `prime.profile.regions`.
+
+use std::sync::Arc;
+use std::thread;
+
+const THREADS: usize = 2;
 
 #[inline(never)]
 fn is_prime_number(v: usize, prime_numbers: &[usize]) -> bool {
@@ -66,19 +82,34 @@ fn prepare_prime_numbers() -> Vec<usize> {
     prime_numbers
 }
 
-fn main() {
-    let prime_numbers = prepare_prime_numbers();
-
-    profiler::start("prime.profile");
+fn main() -> profiler::Result<()> {
+    let prime_numbers = Arc::new(prepare_prime_numbers());
 
-    let mut v = 0;
-    for i in 2..50000 {
-        if is_prime_number(i, &prime_numbers) {
-            v += 1;
-        }
+    // Fails if neither `profiling` nor `sampling` feature is enabled.
+    let guard = profiler::profile("prime.profile");
+    if let Err(ref e) = guard {
+        println!("{}", e);
     }
 
-    profiler::stop();
+    // Each thread records the region `count` into the same profile.
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let prime_numbers = prime_numbers.clone();
+            thread::spawn(move || {
+                profiler::register_thread();
+                let _region = profiler::profile("count");
+                (2..50000)
+                    .filter(|i| i % THREADS == t && is_prime_number(*i, &prime_numbers))
+                    .count()
+            })
+        })
+        .collect();
+    let v: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+
+    if let Ok(guard) = guard {
+        guard.finish()?;
+    }
 
     println!("Prime numbers: {}", v);
+    Ok(())
 }
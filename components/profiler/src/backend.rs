@@ -0,0 +1,45 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::str::FromStr;
+
+/// The environment variable to choose the backend, e.g. `PROFILER_BACKEND=sampling`.
+pub const BACKEND_ENV: &str = "PROFILER_BACKEND";
+
+/// The profiler used by `start`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Backend {
+    /// Callgrind when running in Callgrind, otherwise CPU Profiler, or the sampling
+    /// profiler if only the `sampling` feature is enabled.
+    #[default]
+    Auto,
+    /// CPU Profiler from gperftools, needs the `profiling` feature.
+    GPerfTools,
+    /// Callgrind instrumentation, needs the `profiling` feature.
+    CallGrind,
+    /// The pure Rust SIGPROF based profiler, needs the `sampling` feature.
+    Sampling,
+}
+
+impl Backend {
+    /// Reads the backend from `PROFILER_BACKEND`, `Auto` if it is not set or unknown.
+    pub fn from_env() -> Backend {
+        std::env::var(BACKEND_ENV)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(Backend::Auto)
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Backend, String> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(Backend::Auto),
+            "gperftools" | "cpuprofiler" => Ok(Backend::GPerfTools),
+            "callgrind" => Ok(Backend::CallGrind),
+            "sampling" | "pprof" => Ok(Backend::Sampling),
+            _ => Err(format!("unknown profiler backend {}", s)),
+        }
+    }
+}
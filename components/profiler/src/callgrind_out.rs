@@ -0,0 +1,122 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Reads the event counts, e.g. `Ir` (instructions executed), of Callgrind output files.
+
+use std::fs;
+use std::path::Path;
+
+use crate::{Error, Result};
+
+/// The total count of each event of a Callgrind run, in the order of its `events:` line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallgrindTotals {
+    pub events: Vec<(String, u64)>,
+}
+
+impl CallgrindTotals {
+    pub fn get(&self, event: &str) -> Option<u64> {
+        self.events
+            .iter()
+            .find(|(e, _)| e == event)
+            .map(|(_, v)| *v)
+    }
+
+    /// Parses the `events:` line and the `totals:` (or `summary:`) line of an output file.
+    pub fn parse(content: &str) -> Result<CallgrindTotals> {
+        let (mut events, mut summary, mut totals) = (None, None, None);
+        for line in content.lines() {
+            if let Some(rest) = line.strip_prefix("events:") {
+                events = Some(rest);
+            } else if let Some(rest) = line.strip_prefix("summary:") {
+                summary = Some(rest);
+            } else if let Some(rest) = line.strip_prefix("totals:") {
+                totals = Some(rest);
+            }
+        }
+        let events = events.ok_or_else(|| Error::CallgrindOutput("no events line".to_owned()))?;
+        let counts = totals
+            .or(summary)
+            .ok_or_else(|| Error::CallgrindOutput("no totals line".to_owned()))?;
+
+        let counts = counts
+            .split_whitespace()
+            .map(|c| {
+                c.parse::<u64>()
+                    .map_err(|e| Error::CallgrindOutput(format!("bad count {}: {}", c, e)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Trailing zero counts may be omitted.
+        let events = events
+            .split_whitespace()
+            .enumerate()
+            .map(|(i, e)| (e.to_owned(), counts.get(i).cloned().unwrap_or(0)))
+            .collect();
+        Ok(CallgrindTotals { events })
+    }
+
+    /// Reads the output file `path` and the parts dumped into it by `CALLGRIND_DUMP_STATS`,
+    /// i.e. `{path}.1`, `{path}.2`, ..., summing their counts.
+    pub fn read(path: impl AsRef<Path>) -> Result<CallgrindTotals> {
+        let path = path.as_ref();
+        let (dir, base) = match (path.parent(), path.file_name().and_then(|f| f.to_str())) {
+            (Some(dir), Some(base)) => (dir, base),
+            _ => return Err(Error::CallgrindOutput(format!("bad path {:?}", path))),
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+
+        let mut totals: Option<CallgrindTotals> = None;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = match name.to_str() {
+                Some(name) => name,
+                None => continue,
+            };
+            if name != base && !name.starts_with(&format!("{}.", base)) {
+                continue;
+            }
+            let part = CallgrindTotals::parse(&fs::read_to_string(entry.path())?)?;
+            match totals {
+                Some(ref mut totals) => totals.add(&part),
+                None => totals = Some(part),
+            }
+        }
+        totals.ok_or_else(|| Error::CallgrindOutput(format!("{:?} not found", path)))
+    }
+
+    fn add(&mut self, other: &CallgrindTotals) {
+        for (event, count) in &other.events {
+            match self.events.iter_mut().find(|(e, _)| e == event) {
+                Some((_, c)) => *c += count,
+                None => self.events.push((event.clone(), *count)),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_read_callgrind_totals() {
+    let dir = std::env::temp_dir().join(format!("callgrind-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("scan.out");
+    let header = "# callgrind format\nversion: 1\ncmd: ./profile\npart: 1\n\nevents: Ir Dr Dw\n";
+    fs::write(
+        dir.join("scan.out.1"),
+        format!("{}\nfn=(1) main\n3 10 2\n\nsummary: 1000 200 30\n", header),
+    )
+    .unwrap();
+    fs::write(&path, format!("{}\ntotals: 7\nsummary: 7\n", header)).unwrap();
+    fs::write(dir.join("scan.outer"), "not a part").unwrap();
+
+    let totals = CallgrindTotals::read(&path).unwrap();
+    assert_eq!(totals.get("Ir"), Some(1007));
+    assert_eq!(totals.get("Dw"), Some(30));
+    assert_eq!(totals.get("Bc"), None);
+    assert!(CallgrindTotals::read(dir.join("missing.out")).is_err());
+    assert!(CallgrindTotals::parse("events: Ir\nsummary: x\n").is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
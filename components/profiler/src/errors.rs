@@ -0,0 +1,48 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::io;
+
+use quick_error::quick_error;
+
+use crate::Backend;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        /// The backend is not compiled in, see the `profiling` and `sampling` features.
+        NotEnabled(backend: Backend) {
+            description("profiler backend not enabled")
+            display("{:?} profiler backend not enabled", backend)
+        }
+        /// `start` is called while the profile `name` is being recorded.
+        InProgress(name: String) {
+            description("profiling in progress")
+            display("profiling {} in progress", name)
+        }
+        NotStarted {
+            description("no profiling in progress")
+        }
+        /// Hardware counters need the `perf` feature on Linux.
+        PerfNotEnabled {
+            description("perf counters not enabled")
+        }
+        /// A Callgrind output file can't be parsed.
+        CallgrindOutput(msg: String) {
+            description("bad callgrind output")
+            display("bad callgrind output: {}", msg)
+        }
+        /// Error reported by CPU Profiler or the sampling profiler.
+        Backend(msg: String) {
+            description("profiler backend error")
+            display("profiler backend error: {}", msg)
+        }
+        Io(err: io::Error) {
+            from()
+            cause(err)
+            description("io error")
+            display("io error: {}", err)
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
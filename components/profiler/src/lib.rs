@@ -1,7 +1,7 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
-//! Profile a part of the code using CPU Profiler from gperftools or Callgrind.
-//! Supports Linux and MacOS.
+//! Profile a part of the code using CPU Profiler from gperftools, Callgrind or a pure Rust
+//! sampling profiler. Supports Linux and MacOS.
 //!
 //! ## Requirements
 //!
@@ -23,9 +23,9 @@
 //! ## Usage
 //!
 //! ```ignore
-//! profiler::start("./app.profile");
+//! profiler::start("./app.profile")?;
 //! some_complex_code();
-//! profiler::stop();
+//! profiler::stop()?;
 //! ```
 //!
 //! Then, compile the code with `profiling` feature enabled.
@@ -40,16 +40,61 @@
 //! valgrind --tool=callgrind --instr-atstart=no ./my_example
 //! ```
 //!
+//! `callgrind_out::CallgrindTotals` reads the event counts of the output files, which are far
+//! less noisy than wall-clock times.
+//!
+//! ## Sampling profiler
+//!
+//! With the `sampling` feature, a SIGPROF based sampling profiler from
+//! [pprof-rs](https://github.com/tikv/pprof-rs) is available, which needs no native tools.
+//! It writes `app.profile.pb` (for pprof), `app.profile.collapsed` (collapsed stacks) and
+//! `app.profile.svg` (flamegraph) when profiling stops.
+//!
+//! The backend is chosen at runtime by `set_backend` or the `PROFILER_BACKEND` environment
+//! variable (`auto`, `gperftools`, `callgrind` or `sampling`). `auto` uses Callgrind when
+//! running in it, otherwise CPU Profiler, or the sampling profiler if only `sampling` is enabled.
+//!
+//! ## Scoped profiling
+//!
+//! `profile` returns a `ProfileGuard`, which starts profiling if it is not in progress and
+//! stops it when the last guard drops. Guards can be nested and created on other threads,
+//! all of them record into the same profile:
+//!
+//! ```ignore
+//! let _guard = profiler::profile("./app.profile")?;
+//! {
+//!     let _scan = profiler::profile("scan")?;
+//!     scan();
+//! }
+//! ```
+//!
+//! The time spent in every region is written to `app.profile.regions`, e.g.
+//! `./app.profile;scan 1 1024` for the region `scan` entered once for 1024us. Threads
+//! should call `register_thread` if CPU Profiler uses per thread timers.
+//!
+//! ## Hardware counters
+//!
+//! With the `perf` feature on Linux, `perf::region` counts cycles, instructions, cache misses
+//! and branch misses of a region by `perf_event_open`.
+//!
 //! Also see `examples/prime.rs`.
 
-#[cfg(all(unix, feature = "profiling"))]
+mod backend;
+pub mod callgrind_out;
+mod errors;
+pub mod perf;
+
+pub use backend::{Backend, BACKEND_ENV};
+pub use errors::{Error, Result};
+
+#[cfg(all(unix, any(feature = "profiling", feature = "sampling")))]
 mod profiler_unix;
 
-#[cfg(all(unix, feature = "profiling"))]
+#[cfg(all(unix, any(feature = "profiling", feature = "sampling")))]
 pub use profiler_unix::*;
 
-#[cfg(not(all(unix, feature = "profiling")))]
+#[cfg(not(all(unix, any(feature = "profiling", feature = "sampling"))))]
 mod profiler_dummy;
 
-#[cfg(not(all(unix, feature = "profiling")))]
+#[cfg(not(all(unix, any(feature = "profiling", feature = "sampling"))))]
 pub use profiler_dummy::*;
@@ -0,0 +1,395 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Hardware performance counters of a region, read by Linux `perf_event_open`.
+//!
+//! ```ignore
+//! let region = profiler::perf::region("forward_scan");
+//! forward_scan();
+//! println!("{}", region.finish());
+//! ```
+//!
+//! Needs the `perf` feature on Linux. Counters which can't be opened, e.g. the kernel
+//! doesn't allow it (see `/proc/sys/kernel/perf_event_paranoid`) or there is no PMU in a
+//! virtual machine, are reported as `n/a` instead of failing the region.
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::Once;
+
+use crate::Result;
+
+/// A hardware event to count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    Cycles,
+    Instructions,
+    /// Cache misses, usually of the last level cache.
+    LlcMisses,
+    BranchMisses,
+}
+
+pub const COUNTERS: [Counter; 4] = [
+    Counter::Cycles,
+    Counter::Instructions,
+    Counter::LlcMisses,
+    Counter::BranchMisses,
+];
+
+/// The counted events, `None` if the counter is not available.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CounterValues {
+    pub cycles: Option<u64>,
+    pub instructions: Option<u64>,
+    pub llc_misses: Option<u64>,
+    pub branch_misses: Option<u64>,
+}
+
+impl CounterValues {
+    pub fn get(&self, counter: Counter) -> Option<u64> {
+        match counter {
+            Counter::Cycles => self.cycles,
+            Counter::Instructions => self.instructions,
+            Counter::LlcMisses => self.llc_misses,
+            Counter::BranchMisses => self.branch_misses,
+        }
+    }
+
+    fn get_mut(&mut self, counter: Counter) -> &mut Option<u64> {
+        match counter {
+            Counter::Cycles => &mut self.cycles,
+            Counter::Instructions => &mut self.instructions,
+            Counter::LlcMisses => &mut self.llc_misses,
+            Counter::BranchMisses => &mut self.branch_misses,
+        }
+    }
+
+    /// Instructions per cycle.
+    pub fn ipc(&self) -> Option<f64> {
+        match (self.instructions, self.cycles) {
+            (Some(i), Some(c)) if c > 0 => Some(i as f64 / c as f64),
+            _ => None,
+        }
+    }
+
+    /// Divides every counter by `n`, e.g. to get the values of one run of many.
+    pub fn per(&self, n: u64) -> CounterValues {
+        let mut values = *self;
+        for &counter in &COUNTERS {
+            let v = values.get_mut(counter);
+            *v = v.map(|v| v / n.max(1));
+        }
+        values
+    }
+
+    /// Adds `other` to the counters, a counter missing in either one is missing.
+    pub fn add(&mut self, other: &CounterValues) {
+        for &counter in &COUNTERS {
+            let v = self.get_mut(counter);
+            *v = match (*v, other.get(counter)) {
+                (Some(a), Some(b)) => Some(a + b),
+                _ => None,
+            };
+        }
+    }
+}
+
+impl Display for CounterValues {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let fmt_value = |v: Option<u64>| v.map_or_else(|| "n/a".to_owned(), |v| v.to_string());
+        write!(
+            f,
+            "cycles {}, instructions {}, ipc {}, llc misses {}, branch misses {}",
+            fmt_value(self.cycles),
+            fmt_value(self.instructions),
+            self.ipc()
+                .map_or_else(|| "n/a".to_owned(), |ipc| format!("{:.2}", ipc)),
+            fmt_value(self.llc_misses),
+            fmt_value(self.branch_misses),
+        )
+    }
+}
+
+/// The counters of the calling thread and the threads it spawns after opening.
+pub struct PerfCounters {
+    counters: Vec<(Counter, sys::Event)>,
+}
+
+impl PerfCounters {
+    /// Opens the available counters, fails only if none of them can be opened.
+    pub fn open() -> Result<PerfCounters> {
+        let mut counters = vec![];
+        let mut first_err = None;
+        for &counter in &COUNTERS {
+            match sys::Event::open(counter) {
+                Ok(event) => counters.push((counter, event)),
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        match first_err {
+            Some(e) if counters.is_empty() => Err(e),
+            _ => Ok(PerfCounters { counters }),
+        }
+    }
+
+    /// Resets and starts counting.
+    pub fn enable(&self) -> Result<()> {
+        for (_, event) in &self.counters {
+            event.reset()?;
+            event.enable()?;
+        }
+        Ok(())
+    }
+
+    pub fn disable(&self) -> Result<()> {
+        for (_, event) in &self.counters {
+            event.disable()?;
+        }
+        Ok(())
+    }
+
+    /// Reads the counters, scaled up if the kernel multiplexed them.
+    pub fn read(&self) -> Result<CounterValues> {
+        let mut values = CounterValues::default();
+        for (counter, event) in &self.counters {
+            *values.get_mut(*counter) = event.read()?;
+        }
+        Ok(values)
+    }
+}
+
+/// The counters of a named region.
+#[derive(Debug, Clone)]
+pub struct PerfReport {
+    pub name: String,
+    pub values: CounterValues,
+}
+
+impl Display for PerfReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.values)
+    }
+}
+
+/// Counts the events from `region` to `PerfRegion::finish`.
+pub struct PerfRegion {
+    name: String,
+    counters: Option<PerfCounters>,
+}
+
+static WARN_UNAVAILABLE: Once = Once::new();
+
+/// Starts counting the region `name` on the calling thread. If the counters are not
+/// available, a warning is printed once and the region reports no values.
+pub fn region(name: impl Into<String>) -> PerfRegion {
+    let counters = PerfCounters::open().and_then(|c| c.enable().map(|_| c));
+    let counters = match counters {
+        Ok(counters) => Some(counters),
+        Err(e) => {
+            WARN_UNAVAILABLE.call_once(|| println!("perf counters not available: {}", e));
+            None
+        }
+    };
+    PerfRegion {
+        name: name.into(),
+        counters,
+    }
+}
+
+impl PerfRegion {
+    pub fn finish(self) -> PerfReport {
+        let values = self
+            .counters
+            .and_then(|c| c.disable().and_then(|_| c.read()).ok())
+            .unwrap_or_default();
+        PerfReport {
+            name: self.name,
+            values,
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "perf"))]
+mod sys {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    use super::Counter;
+    use crate::Result;
+
+    const PERF_TYPE_HARDWARE: u32 = 0;
+    const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+    const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+    const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+    const PERF_COUNT_HW_BRANCH_MISSES: u64 = 5;
+
+    const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1;
+    const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+
+    const FLAG_DISABLED: u64 = 1;
+    const FLAG_INHERIT: u64 = 1 << 1;
+    const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+    const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+
+    const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+    const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+    const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+    const PERF_EVENT_IOC_RESET: libc::c_ulong = 0x2403;
+
+    /// `struct perf_event_attr` of `PERF_ATTR_SIZE_VER5`.
+    #[repr(C)]
+    #[derive(Default)]
+    struct PerfEventAttr {
+        type_: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        config1: u64,
+        config2: u64,
+        branch_sample_type: u64,
+        sample_regs_user: u64,
+        sample_stack_user: u32,
+        clockid: i32,
+        sample_regs_intr: u64,
+        aux_watermark: u32,
+        sample_max_stack: u16,
+        reserved: u16,
+    }
+
+    pub struct Event {
+        file: File,
+    }
+
+    impl Event {
+        pub fn open(counter: Counter) -> Result<Event> {
+            let config = match counter {
+                Counter::Cycles => PERF_COUNT_HW_CPU_CYCLES,
+                Counter::Instructions => PERF_COUNT_HW_INSTRUCTIONS,
+                Counter::LlcMisses => PERF_COUNT_HW_CACHE_MISSES,
+                Counter::BranchMisses => PERF_COUNT_HW_BRANCH_MISSES,
+            };
+            let attr = PerfEventAttr {
+                type_: PERF_TYPE_HARDWARE,
+                size: std::mem::size_of::<PerfEventAttr>() as u32,
+                config,
+                read_format: PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING,
+                // User space only, which is allowed by the default `perf_event_paranoid`.
+                flags: FLAG_DISABLED | FLAG_INHERIT | FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV,
+                ..Default::default()
+            };
+            // The calling thread on any cpu.
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_perf_event_open,
+                    &attr as *const PerfEventAttr,
+                    0,
+                    -1,
+                    -1,
+                    PERF_FLAG_FD_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            Ok(Event {
+                file: unsafe { File::from_raw_fd(fd as i32) },
+            })
+        }
+
+        fn ioctl(&self, request: libc::c_ulong) -> Result<()> {
+            if unsafe { libc::ioctl(self.file.as_raw_fd(), request as _, 0) } < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            Ok(())
+        }
+
+        pub fn enable(&self) -> Result<()> {
+            self.ioctl(PERF_EVENT_IOC_ENABLE)
+        }
+
+        pub fn disable(&self) -> Result<()> {
+            self.ioctl(PERF_EVENT_IOC_DISABLE)
+        }
+
+        pub fn reset(&self) -> Result<()> {
+            self.ioctl(PERF_EVENT_IOC_RESET)
+        }
+
+        /// Reads the count, `None` if the event was never scheduled on the PMU.
+        pub fn read(&self) -> Result<Option<u64>> {
+            let mut buf = [0u8; 24];
+            (&self.file).read_exact(&mut buf)?;
+            let word = |i: usize| {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&buf[i * 8..i * 8 + 8]);
+                u64::from_ne_bytes(bytes)
+            };
+            let (value, enabled, running) = (word(0), word(1), word(2));
+            if running == 0 {
+                return Ok(None);
+            }
+            Ok(Some(
+                (u128::from(value) * u128::from(enabled) / u128::from(running)) as u64,
+            ))
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "perf")))]
+mod sys {
+    use super::Counter;
+    use crate::{Error, Result};
+
+    pub struct Event;
+
+    impl Event {
+        pub fn open(_counter: Counter) -> Result<Event> {
+            Err(Error::PerfNotEnabled)
+        }
+
+        pub fn enable(&self) -> Result<()> {
+            Ok(())
+        }
+
+        pub fn disable(&self) -> Result<()> {
+            Ok(())
+        }
+
+        pub fn reset(&self) -> Result<()> {
+            Ok(())
+        }
+
+        pub fn read(&self) -> Result<Option<u64>> {
+            Ok(None)
+        }
+    }
+}
+
+#[test]
+fn test_counter_values() {
+    let mut values = CounterValues {
+        cycles: Some(200),
+        instructions: Some(300),
+        llc_misses: Some(4),
+        branch_misses: None,
+    };
+    assert_eq!(values.ipc(), Some(1.5));
+    values.add(&values.clone());
+    assert_eq!(values.per(2).cycles, Some(200));
+    assert_eq!(values.branch_misses, None);
+    assert_eq!(
+        values.to_string(),
+        "cycles 400, instructions 600, ipc 1.50, llc misses 8, branch misses n/a"
+    );
+
+    // a region never fails, whether the counters are available or not.
+    let report = region("empty").finish();
+    assert_eq!(report.name, "empty");
+}
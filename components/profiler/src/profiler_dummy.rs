@@ -1,17 +1,42 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
-/// Start profiling. Always returns false if `profiling` feature is not enabled.
+use crate::{Backend, Error, Result};
+
+/// Choose the profiler backend. Does nothing if neither `profiling` nor `sampling` feature is
+/// enabled.
+#[inline]
+pub fn set_backend(_backend: Backend) {}
+
+/// Start profiling. Always fails if neither `profiling` nor `sampling` feature is enabled.
+#[inline]
+pub fn start(_name: impl AsRef<str>) -> Result<()> {
+    Err(Error::NotEnabled(Backend::Auto))
+}
+
+/// Stop profiling. Always fails if neither `profiling` nor `sampling` feature is enabled.
 #[inline]
-pub fn start(_name: impl AsRef<str>) -> bool {
-    // Do nothing
-    println!("profiling feature not enabled");
-    false
+pub fn stop() -> Result<()> {
+    Err(Error::NotStarted)
+}
+
+/// Does nothing if `profiling` feature is not enabled.
+#[inline]
+pub fn register_thread() {}
+
+/// A region guard, which can't be created if neither `profiling` nor `sampling` feature is
+/// enabled.
+pub struct ProfileGuard {
+    _private: (),
+}
+
+impl ProfileGuard {
+    pub fn finish(self) -> Result<()> {
+        Ok(())
+    }
 }
 
-/// Stop profiling. Always returns false if `profiling` feature is not enabled.
+/// Enter a region. Always fails if neither `profiling` nor `sampling` feature is enabled.
 #[inline]
-pub fn stop() -> bool {
-    println!("profiling feature not enabled");
-    // Do nothing
-    false
+pub fn profile(_name: impl AsRef<str>) -> Result<ProfileGuard> {
+    Err(Error::NotEnabled(Backend::Auto))
 }
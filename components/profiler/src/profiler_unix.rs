@@ -1,76 +1,375 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "profiling")]
 use callgrind::CallgrindClientRequest;
 
-#[derive(Debug, PartialEq)]
+use crate::{Backend, Error, Result};
+
 enum Profiler {
-    None,
+    #[cfg(feature = "profiling")]
     GPerfTools,
+    #[cfg(feature = "profiling")]
     CallGrind,
+    #[cfg(feature = "sampling")]
+    Sampling(sampling::Sampling),
+}
+
+/// The time spent in a region, summed over all its guards.
+#[derive(Default)]
+struct RegionStat {
+    count: u64,
+    elapsed: Duration,
+}
+
+struct Session {
+    id: u64,
+    name: String,
+    profiler: Profiler,
+    /// The number of alive `ProfileGuard`s, on any thread.
+    guards: usize,
+    /// The session is started by a `ProfileGuard` and stops with the last one.
+    scoped: bool,
+    /// Keyed by the `;` joined names of the nested regions.
+    regions: BTreeMap<String, RegionStat>,
 }
 
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(0);
+
 lazy_static::lazy_static! {
-    #[derive(Debug)]
-    static ref ACTIVE_PROFILER: Mutex<Profiler> = Mutex::new(Profiler::None);
+    static ref ACTIVE_SESSION: Mutex<Option<Session>> = Mutex::new(None);
+    static ref BACKEND: Mutex<Backend> = Mutex::new(Backend::from_env());
+}
+
+thread_local! {
+    /// The names of the regions entered by this thread, outermost first.
+    static REGION_STACK: RefCell<Vec<String>> = RefCell::new(vec![]);
+}
+
+/// Choose the backend used by the following `start` calls. By default it is read from the
+/// `PROFILER_BACKEND` environment variable, see `Backend::from_env`.
+pub fn set_backend(backend: Backend) {
+    *BACKEND.lock().unwrap() = backend;
+}
+
+/// Resolves `Backend::Auto` to the backend to use.
+fn resolve_backend(backend: Backend) -> Backend {
+    if backend != Backend::Auto {
+        return backend;
+    }
+    #[cfg(feature = "profiling")]
+    {
+        if valgrind_request::running_on_valgrind() != 0 {
+            Backend::CallGrind
+        } else {
+            Backend::GPerfTools
+        }
+    }
+    #[cfg(not(feature = "profiling"))]
+    {
+        Backend::Sampling
+    }
+}
+
+fn start_profiler(name: &str) -> Result<Profiler> {
+    match resolve_backend(*BACKEND.lock().unwrap()) {
+        #[cfg(feature = "profiling")]
+        Backend::CallGrind => {
+            println!("Callgrind start record");
+            CallgrindClientRequest::start();
+            Ok(Profiler::CallGrind)
+        }
+        #[cfg(feature = "profiling")]
+        Backend::GPerfTools => {
+            cpuprofiler::PROFILER
+                .lock()
+                .unwrap()
+                .start(name)
+                .map_err(|e| Error::Backend(e.to_string()))?;
+            println!("GPerfTools start record");
+            Ok(Profiler::GPerfTools)
+        }
+        #[cfg(feature = "sampling")]
+        Backend::Sampling => {
+            let sampling = sampling::Sampling::start(name)?;
+            println!("Sampling profiler start record");
+            Ok(Profiler::Sampling(sampling))
+        }
+        backend => Err(Error::NotEnabled(backend)),
+    }
+}
+
+fn stop_session(session: Session) -> Result<()> {
+    match session.profiler {
+        #[cfg(feature = "profiling")]
+        Profiler::CallGrind => {
+            CallgrindClientRequest::stop(None);
+            println!("Callgrind stop record");
+        }
+        #[cfg(feature = "profiling")]
+        Profiler::GPerfTools => {
+            cpuprofiler::PROFILER
+                .lock()
+                .unwrap()
+                .stop()
+                .map_err(|e| Error::Backend(e.to_string()))?;
+            println!("GPerftool stop record");
+        }
+        #[cfg(feature = "sampling")]
+        Profiler::Sampling(sampling) => {
+            sampling.stop()?;
+            println!("Sampling profiler stop record");
+        }
+    }
+    if !session.regions.is_empty() {
+        write_regions(&session.name, &session.regions)?;
+    }
+    Ok(())
+}
+
+/// Writes `{name}.regions`, a line of `region count elapsed_us` for each region.
+fn write_regions(name: &str, regions: &BTreeMap<String, RegionStat>) -> Result<()> {
+    let mut w = BufWriter::new(File::create(format!("{}.regions", name))?);
+    for (region, stat) in regions {
+        writeln!(w, "{} {} {}", region, stat.count, stat.elapsed.as_micros())?;
+    }
+    w.flush()?;
+    Ok(())
 }
 
-/// Start profiling. Returns false if failed, i.e. there is already a profiling in progress.
+/// Start profiling. Fails if there is already a profiling in progress or the chosen backend
+/// is not enabled.
 ///
-/// When `profiling` feature is not enabled, this function will do nothing and there is totally
-/// zero cost.
+/// When neither `profiling` nor `sampling` feature is enabled, this function will do nothing and
+/// there is totally zero cost.
 ///
 /// When running in Callgrind, Callgrind instrumentation will be started
 /// (`CALLGRIND_START_INSTRUMENTATION`). Otherwise, the CPU Profiler will be started and profile
-/// will be generated to the file specified by `name`.
-// TODO: Better multi-thread support.
+/// will be generated to the file specified by `name`. The sampling profiler generates
+/// `{name}.pb`, `{name}.collapsed` and `{name}.svg` instead.
 #[inline]
-pub fn start(name: impl AsRef<str>) -> bool {
-    let mut profiler = ACTIVE_PROFILER.lock().unwrap();
-
-    // Profiling in progress.
-    if *profiler != Profiler::None {
-        return false;
+pub fn start(name: impl AsRef<str>) -> Result<()> {
+    let mut active = ACTIVE_SESSION.lock().unwrap();
+    if let Some(ref session) = *active {
+        return Err(Error::InProgress(session.name.clone()));
     }
+    let name = name.as_ref();
+    *active = Some(Session {
+        id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+        name: name.to_owned(),
+        profiler: start_profiler(name)?,
+        guards: 0,
+        scoped: false,
+        regions: BTreeMap::new(),
+    });
+    Ok(())
+}
+
+/// Stop profiling. Fails if there is no profiling in progress or the profile can't be written.
+/// The regions of alive `ProfileGuard`s are not recorded.
+///
+/// When neither `profiling` nor `sampling` feature is enabled, this function will do nothing and
+/// there is totally zero cost.
+#[inline]
+pub fn stop() -> Result<()> {
+    let session = ACTIVE_SESSION.lock().unwrap().take();
+    stop_session(session.ok_or(Error::NotStarted)?)
+}
 
-    if valgrind_request::running_on_valgrind() != 0 {
-        *profiler = Profiler::CallGrind;
-        println!("Callgrind start record");
-        CallgrindClientRequest::start();
-    } else {
-        *profiler = Profiler::GPerfTools;
-        println!("GPerfTools start record");
-        cpuprofiler::PROFILER
-            .lock()
-            .unwrap()
-            .start(name.as_ref())
-            .unwrap();
+/// Registers the current thread to CPU Profiler, which only samples registered threads when
+/// it uses per thread timers (`CPUPROFILE_PER_THREAD_TIMERS`). Callgrind and the sampling
+/// profiler see all threads.
+pub fn register_thread() {
+    #[cfg(feature = "profiling")]
+    {
+        extern "C" {
+            fn ProfilerRegisterThread();
+        }
+        unsafe { ProfilerRegisterThread() }
     }
+}
 
-    true
+/// Profiles a named region until the guard drops, see `profile`.
+pub struct ProfileGuard {
+    session_id: u64,
+    /// Taken when the guard leaves the region.
+    region: Option<String>,
+    start: Instant,
+    // The region stack is thread local, so the guard must drop on the thread creating it.
+    _not_send: PhantomData<*const ()>,
 }
 
-/// Stop profiling. Returns false if failed, i.e. there is no profiling in progress.
+/// Enters the region `name` and returns its guard.
 ///
-/// When `profiling` feature is not enabled, this function will do nothing and there is totally
-/// zero cost.
-#[inline]
-pub fn stop() -> bool {
-    let mut profiler = ACTIVE_PROFILER.lock().unwrap();
-    match *profiler {
-        Profiler::None => false,
-        Profiler::CallGrind => {
-            CallgrindClientRequest::stop(None);
-            *profiler = Profiler::None;
-            println!("Callgrind stop record");
-            true
+/// If no profiling is in progress, one is started with `name` as the profile name and stopped
+/// when the last guard, of any thread, drops. Otherwise the region joins the current profile.
+/// The time spent in each region, keyed by the names of its enclosing regions, is written to
+/// `{profile name}.regions`.
+pub fn profile(name: impl AsRef<str>) -> Result<ProfileGuard> {
+    let name = name.as_ref();
+    let mut active = ACTIVE_SESSION.lock().unwrap();
+    if active.is_none() {
+        *active = Some(Session {
+            id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            name: name.to_owned(),
+            profiler: start_profiler(name)?,
+            guards: 0,
+            scoped: true,
+            regions: BTreeMap::new(),
+        });
+    }
+    let session = active.as_mut().unwrap();
+    session.guards += 1;
+    let region = REGION_STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        stack.push(name.to_owned());
+        stack.join(";")
+    });
+    Ok(ProfileGuard {
+        session_id: session.id,
+        region: Some(region),
+        start: Instant::now(),
+        _not_send: PhantomData,
+    })
+}
+
+impl ProfileGuard {
+    /// Leaves the region, returning the error of writing the profile if it is the last guard.
+    pub fn finish(mut self) -> Result<()> {
+        self.leave()
+    }
+
+    fn leave(&mut self) -> Result<()> {
+        let region = match self.region.take() {
+            Some(region) => region,
+            None => return Ok(()),
+        };
+        let elapsed = self.start.elapsed();
+        REGION_STACK.with(|stack| stack.borrow_mut().pop());
+
+        let mut active = ACTIVE_SESSION.lock().unwrap();
+        // The session may be stopped by `stop`.
+        let session = match *active {
+            Some(ref mut session) if session.id == self.session_id => session,
+            _ => return Ok(()),
+        };
+        let stat = session.regions.entry(region).or_default();
+        stat.count += 1;
+        stat.elapsed += elapsed;
+        session.guards -= 1;
+        if session.guards == 0 && session.scoped {
+            return stop_session(active.take().unwrap());
         }
-        Profiler::GPerfTools => {
-            cpuprofiler::PROFILER.lock().unwrap().stop().unwrap();
-            *profiler = Profiler::None;
-            println!("GPerftool stop record");
-            true
+        Ok(())
+    }
+}
+
+impl Drop for ProfileGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.leave() {
+            eprintln!("failed to stop profiling: {}", e);
         }
     }
 }
+
+#[cfg(feature = "sampling")]
+mod sampling {
+    use std::fs::File;
+    use std::io::{BufWriter, Write};
+
+    use pprof::protos::Message;
+    use pprof::{ProfilerGuard, ProfilerGuardBuilder, Report};
+
+    use crate::{Error, Result};
+
+    /// Samples per second, a prime avoids sampling in lockstep with periodic work.
+    const FREQUENCY: i32 = 99;
+
+    /// Unwinding through these libraries may deadlock or crash in the signal handler.
+    const BLOCKLIST: [&str; 4] = ["libc", "libgcc", "pthread", "vdso"];
+
+    pub struct Sampling {
+        name: String,
+        guard: ProfilerGuard<'static>,
+    }
+
+    impl Sampling {
+        pub fn start(name: &str) -> Result<Sampling> {
+            let guard = ProfilerGuardBuilder::default()
+                .frequency(FREQUENCY)
+                .blocklist(&BLOCKLIST)
+                .build()
+                .map_err(backend_error)?;
+            Ok(Sampling {
+                name: name.to_owned(),
+                guard,
+            })
+        }
+
+        /// Stops sampling and writes the pprof protobuf, the collapsed stacks and the
+        /// flamegraph of them.
+        pub fn stop(self) -> Result<()> {
+            let report = self.guard.report().build().map_err(backend_error)?;
+            drop(self.guard);
+
+            let mut pb = vec![];
+            report
+                .pprof()
+                .map_err(backend_error)?
+                .encode(&mut pb)
+                .map_err(backend_error)?;
+            File::create(format!("{}.pb", self.name))?.write_all(&pb)?;
+
+            let lines = collapsed_stacks(&report);
+            let mut collapsed = BufWriter::new(File::create(format!("{}.collapsed", self.name))?);
+            for line in &lines {
+                writeln!(collapsed, "{}", line)?;
+            }
+            collapsed.flush()?;
+
+            // inferno refuses to draw nothing, e.g. the profiled code is too short to be sampled.
+            if lines.is_empty() {
+                println!("No samples collected, skip the flamegraph");
+                return Ok(());
+            }
+            let svg = BufWriter::new(File::create(format!("{}.svg", self.name))?);
+            let mut options = pprof::flamegraph::Options::default();
+            pprof::flamegraph::from_lines(&mut options, lines.iter().map(String::as_str), svg)
+                .map_err(backend_error)?;
+            Ok(())
+        }
+    }
+
+    fn backend_error(e: impl std::fmt::Display) -> Error {
+        Error::Backend(e.to_string())
+    }
+
+    /// Formats every sampled stack as `thread;root;...;leaf count`, the input format of
+    /// `flamegraph.pl` and inferno.
+    fn collapsed_stacks(report: &Report) -> Vec<String> {
+        let mut lines: Vec<String> = report
+            .data
+            .iter()
+            .map(|(frames, count)| {
+                let mut line = frames.thread_name_or_id();
+                for frame in frames.frames.iter().rev() {
+                    for symbol in frame.iter().rev() {
+                        line.push(';');
+                        line.push_str(&symbol.to_string());
+                    }
+                }
+                format!("{} {}", line, count)
+            })
+            .collect();
+        lines.sort();
+        lines
+    }
+}
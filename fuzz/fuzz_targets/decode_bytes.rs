@@ -0,0 +1,40 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use nacs::tikv_code::bytes;
+
+fuzz_target!(|data: &[u8]| {
+    for &desc in &[false, true] {
+        let mut rest = data;
+        let decoded = bytes::decode_bytes(&mut rest, desc);
+
+        let mut word_rest = data;
+        let mut buf = vec![];
+        let word_decoded = if desc {
+            bytes::decode_bytes_desc_into(&mut word_rest, &mut buf)
+        } else {
+            bytes::decode_bytes_into(&mut word_rest, &mut buf)
+        };
+        assert_eq!(decoded.is_ok(), word_decoded.is_ok());
+
+        if let Ok(key) = decoded {
+            assert_eq!(key, buf);
+            assert_eq!(rest, word_rest);
+            // a successfully decoded key is canonical, so it encodes back to the consumed bytes.
+            let consumed = &data[..data.len() - rest.len()];
+            let encoded = if desc {
+                bytes::encode_bytes_desc(&key)
+            } else {
+                bytes::encode_bytes(&key)
+            };
+            assert_eq!(encoded.as_slice(), consumed);
+            assert_eq!(bytes::encoded_bytes_len(data, desc), consumed.len());
+
+            let mut in_place = data.to_vec();
+            bytes::decode_bytes_in_place(&mut in_place, desc).unwrap();
+            assert_eq!(in_place, key);
+        } else {
+            assert!(buf.is_empty());
+        }
+    }
+});
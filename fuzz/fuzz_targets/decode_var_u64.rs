@@ -0,0 +1,21 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use nacs::tikv_code::number::{self, NumberEncoder};
+
+fuzz_target!(|data: &[u8]| {
+    let mut rest = data;
+    if let Ok(v) = number::decode_var_u64(&mut rest) {
+        let consumed = data.len() - rest.len();
+        assert!(consumed >= 1 && consumed <= number::MAX_VAR_U64_LEN);
+
+        // the input may not be the shortest encoding, but it must decode to the same value.
+        let mut encoded = vec![];
+        encoded.encode_var_u64(v).unwrap();
+        assert!(encoded.len() <= consumed);
+        assert_eq!(number::decode_var_u64(&mut encoded.as_slice()).unwrap(), v);
+    }
+
+    let mut rest = data;
+    let _ = number::decode_var_i64(&mut rest);
+});
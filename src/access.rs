@@ -0,0 +1,434 @@
+//! Scans reading the column families in other orders than `forward_scan`, which steps both
+//! iterators row by row, and `forward_batch_scan`, which steps `CF_WRITE` then `CF_DEFAULT`
+//! a batch at a time. Comparing them tells where the batching gains come from.
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+
+use rocksdb::rocksdb::DBIterator;
+use rocksdb::{SeekKey, DB};
+use test::black_box;
+
+use crate::errors::{Error, Result};
+use crate::gen_db::get_cf_handle;
+use crate::lock::check_lock_unsupported;
+use crate::schema::{WriteRef, WriteType};
+use crate::tikv_code::bytes;
+use crate::tikv_code::constexpr::CF_DEFAULT;
+use crate::tikv_code::key::Key;
+use crate::tikv_code::number::NumberEncoder;
+use crate::{
+    check_iter_status, default_row_missing, less_default_rows, more_default_rows, KeyMode,
+    ScanStats, Scanner,
+};
+
+/// Chunks a producer of `pipelined_scan` can send before it blocks.
+pub const PIPELINE_DEPTH: usize = 4;
+
+pub(crate) fn key_only_unsupported(scan: &str) -> Error {
+    Error::Unsupported(format!(
+        "{} reads CF_DEFAULT, the scanner is key only",
+        scan
+    ))
+}
+
+/// Appends the key of the write iterator, decoded if the keys are written in `KeyMode::Raw`.
+fn append_write_key(
+    iter_write: &DBIterator<Arc<DB>>,
+    key_mode: KeyMode,
+    buf: &mut Vec<u8>,
+) -> Result<()> {
+    match key_mode {
+        KeyMode::Raw => bytes::decode_bytes_into(&mut iter_write.key(), buf)?,
+        KeyMode::Encoded => buf.extend_from_slice(iter_write.key()),
+    }
+    Ok(())
+}
+
+/// Scans at most `limit` rows of `CF_WRITE` and never touches `CF_DEFAULT`.
+pub fn write_only_scan(scanner: &mut Scanner, limit: u64) -> Result<ScanStats> {
+    check_lock_unsupported(scanner, "write_only_scan")?;
+    let mut stats = ScanStats::default();
+    let mut raw_key = vec![];
+    let iter_write = &mut scanner.iter_write;
+    while stats.rows < limit && iter_write.valid() {
+        if scanner.cfg.key_mode == KeyMode::Raw {
+            raw_key.clear();
+            append_write_key(iter_write, KeyMode::Raw, &mut raw_key)?;
+        }
+        black_box((iter_write.key(), &raw_key, iter_write.value()));
+        stats.add_write(iter_write.key(), iter_write.value());
+        stats.rows += 1;
+        iter_write.next();
+    }
+    if stats.rows < limit {
+        check_iter_status(iter_write)?;
+    }
+    Ok(stats)
+}
+
+/// Scans at most `limit` rows of `CF_DEFAULT` and never touches `CF_WRITE`.
+pub fn default_only_scan(scanner: &mut Scanner, limit: u64) -> Result<ScanStats> {
+    check_lock_unsupported(scanner, "default_only_scan")?;
+    let iter_default = scanner
+        .iter_default
+        .as_mut()
+        .ok_or_else(|| key_only_unsupported("default_only_scan"))?;
+    let mut stats = ScanStats::default();
+    while stats.rows < limit && iter_default.valid() {
+        black_box((iter_default.key(), iter_default.value()));
+        stats.add_default(iter_default.key(), iter_default.value());
+        stats.rows += 1;
+        iter_default.next();
+    }
+    if stats.rows < limit {
+        check_iter_status(iter_default)?;
+    }
+    Ok(stats)
+}
+
+/// Like `forward_scan`, but the default iterator runs `distance` rows ahead of the write one.
+/// The values read ahead are copied into recycled buffers.
+pub fn prefetch_scan(scanner: &mut Scanner, distance: u64, limit: u64) -> Result<ScanStats> {
+    check_lock_unsupported(scanner, "prefetch_scan")?;
+    let key_mode = scanner.cfg.key_mode;
+    let iter_write = &mut scanner.iter_write;
+    let iter_default = scanner
+        .iter_default
+        .as_mut()
+        .ok_or_else(|| key_only_unsupported("prefetch_scan"))?;
+    let distance = distance.max(1);
+
+    let mut stats = ScanStats::default();
+    let mut ahead: VecDeque<Vec<u8>> = VecDeque::with_capacity(distance as usize);
+    let mut spare: Vec<Vec<u8>> = Vec::with_capacity(distance as usize);
+    let mut fetched = 0;
+    let mut raw_key = vec![];
+    while stats.rows < limit && iter_write.valid() {
+        while fetched < limit && fetched < stats.rows + distance && iter_default.valid() {
+            let mut value = spare.pop().unwrap_or_default();
+            value.clear();
+            value.extend_from_slice(iter_default.value());
+            black_box(iter_default.key());
+            stats.add_default(iter_default.key(), iter_default.value());
+            ahead.push_back(value);
+            iter_default.next();
+            fetched += 1;
+        }
+        let default_value = match ahead.pop_front() {
+            Some(value) => value,
+            None => return Err(default_row_missing(iter_default)),
+        };
+        if key_mode == KeyMode::Raw {
+            raw_key.clear();
+            append_write_key(iter_write, key_mode, &mut raw_key)?;
+        }
+        black_box((
+            iter_write.key(),
+            &raw_key,
+            iter_write.value(),
+            &default_value,
+        ));
+        stats.add_write(iter_write.key(), iter_write.value());
+        stats.rows += 1;
+        spare.push(default_value);
+        iter_write.next();
+    }
+
+    if stats.rows < limit {
+        check_iter_status(iter_write)?;
+        if !ahead.is_empty() || iter_default.valid() {
+            return Err(more_default_rows());
+        }
+        check_iter_status(iter_default)?;
+    }
+    Ok(stats)
+}
+
+/// How `lookup_scan` finds the `CF_DEFAULT` row of a write record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LookupMode {
+    /// `get_cf` on the snapshot of the scanner.
+    Get,
+    /// Seeks the default iterator of the scanner to the key.
+    Seek,
+}
+
+pub(crate) fn default_key_missing(key: &[u8]) -> Error {
+    Error::KeyLayout(format!("CF_DEFAULT has no row {}", hex::encode(key)))
+}
+
+/// Scans at most `limit` rows of `CF_WRITE` and looks up the `CF_DEFAULT` row of each put by
+/// its user key and the start ts in its value, like the point lookups of TiKV. The puts with a
+/// short value and the other records have no `CF_DEFAULT` row and are never looked up. Unlike
+/// the other scans, it doesn't rely on both column families having their rows in the same
+/// order.
+pub fn lookup_scan(scanner: &mut Scanner, mode: LookupMode, limit: u64) -> Result<ScanStats> {
+    check_lock_unsupported(scanner, "lookup_scan")?;
+    let key_mode = scanner.cfg.key_mode;
+    let iter_write = &mut scanner.iter_write;
+    let iter_default = scanner
+        .iter_default
+        .as_mut()
+        .ok_or_else(|| key_only_unsupported("lookup_scan"))?;
+    let default_cf = get_cf_handle(scanner.snap.get_db(), CF_DEFAULT)?;
+
+    let mut stats = ScanStats::default();
+    let mut default_key = vec![];
+    let mut raw_key = vec![];
+    while stats.rows < limit && iter_write.valid() {
+        let write = WriteRef::parse(iter_write.value())?;
+        if write.write_type == WriteType::Put && write.short_value.is_none() {
+            default_key.clear();
+            default_key.extend_from_slice(Key::truncate_ts_for(iter_write.key())?);
+            default_key.encode_u64_desc(write.start_ts)?;
+            match mode {
+                LookupMode::Get => {
+                    let value = scanner
+                        .snap
+                        .get_cf(default_cf, &default_key)
+                        .map_err(Error::Engine)?
+                        .ok_or_else(|| default_key_missing(&default_key))?;
+                    black_box(&*value);
+                    stats.add_default(&default_key, &value);
+                }
+                LookupMode::Seek => {
+                    if !iter_default.seek(SeekKey::Key(&default_key))
+                        || iter_default.key() != default_key.as_slice()
+                    {
+                        check_iter_status(iter_default)?;
+                        return Err(default_key_missing(&default_key));
+                    }
+                    black_box(iter_default.value());
+                    stats.add_default(iter_default.key(), iter_default.value());
+                }
+            }
+        }
+        if key_mode == KeyMode::Raw {
+            raw_key.clear();
+            append_write_key(iter_write, key_mode, &mut raw_key)?;
+        }
+        black_box((iter_write.key(), &raw_key, iter_write.value()));
+        stats.add_write(iter_write.key(), iter_write.value());
+        stats.rows += 1;
+        iter_write.next();
+    }
+    if stats.rows < limit {
+        check_iter_status(iter_write)?;
+    }
+    Ok(stats)
+}
+
+/// Rows sent by a producer of `pipelined_scan`.
+#[derive(Default)]
+struct Chunk {
+    data: Vec<u8>,
+    rows: u64,
+}
+
+/// Scans at most `limit` rows with a thread for each column family, which sends the rows in
+/// chunks of `chunk_rows` to the scanning thread. Spawning the threads is part of the scan.
+pub fn pipelined_scan(scanner: &mut Scanner, chunk_rows: u64, limit: u64) -> Result<ScanStats> {
+    check_lock_unsupported(scanner, "pipelined_scan")?;
+    let key_mode = scanner.cfg.key_mode;
+    let iter_write = &mut scanner.iter_write;
+    let iter_default = scanner
+        .iter_default
+        .as_mut()
+        .ok_or_else(|| key_only_unsupported("pipelined_scan"))?;
+    let chunk_rows = chunk_rows.max(1);
+    let (write_tx, write_rx) = mpsc::sync_channel::<Chunk>(PIPELINE_DEPTH);
+    let (default_tx, default_rx) = mpsc::sync_channel::<Chunk>(PIPELINE_DEPTH);
+
+    thread::scope(|s| {
+        let writer = s.spawn(move || -> Result<ScanStats> {
+            let mut stats = ScanStats::default();
+            let mut chunk = Chunk::default();
+            while stats.rows < limit && iter_write.valid() {
+                append_write_key(iter_write, key_mode, &mut chunk.data)?;
+                chunk.data.extend_from_slice(iter_write.value());
+                chunk.rows += 1;
+                stats.add_write(iter_write.key(), iter_write.value());
+                stats.rows += 1;
+                iter_write.next();
+                // The receiver is gone only if the scanning thread panics.
+                if chunk.rows == chunk_rows && write_tx.send(mem::take(&mut chunk)).is_err() {
+                    return Ok(stats);
+                }
+            }
+            if chunk.rows > 0 {
+                let _ = write_tx.send(chunk);
+            }
+            if stats.rows < limit {
+                check_iter_status(iter_write)?;
+            }
+            Ok(stats)
+        });
+        let defaulter = s.spawn(move || -> Result<ScanStats> {
+            let mut stats = ScanStats::default();
+            let mut chunk = Chunk::default();
+            while stats.rows < limit && iter_default.valid() {
+                black_box(iter_default.key());
+                chunk.data.extend_from_slice(iter_default.value());
+                chunk.rows += 1;
+                stats.add_default(iter_default.key(), iter_default.value());
+                stats.rows += 1;
+                iter_default.next();
+                if chunk.rows == chunk_rows && default_tx.send(mem::take(&mut chunk)).is_err() {
+                    return Ok(stats);
+                }
+            }
+            if chunk.rows > 0 {
+                let _ = default_tx.send(chunk);
+            }
+            if stats.rows < limit {
+                check_iter_status(iter_default)?;
+            }
+            Ok(stats)
+        });
+
+        // Takes a chunk of each column family at a time.
+        for write in write_rx {
+            black_box(&write.data);
+            if let Ok(default) = default_rx.recv() {
+                black_box(&default.data);
+            }
+        }
+        for default in default_rx {
+            black_box(&default.data);
+        }
+
+        let write = writer.join().unwrap()?;
+        let default = defaulter.join().unwrap()?;
+        if default.rows < write.rows {
+            return Err(less_default_rows());
+        }
+        if default.rows > write.rows {
+            return Err(more_default_rows());
+        }
+        Ok(ScanStats {
+            default_keys: default.default_keys,
+            default_bytes: default.default_bytes,
+            ..write
+        })
+    })
+}
+
+#[test]
+fn test_access_patterns() {
+    use crate::drain::{DrainConfig, ValueType, WriteRatios};
+    use crate::schema::{generate_write_value, WriteFiled};
+    use crate::test_util::TestDb;
+    use crate::tikv_code::constexpr::CF_WRITE;
+    use crate::{forward_scan, ScannerConfig};
+
+    let db = TestDb::with_rows(500);
+    let cfg = ScannerConfig::default();
+    let scanner = |cfg: &ScannerConfig| db.scanner(cfg);
+
+    for &limit in &[u64::MAX, 300, 1] {
+        let forward = forward_scan(&mut scanner(&cfg), limit).unwrap();
+        let write = write_only_scan(&mut scanner(&cfg), limit).unwrap();
+        assert_eq!(write.rows, forward.rows);
+        assert_eq!(write.write_bytes, forward.write_bytes);
+        assert_eq!(write.default_keys, 0);
+        let default = default_only_scan(&mut scanner(&cfg), limit).unwrap();
+        assert_eq!(default.rows, forward.rows);
+        assert_eq!(default.default_bytes, forward.default_bytes);
+        assert_eq!(default.write_keys, 0);
+        for &n in &[1, 7, 1000] {
+            assert_eq!(
+                prefetch_scan(&mut scanner(&cfg), n, limit).unwrap(),
+                forward
+            );
+            assert_eq!(
+                pipelined_scan(&mut scanner(&cfg), n, limit).unwrap(),
+                forward
+            );
+        }
+        for &mode in &[LookupMode::Get, LookupMode::Seek] {
+            assert_eq!(
+                lookup_scan(&mut scanner(&cfg), mode, limit).unwrap(),
+                forward
+            );
+        }
+    }
+
+    // Every version of a key points to its own default row.
+    let drain_cfg = DrainConfig::new(400, ValueType::MiddleValue).versions_per_key(8);
+    let versions_db = TestDb::new(&drain_cfg);
+    let forward = forward_scan(&mut versions_db.scanner(&cfg), u64::MAX).unwrap();
+    assert_eq!(forward.rows, 400);
+    for &mode in &[LookupMode::Get, LookupMode::Seek] {
+        let stats = lookup_scan(&mut versions_db.scanner(&cfg), mode, u64::MAX).unwrap();
+        assert_eq!(stats, forward);
+    }
+
+    // Only the puts have a default row to look up.
+    let drain_cfg = DrainConfig::new(400, ValueType::MiddleValue)
+        .versions_per_key(8)
+        .write_ratios(WriteRatios::new(2, 1, 1, 1));
+    let deletes_db = TestDb::new(&drain_cfg);
+    let write = write_only_scan(&mut deletes_db.scanner(&cfg), u64::MAX).unwrap();
+    let default = default_only_scan(&mut deletes_db.scanner(&cfg), u64::MAX).unwrap();
+    assert_eq!(write.rows, 400);
+    assert!(default.rows < write.rows);
+    for &mode in &[LookupMode::Get, LookupMode::Seek] {
+        let stats = lookup_scan(&mut deletes_db.scanner(&cfg), mode, u64::MAX).unwrap();
+        assert_eq!(stats.rows, write.rows);
+        assert_eq!(stats.write_bytes, write.write_bytes);
+        assert_eq!(stats.default_keys, default.rows);
+        assert_eq!(stats.default_bytes, default.default_bytes);
+    }
+
+    // A put with a short value has no default row.
+    let short = WriteFiled::new(WriteType::Put, 4, Some(b"v".to_vec()));
+    deletes_db.put_stray_row(CF_WRITE, 5, &short.to_bytes());
+    for &mode in &[LookupMode::Get, LookupMode::Seek] {
+        let stats = lookup_scan(&mut deletes_db.scanner(&cfg), mode, u64::MAX).unwrap();
+        assert_eq!(stats.rows, write.rows + 1);
+        assert_eq!(stats.default_keys, default.rows);
+    }
+
+    let key_only = cfg.clone().key_only(true);
+    assert_eq!(
+        write_only_scan(&mut scanner(&key_only), 10).unwrap().rows,
+        10
+    );
+    match default_only_scan(&mut scanner(&key_only), 10) {
+        Err(Error::Unsupported(_)) => {}
+        res => panic!("expect unsupported, got {:?}", res),
+    }
+    match lookup_scan(&mut scanner(&key_only), LookupMode::Get, 10) {
+        Err(Error::Unsupported(_)) => {}
+        res => panic!("expect unsupported, got {:?}", res),
+    }
+
+    // A default row without the matching write row.
+    db.put_stray_row(CF_DEFAULT, 1, b"v");
+    match prefetch_scan(&mut scanner(&cfg), 16, u64::MAX) {
+        Err(Error::KeyLayout(_)) => {}
+        res => panic!("expect key layout error, got {:?}", res),
+    }
+    match pipelined_scan(&mut scanner(&cfg), 16, u64::MAX) {
+        Err(Error::KeyLayout(_)) => {}
+        res => panic!("expect key layout error, got {:?}", res),
+    }
+    // The lookups never see the default row nobody points to.
+    for &mode in &[LookupMode::Get, LookupMode::Seek] {
+        let stats = lookup_scan(&mut scanner(&cfg), mode, u64::MAX).unwrap();
+        assert_eq!(stats.rows, 500);
+    }
+
+    // A write row pointing to a missing default row.
+    db.put_stray_row(CF_WRITE, 3, &generate_write_value(WriteType::Put, 2));
+    for &mode in &[LookupMode::Get, LookupMode::Seek] {
+        match lookup_scan(&mut scanner(&cfg), mode, u64::MAX) {
+            Err(Error::KeyLayout(_)) => {}
+            res => panic!("expect key layout error, got {:?}", res),
+        }
+    }
+}
@@ -1,3 +1,30 @@
+//! Generates datasets and profiles the scans over them.
+//!
+//! ```bash
+//! profile gen --rows 20000,100000 --value 64,256
+//! profile scan --variant forward_batch_scan --batch-size 256 --rows 100000
+//! profile compare --rows 20000 --value 64,128,256 --batch-sizes 64,256,1024
+//! profile compare --variants forward_batch_scan --param batch_size=32,512
+//! profile compare --variants forward_scan,get_lookup_scan --versions 1,8
+//! profile compare --variants write_only_scan,mvcc_scan --versions 8 --writes 1:3:0:0
+//! profile compare --variants forward_scan,mvcc_scan --locks 0.01 --lock-check report
+//! profile inspect --rows 20000
+//! ```
+//!
+//! The profiler is chosen by the `profiling` or `sampling` feature and `--backend`, the scans
+//! run without a profile if neither feature is enabled. `compare --callgrind` instead runs
+//! each variant under `valgrind --tool=callgrind --instr-atstart=no` and prints the
+//! instructions executed by the scan, which needs the `profiling` feature.
+//!
+//! The variants are the strategies of `nacs::strategy::StrategyRegistry::builtin`, `compare`
+//! runs all of them unless `--variants` is given.
+//!
+//! `scan` and `compare` time `--samples` scans of every variant and save them as JSON and CSV
+//! run records into `--results`, which the `results` binary compares. Every scan starts with
+//! the caches of `--cache`, after warming up until the latency is stable. `--seek included`
+//! measures the seeks of the scanner to the lower bound with the scan. `--lock-check` checks
+//! `CF_LOCK` for the pending locks of `--locks`, the variants not checking it are skipped.
+
 #![feature(test)]
 
 // dev packages
@@ -8,98 +35,842 @@ extern crate valgrind_request;
 
 extern crate test;
 
+#[macro_use]
+extern crate clap;
 extern crate nacs;
 extern crate profiler;
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::Once;
+use std::time::{Duration, Instant};
 use test::black_box;
 
-use tempdir::TempDir;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use profiler::callgrind_out::CallgrindTotals;
+use profiler::perf::{self, CounterValues};
+use profiler::Backend;
 
 #[allow(unused)]
 use rocksdb::rocksdb::{DBIterator, Snapshot, Writable};
 #[allow(unused)]
 use rocksdb::{ReadOptions, SeekKey, WriteBatch, WriteOptions, DB};
 
+use nacs::cache::{CacheState, CachedDb, Warmup};
+use nacs::harness::{ScanHarness, SeekMode};
+use nacs::results::{format_bound, ResultFile, RunParams, RunRecord};
 use nacs::{
-    default_test_db_with_path, drain_data, Scanner, ScannerConfig, ValueType, DIST_QT1_KEY,
-    DIST_QT3_KEY,
+    default_test_db_with_path, drain_data_with_config, encode_row_key, get_cf_handle,
+    open_test_db_with_path, DrainConfig, LockCheck, ScanStats, Scanner, ScannerConfig, ValueType,
+    WriteRatios, CF_DEFAULT, CF_LOCK, CF_WRITE,
 };
 
-use nacs::{forward_batch_scan, forward_scan};
+use nacs::strategy::{ScanStrategy, StrategyRegistry};
 
-fn bench_scan() {
-    // handle config here
-    let common_cfg = ScannerConfig::new(
-        Some(DIST_QT1_KEY.clone().to_vec()),
-        Some(DIST_QT3_KEY.clone().to_vec()),
-    );
+/// The hidden subcommand run under Callgrind by `compare --callgrind`.
+const CALLGRIND_VARIANT: &str = "callgrind-variant";
+
+/// Runs the strategy and panics on an error.
+fn run_strategy(
+    strategy: &dyn ScanStrategy,
+    scanner: &mut Scanner,
+    limit: u64,
+    write_cache: &mut Vec<u8>,
+) -> ScanStats {
+    strategy
+        .run(scanner, limit, write_cache)
+        .unwrap_or_else(|e| panic!("{} failed: {}", strategy.id(), e))
+}
+
+/// A dataset generated by `drain_data_with_config` into `{dir}/{rows}_{value type}`, which
+/// ends with `_v{versions}` if every key has more than one version, with
+/// `_w{put}-{delete}-{lock}-{rollback}` if not every record is a put, and with `_l{locks}` if
+/// some keys are left locked.
+#[derive(Debug, Clone)]
+struct Dataset {
+    path: PathBuf,
+    rows: u64,
+    value_type: ValueType,
+    versions: u64,
+    writes: WriteRatios,
+    locks: f64,
+}
+
+impl Dataset {
+    fn new(
+        dir: &Path,
+        rows: u64,
+        value_type: ValueType,
+        versions: u64,
+        writes: WriteRatios,
+        locks: f64,
+    ) -> Dataset {
+        let mut dataset = Dataset {
+            path: PathBuf::new(),
+            rows,
+            value_type,
+            versions,
+            writes,
+            locks,
+        };
+        dataset.path = dir.join(dataset.name());
+        dataset
+    }
 
-    //    let common_cfg = ScannerConfig::default();
-    let test_rocks_size: Vec<u64> = vec![20000, 50000, 100000, 200000, 500000, 1000000];
-    let allow_values = vec![
-        ValueType::MiddleValue,
-        ValueType::LongValue,
-        ValueType::LongLongValue,
-    ];
-
-    let profile_end = format!(".profile");
-    for rocks_size in test_rocks_size {
-        for defaultcf_value_length in &allow_values {
-            let temp_dir = TempDir::new_in("data", "data").unwrap();
-            println!("{:?}", temp_dir.path());
-            let mut db = default_test_db_with_path(temp_dir.path());
-            drain_data(&mut db, rocks_size, defaultcf_value_length.clone());
-            let db = Arc::new(db);
-            let cur_db = db.clone();
-            let cfg = common_cfg.clone();
-
-            let mut current_vec = Vec::with_capacity(100 * 1024 * 1024);
-            // 预热
-            println!("预热开始");
-
-            for _ in 0..10 {
-                let scanner = Scanner::new(cur_db.clone(), common_cfg.clone());
-                forward_scan(scanner, rocks_size / 2);
-                let scanner = Scanner::new(cur_db.clone(), common_cfg.clone());
-                forward_batch_scan(scanner, 128, rocks_size / 2, &mut current_vec);
+    /// Every dataset of `--rows`, `--value`, `--versions`, `--writes` and `--locks`.
+    fn from_matches(m: &ArgMatches<'_>) -> Vec<Dataset> {
+        let dir = Path::new(m.value_of("dir").unwrap());
+        let rows = values_t!(m, "rows", u64).unwrap_or_else(|e| e.exit());
+        let values = values_t!(m, "value", ValueType).unwrap_or_else(|e| e.exit());
+        let versions = values_t!(m, "versions", u64).unwrap_or_else(|e| e.exit());
+        let writes = values_t!(m, "writes", WriteRatios).unwrap_or_else(|e| e.exit());
+        let locks = values_t!(m, "locks", f64).unwrap_or_else(|e| e.exit());
+        let mut datasets = vec![];
+        for &rows in &rows {
+            for &value_type in &values {
+                for &versions in &versions {
+                    for &writes in &writes {
+                        for &locks in &locks {
+                            datasets
+                                .push(Dataset::new(dir, rows, value_type, versions, writes, locks));
+                        }
+                    }
+                }
             }
-            println!("预热完毕");
-            // value length of default value field.
-            let vl = defaultcf_value_length.value();
-
-            let scan_batch_size = vec![64, 256, 1024];
-
-            let scale = format!("_{}_{}", rocks_size, vl);
-
-            let scanner_forward = Scanner::new(cur_db.clone(), cfg.clone());
-
-            let scanner_forward_name = format!("forward_scan") + &scale + &profile_end;
-            println!("start_task: {}", scanner_forward_name);
-
-            profiler::start(&scanner_forward_name);
-            forward_scan(scanner_forward, black_box(rocks_size / 2));
-            assert!(profiler::stop());
-
-            let scanner_forward_batch_name = format!("forward_scan_batch") + &scale;
-            for sbc in scan_batch_size {
-                let scanner_forward = Scanner::new(cur_db.clone(), cfg.clone());
-                // TODO: should bench on this
-                let name = scanner_forward_batch_name.clone() + &format!("_{}", sbc) + &profile_end;
-                println!("start_task name {}", name);
-                profiler::start(&name);
-                forward_batch_scan(
-                    scanner_forward,
-                    black_box(sbc),
-                    black_box(rocks_size / 2),
-                    &mut current_vec,
-                );
-                assert!(profiler::stop());
+        }
+        datasets
+    }
+
+    fn name(&self) -> String {
+        let mut name = format!("{}_{:?}", self.rows, self.value_type);
+        if self.versions > 1 {
+            name.push_str(&format!("_v{}", self.versions));
+        }
+        if !self.writes.is_puts_only() {
+            name.push_str(&format!("_w{}", self.writes.to_string().replace(':', "-")));
+        }
+        if self.locks > 0.0 {
+            name.push_str(&format!("_l{}", self.locks));
+        }
+        name
+    }
+
+    /// The fields telling the dataset apart in the reports, like in the benchmark ids, e.g.
+    /// `versions 8, writes 2:1:1:1`.
+    fn labels(&self) -> String {
+        let mut labels = vec![];
+        if self.versions > 1 {
+            labels.push(format!("versions {}", self.versions));
+        }
+        if !self.writes.is_puts_only() {
+            labels.push(format!("writes {}", self.writes));
+        }
+        if self.locks > 0.0 {
+            labels.push(format!("pending locks {}", self.locks));
+        }
+        labels.join(", ")
+    }
+
+    fn exists(&self) -> bool {
+        self.path.join("CURRENT").exists()
+    }
+
+    /// Generates the dataset, removing the existing one if `force`.
+    fn generate(&self, force: bool) {
+        if self.exists() && !force {
+            println!("{} exists, skipped", self.path.display());
+            return;
+        }
+        if self.path.exists() {
+            fs::remove_dir_all(&self.path).unwrap();
+        }
+        fs::create_dir_all(&self.path).unwrap();
+        println!("generating {}", self.path.display());
+        let mut db = default_test_db_with_path(&self.path).unwrap();
+        let cfg = DrainConfig::new(self.rows, self.value_type)
+            .versions_per_key(self.versions)
+            .write_ratios(self.writes)
+            .pending_locks(self.locks);
+        drain_data_with_config(&mut db, &cfg).unwrap();
+    }
+
+    /// Opens the dataset, generating it if it doesn't exist.
+    fn open(&self, cache_state: CacheState) -> CachedDb {
+        if !self.exists() {
+            self.generate(false);
+        }
+        CachedDb::open(&self.path, cache_state).unwrap()
+    }
+}
+
+/// How the variants scan a dataset.
+#[derive(Clone)]
+struct ScanOptions {
+    cfg: ScannerConfig,
+    lower: String,
+    upper: String,
+    cache_state: CacheState,
+    seek_mode: SeekMode,
+    warmup: Warmup,
+    samples: usize,
+    out_dir: PathBuf,
+    results_dir: PathBuf,
+}
+
+impl ScanOptions {
+    fn from_matches(m: &ArgMatches<'_>) -> ScanOptions {
+        let lower = parse_row_key(m.value_of("lower").unwrap());
+        let upper = parse_row_key(m.value_of("upper").unwrap());
+        let out_dir = PathBuf::from(m.value_of("out-dir").unwrap());
+        fs::create_dir_all(&out_dir).unwrap();
+        if let Some(backend) = m.value_of("backend") {
+            profiler::set_backend(backend.parse::<Backend>().unwrap());
+        }
+        ScanOptions {
+            lower: format_bound(&lower),
+            upper: format_bound(&upper),
+            cfg: ScannerConfig::new(Some(lower), Some(upper))
+                .lock_check(lock_check_from_matches(m)),
+            cache_state: value_t!(m, "cache", CacheState).unwrap_or_else(|e| e.exit()),
+            seek_mode: value_t!(m, "seek", SeekMode).unwrap_or_else(|e| e.exit()),
+            warmup: warmup_from_matches(m),
+            samples: value_t!(m, "samples", usize)
+                .unwrap_or_else(|e| e.exit())
+                .max(1),
+            out_dir,
+            results_dir: PathBuf::from(m.value_of("results").unwrap()),
+        }
+    }
+}
+
+fn lock_check_from_matches(m: &ArgMatches<'_>) -> LockCheck {
+    value_t!(m, "lock-check", LockCheck).unwrap_or_else(|e| e.exit())
+}
+
+fn warmup_from_matches(m: &ArgMatches<'_>) -> Warmup {
+    Warmup::new()
+        .max_runs(value_t!(m, "warmup", usize).unwrap_or_else(|e| e.exit()))
+        .max_cv(value_t!(m, "warmup-cv", f64).unwrap_or_else(|e| e.exit()))
+}
+
+/// Warms up the caches with the variant until its latency is stable, if the cache state needs.
+fn warm_up(
+    harness: &ScanHarness,
+    variant: &dyn ScanStrategy,
+    limit: u64,
+    warmup: &Warmup,
+    seek_mode: SeekMode,
+) -> usize {
+    if !harness.cache_state().needs_warmup() {
+        return 0;
+    }
+    let report = warmup.run(|| {
+        let scan = |scanner: &mut Scanner, write_cache: &mut Vec<u8>| {
+            run_strategy(variant, scanner, limit, write_cache)
+        };
+        let cfg = variant.scanner_config(harness.cfg());
+        harness.measure_with(seek_mode, &cfg, scan).unwrap().1
+    });
+    println!("warmup: {}", report);
+    report.runs
+}
+
+/// Parses a row key bound in the form of `table_id:handle`.
+fn parse_row_key(s: &str) -> Vec<u8> {
+    let parsed = match s.find(':') {
+        Some(i) => s[..i]
+            .parse()
+            .and_then(|t| s[i + 1..].parse().map(|h| (t, h))),
+        None => s.parse().map(|t| (t, 0)),
+    };
+    match parsed {
+        Ok((table_id, handle)) => encode_row_key(table_id, handle),
+        Err(e) => {
+            eprintln!("bad key {}, expect table_id:handle: {}", s, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// The variants of `--variants` (or `--variant`), every registered one if absent, swept over
+/// the values of `--param` and `--batch-sizes` (or `--batch-size`). The variants not checking
+/// the locks are skipped if `--lock-check` is on.
+fn variants_from_matches(m: &ArgMatches<'_>) -> Vec<Box<dyn ScanStrategy>> {
+    let registry = StrategyRegistry::builtin();
+    let mut overrides = HashMap::new();
+    overrides.insert(
+        "batch_size".to_owned(),
+        values_t!(m, "batch-size", u64).unwrap_or_else(|e| e.exit()),
+    );
+    for param in m.values_of("param").into_iter().flatten() {
+        let (name, values) = parse_param(param).unwrap_or_else(|| {
+            eprintln!("bad param {}, expect name=v1,v2", param);
+            process::exit(1);
+        });
+        overrides.insert(name, values);
+    }
+    let names = match m.values_of("variant") {
+        Some(names) => names.collect(),
+        None => registry.names(),
+    };
+    let checks_locks = lock_check_from_matches(m) != LockCheck::Off;
+    names
+        .into_iter()
+        .flat_map(|name| registry.get(name).unwrap().sweep(&overrides))
+        .filter(|variant| {
+            let skipped = checks_locks && !variant.checks_locks();
+            if skipped {
+                println!("{} skipped, it doesn't check CF_LOCK", variant.id());
+            }
+            !skipped
+        })
+        .collect()
+}
+
+/// Parses a `--param` in the form of `name=v1,v2`.
+fn parse_param(s: &str) -> Option<(String, Vec<u64>)> {
+    let i = s.find('=')?;
+    let values = s[i + 1..]
+        .split(',')
+        .map(|v| v.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    Some((s[..i].to_owned(), values))
+}
+
+static WARN_NO_PROFILE: Once = Once::new();
+
+struct ScanResult {
+    name: String,
+    rows: u64,
+    elapsed: Duration,
+    /// Counted over a single scan.
+    counters: CounterValues,
+    record: RunRecord,
+}
+
+/// The name of the variant scanning the dataset, which ends with `_seek` if the seeks are
+/// measured and with `_lock-{check}` if the locks are checked.
+fn task_name(variant: &dyn ScanStrategy, dataset: &Dataset, opts: &ScanOptions) -> String {
+    let mut name = format!("{}_{}", variant.id(), dataset.name());
+    if opts.seek_mode == SeekMode::Included {
+        name.push_str("_seek");
+    }
+    if opts.cfg.lock_check != LockCheck::Off {
+        name.push_str(&format!("_lock-{}", opts.cfg.lock_check));
+    }
+    name
+}
+
+/// Warms up and then scans half of the rows with the variant `opts.samples` times, under the
+/// profiler if enabled. The profile includes the setup and teardown of every scan, e.g.
+/// reopening the db for the cold cache states, the counters and latencies don't.
+fn profile_variant(
+    harness: &ScanHarness,
+    dataset: &Dataset,
+    variant: &dyn ScanStrategy,
+    opts: &ScanOptions,
+) -> ScanResult {
+    let limit = dataset.rows / 2;
+    let name = task_name(variant, dataset, opts);
+    println!("start_task: {}", name);
+    let warmup_runs = warm_up(harness, variant, limit, &opts.warmup, opts.seek_mode);
+
+    let profile = opts.out_dir.join(format!("{}.profile", name));
+    let guard = match profiler::profile(profile.to_str().unwrap()) {
+        Ok(guard) => Some(guard),
+        Err(e) => {
+            WARN_NO_PROFILE.call_once(|| println!("run without profile: {}", e));
+            None
+        }
+    };
+
+    let mut stats = ScanStats::default();
+    let mut samples = Vec::with_capacity(opts.samples);
+    let mut counters: Option<CounterValues> = None;
+    let cfg = variant.scanner_config(harness.cfg());
+    for _ in 0..opts.samples {
+        let mut input = harness.setup_with(opts.seek_mode, &cfg).unwrap();
+        let region = perf::region(name.as_str());
+        let start = Instant::now();
+        stats = harness.scan(&mut input, |scanner, write_cache| {
+            run_strategy(variant, scanner, black_box(limit), write_cache)
+        });
+        samples.push(start.elapsed());
+        let values = region.finish().values;
+        harness.teardown(input);
+        match counters {
+            Some(ref mut c) => c.add(&values),
+            None => counters = Some(values),
+        }
+    }
+    let counters = counters.unwrap().per(opts.samples as u64);
+
+    if let Some(guard) = guard {
+        guard.finish().unwrap();
+    }
+
+    let params = RunParams {
+        rows: dataset.rows,
+        value_type: format!("{:?}", dataset.value_type),
+        variant: variant.name().to_owned(),
+        batch_size: variant.param("batch_size"),
+        lower: opts.lower.clone(),
+        upper: opts.upper.clone(),
+        warmup: warmup_runs,
+        cache_state: harness.cache_state().to_string(),
+        seek_mode: opts.seek_mode.to_string(),
+        strategy: variant.id(),
+        lock_check: opts.cfg.lock_check.to_string(),
+        dataset: dataset.labels(),
+    };
+    let record = RunRecord::new(name.as_str(), params, &stats, &samples);
+    ScanResult {
+        name,
+        rows: stats.rows,
+        elapsed: Duration::from_secs_f64(record.latency.mean_us / 1e6),
+        counters,
+        record,
+    }
+}
+
+fn print_results(results: &[ScanResult]) {
+    println!(
+        "{:<44} {:>8} {:>12} {:>12} {:>8} {:>14} {:>14} {:>6} {:>12} {:>14}",
+        "task",
+        "rows",
+        "mean(us)",
+        "rows/s",
+        "MB/s",
+        "cycles",
+        "instructions",
+        "ipc",
+        "llc misses",
+        "branch misses"
+    );
+    let fmt_value = |v: Option<u64>| v.map_or_else(|| "n/a".to_owned(), |v| v.to_string());
+    for r in results {
+        println!(
+            "{:<44} {:>8} {:>12} {:>12.0} {:>8.1} {:>14} {:>14} {:>6} {:>12} {:>14}",
+            r.name,
+            r.rows,
+            r.elapsed.as_micros(),
+            r.record.throughput,
+            r.record.byte_throughput / (1024.0 * 1024.0),
+            fmt_value(r.counters.cycles),
+            fmt_value(r.counters.instructions),
+            r.counters
+                .ipc()
+                .map_or_else(|| "n/a".to_owned(), |ipc| format!("{:.2}", ipc)),
+            fmt_value(r.counters.llc_misses),
+            fmt_value(r.counters.branch_misses),
+        );
+    }
+}
+
+fn cmd_gen(m: &ArgMatches<'_>) {
+    for dataset in Dataset::from_matches(m) {
+        dataset.generate(m.is_present("force"));
+    }
+}
+
+fn cmd_scan(m: &ArgMatches<'_>) {
+    let opts = ScanOptions::from_matches(m);
+    let variants = variants_from_matches(m);
+    let mut results = vec![];
+    for dataset in Dataset::from_matches(m) {
+        let harness = ScanHarness::new(dataset.open(opts.cache_state), opts.cfg.clone());
+        for variant in &variants {
+            results.push(profile_variant(&harness, &dataset, variant.as_ref(), &opts));
+        }
+    }
+    print_results(&results);
+    save_results(&opts, results.into_iter().map(|r| r.record).collect());
+}
+
+fn save_results(opts: &ScanOptions, records: Vec<RunRecord>) {
+    match (ResultFile { records }).save(&opts.results_dir, "profile") {
+        Ok(path) => println!("results saved to {}", path.display()),
+        Err(e) => eprintln!("failed to save results: {}", e),
+    }
+}
+
+fn cmd_compare(m: &ArgMatches<'_>) {
+    if m.is_present("callgrind") {
+        return callgrind_compare(m);
+    }
+    cmd_scan(m)
+}
+
+/// Runs every variant under Callgrind and prints the instructions they execute, which are
+/// stable across runs unlike the wall-clock times.
+fn callgrind_compare(m: &ArgMatches<'_>) {
+    if !cfg!(feature = "profiling") {
+        eprintln!("--callgrind needs the `profiling` feature");
+        process::exit(1);
+    }
+    let exe = std::env::current_exe().unwrap();
+    let opts = ScanOptions::from_matches(m);
+    let variants = variants_from_matches(m);
+
+    // (variant, dataset, instructions)
+    let mut results = vec![];
+    for dataset in Dataset::from_matches(m) {
+        // The variants open it in their own processes.
+        if !dataset.exists() {
+            dataset.generate(false);
+        }
+        for variant in &variants {
+            let name = task_name(variant.as_ref(), &dataset, &opts);
+            let out_file = opts.out_dir.join(format!("{}.callgrind.out", name));
+            remove_callgrind_out(&out_file);
+            println!("start_task: {}", name);
+
+            let mut cmd = Command::new("valgrind");
+            cmd.arg("--tool=callgrind")
+                .arg("--instr-atstart=no")
+                .arg(format!("--callgrind-out-file={}", out_file.display()))
+                .arg(&exe)
+                .arg(CALLGRIND_VARIANT)
+                .arg(&dataset.path)
+                .arg(dataset.rows.to_string())
+                .arg(variant.id())
+                .args(&["--lower", m.value_of("lower").unwrap()])
+                .args(&["--upper", m.value_of("upper").unwrap()])
+                .args(&["--warmup", m.value_of("warmup").unwrap()])
+                .args(&["--warmup-cv", m.value_of("warmup-cv").unwrap()])
+                .args(&["--cache", m.value_of("cache").unwrap()])
+                .args(&["--seek", m.value_of("seek").unwrap()])
+                .args(&["--lock-check", m.value_of("lock-check").unwrap()])
+                .env(profiler::BACKEND_ENV, "callgrind");
+            let status = cmd
+                .status()
+                .unwrap_or_else(|e| panic!("failed to run valgrind: {}", e));
+            assert!(status.success(), "{} failed: {}", name, status);
+
+            let totals = CallgrindTotals::read(&out_file).unwrap();
+            let instructions = totals.get("Ir").expect("no Ir event");
+            results.push((variant.id(), dataset.clone(), instructions));
+        }
+    }
+
+    println!(
+        "{:<24} {:>10} {:>16} {:>8} {:>16} {:>15}",
+        "variant", "rows", "value type", "versions", "instructions", "vs forward_scan"
+    );
+    for (variant, dataset, instructions) in &results {
+        let base = results
+            .iter()
+            .find(|r| r.0 == "forward_scan" && r.1.path == dataset.path)
+            .map(|r| format!("{:.3}", *instructions as f64 / r.2 as f64))
+            .unwrap_or_else(|| "n/a".to_owned());
+        println!(
+            "{:<24} {:>10} {:>16} {:>8} {:>16} {:>15}",
+            variant,
+            dataset.rows,
+            format!("{:?}", dataset.value_type),
+            dataset.versions,
+            instructions,
+            base
+        );
+    }
+}
+
+/// Removes the output file and the dumped parts of an earlier run, which would be summed.
+fn remove_callgrind_out(out_file: &Path) {
+    let base = out_file.file_name().unwrap().to_str().unwrap();
+    for entry in fs::read_dir(out_file.parent().unwrap()).unwrap() {
+        let entry = entry.unwrap();
+        let name = entry.file_name();
+        let name = name.to_str().unwrap_or_default();
+        if name == base || name.starts_with(&format!("{}.", base)) {
+            fs::remove_file(entry.path()).unwrap();
+        }
+    }
+}
+
+/// Scans the dataset with the variant, instrumenting only the scan phase.
+fn callgrind_variant(m: &ArgMatches<'_>) {
+    let cache_state = value_t!(m, "cache", CacheState).unwrap_or_else(|e| e.exit());
+    let seek_mode = value_t!(m, "seek", SeekMode).unwrap_or_else(|e| e.exit());
+    let db = CachedDb::open(m.value_of("db").unwrap(), cache_state).unwrap();
+    let limit = value_t!(m, "rows", u64).unwrap_or_else(|e| e.exit()) / 2;
+    let name = m.value_of("variant").unwrap();
+    let variant = StrategyRegistry::builtin()
+        .parse_id(name)
+        .unwrap_or_else(|| panic!("unknown variant {}", name));
+    let warmup = warmup_from_matches(m);
+    let cfg = ScannerConfig::new(
+        Some(parse_row_key(m.value_of("lower").unwrap())),
+        Some(parse_row_key(m.value_of("upper").unwrap())),
+    )
+    .lock_check(lock_check_from_matches(m));
+    let harness = ScanHarness::new(db, cfg);
+
+    // Prepare the caches before the instrumentation starts.
+    warm_up(&harness, variant.as_ref(), limit, &warmup, seek_mode);
+    let cfg = variant.scanner_config(harness.cfg());
+    let mut input = harness.setup_with(seek_mode, &cfg).unwrap();
+
+    let guard = profiler::profile(name).unwrap();
+    harness.scan(&mut input, |scanner, write_cache| {
+        run_strategy(variant.as_ref(), scanner, black_box(limit), write_cache)
+    });
+    guard.finish().unwrap();
+    harness.teardown(input);
+}
+
+fn cmd_inspect(m: &ArgMatches<'_>) {
+    for dataset in Dataset::from_matches(m) {
+        if !dataset.exists() {
+            println!("{} not generated", dataset.path.display());
+            continue;
+        }
+        let db = open_test_db_with_path(&dataset.path).unwrap();
+        println!("{}", dataset.path.display());
+        for cf in &[CF_WRITE, CF_DEFAULT, CF_LOCK] {
+            let handle = get_cf_handle(&db, cf).unwrap();
+            let (mut keys, mut key_bytes, mut value_bytes) = (0u64, 0, 0);
+            let (mut first, mut last) = (vec![], vec![]);
+            let mut iter = db.iter_cf(handle);
+            iter.seek(SeekKey::Start);
+            while iter.valid() {
+                if keys == 0 {
+                    first = iter.key().to_vec();
+                }
+                last.clear();
+                last.extend_from_slice(iter.key());
+                keys += 1;
+                key_bytes += iter.key().len() as u64;
+                value_bytes += iter.value().len() as u64;
+                iter.next();
             }
+            let property = |name| {
+                db.get_property_int_cf(handle, name)
+                    .map_or_else(|| "n/a".to_owned(), |v| v.to_string())
+            };
+            println!("  cf {}", cf);
+            println!("    keys            {}", keys);
+            println!("    key bytes       {}", key_bytes);
+            println!("    value bytes     {}", value_bytes);
+            println!("    first key       {}", hex::encode(&first));
+            println!("    last key        {}", hex::encode(&last));
+            println!(
+                "    estimated keys  {}",
+                property("rocksdb.estimate-num-keys")
+            );
+            println!(
+                "    sst files size  {}",
+                property("rocksdb.total-sst-files-size")
+            );
         }
     }
 }
 
+fn dataset_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("dir")
+            .long("dir")
+            .takes_value(true)
+            .default_value("data")
+            .help("Directory of the datasets"),
+        Arg::with_name("rows")
+            .long("rows")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .default_value("20000")
+            .help("Rows of the datasets"),
+        Arg::with_name("value")
+            .long("value")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .possible_values(&["64", "128", "256", "mix"])
+            .default_value("64")
+            .help("Value lengths of the datasets"),
+        Arg::with_name("versions")
+            .long("versions")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .default_value("1")
+            .help("Versions of every key in the datasets"),
+        Arg::with_name("writes")
+            .long("writes")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .default_value("1:0:0:0")
+            .help("Weights of the put:delete:lock:rollback records of the datasets"),
+        Arg::with_name("locks")
+            .long("locks")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .default_value("0")
+            .help("Fractions of the keys left locked by pending transactions in the datasets"),
+    ]
+}
+
+fn scan_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("lower")
+            .long("lower")
+            .takes_value(true)
+            .default_value("7:0")
+            .help("Lower bound of the scan, as table_id:handle"),
+        Arg::with_name("upper")
+            .long("upper")
+            .takes_value(true)
+            .default_value("22:2000")
+            .help("Upper bound of the scan, as table_id:handle"),
+        Arg::with_name("warmup")
+            .long("warmup")
+            .takes_value(true)
+            .default_value("50")
+            .help("Most scans to warm up with, the warmup stops once the latency is stable"),
+        Arg::with_name("warmup-cv")
+            .long("warmup-cv")
+            .takes_value(true)
+            .default_value("0.05")
+            .help("The latency is stable once the coefficient of variation of 5 scans is below"),
+        Arg::with_name("cache")
+            .long("cache")
+            .takes_value(true)
+            .possible_values(&["cold", "warm-os", "warm-block"])
+            .default_value("warm-block")
+            .help("Caches the profiled scans start with, cold and warm-os reopen the db"),
+        Arg::with_name("seek")
+            .long("seek")
+            .takes_value(true)
+            .possible_values(&["included", "excluded"])
+            .default_value("excluded")
+            .help("Whether the seeks to the lower bound are measured with the scans"),
+        Arg::with_name("lock-check")
+            .long("lock-check")
+            .takes_value(true)
+            .possible_values(&["off", "report", "fail"])
+            .default_value("off")
+            .help("Whether the scans check CF_LOCK, and fail or report the conflicting locks"),
+        Arg::with_name("samples")
+            .long("samples")
+            .takes_value(true)
+            .default_value("10")
+            .help("Profiled scans, their latencies are recorded"),
+        Arg::with_name("out-dir")
+            .long("out-dir")
+            .takes_value(true)
+            .default_value("profile")
+            .help("Directory of the profiles"),
+        Arg::with_name("results")
+            .long("results")
+            .takes_value(true)
+            .default_value("results")
+            .help("Directory of the JSON and CSV run records"),
+        Arg::with_name("backend")
+            .long("backend")
+            .takes_value(true)
+            .possible_values(&["auto", "gperftools", "callgrind", "sampling"])
+            .help("Profiler backend, defaults to PROFILER_BACKEND or auto"),
+    ]
+}
+
+fn variant_args<'a, 'b>(multiple: bool, names: &'a [&'b str]) -> Vec<Arg<'a, 'b>> {
+    let mut variant = Arg::with_name("variant")
+        .long(if multiple { "variants" } else { "variant" })
+        .takes_value(true)
+        .multiple(multiple)
+        .use_delimiter(multiple)
+        .possible_values(names);
+    variant = if multiple {
+        variant.help("Scan variants to run, every registered one by default")
+    } else {
+        variant
+            .default_value("forward_scan")
+            .help("Scan variant to run")
+    };
+    vec![
+        variant,
+        Arg::with_name("batch-size")
+            .long(if multiple {
+                "batch-sizes"
+            } else {
+                "batch-size"
+            })
+            .takes_value(true)
+            .multiple(multiple)
+            .use_delimiter(multiple)
+            .default_value(if multiple { "64,256,1024" } else { "256" })
+            .help("Batch sizes of forward_batch_scan, the same as --param batch_size=.."),
+        Arg::with_name("param")
+            .long("param")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Values of a variant parameter as name=v1,v2, can be repeated"),
+    ]
+}
+
+fn app<'a, 'b>(variant_names: &'a [&'b str]) -> App<'a, 'b> {
+    App::new("profile")
+        .about("Generates datasets and profiles the scans")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("gen")
+                .about("Generates datasets")
+                .args(&dataset_args())
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Regenerates existing datasets"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("scan")
+                .about("Profiles a scan variant")
+                .args(&dataset_args())
+                .args(&scan_args())
+                .args(&variant_args(false, variant_names)),
+        )
+        .subcommand(
+            SubCommand::with_name("compare")
+                .about("Profiles several scan variants and compares them")
+                .args(&dataset_args())
+                .args(&scan_args())
+                .args(&variant_args(true, variant_names))
+                .arg(
+                    Arg::with_name("callgrind")
+                        .long("callgrind")
+                        .help("Counts the instructions of each variant under Callgrind"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .about("Shows the stats of datasets")
+                .args(&dataset_args()),
+        )
+        .subcommand(
+            SubCommand::with_name(CALLGRIND_VARIANT)
+                .setting(AppSettings::Hidden)
+                .arg(Arg::with_name("db").required(true))
+                .arg(Arg::with_name("rows").required(true))
+                .arg(Arg::with_name("variant").required(true))
+                .args(&scan_args()),
+        )
+}
+
 fn main() {
-    bench_scan()
+    let variant_names = StrategyRegistry::builtin().names();
+    let matches = app(&variant_names).get_matches();
+    match matches.subcommand() {
+        ("gen", Some(m)) => cmd_gen(m),
+        ("scan", Some(m)) => cmd_scan(m),
+        ("compare", Some(m)) => cmd_compare(m),
+        ("inspect", Some(m)) => cmd_inspect(m),
+        (CALLGRIND_VARIANT, Some(m)) => callgrind_variant(m),
+        _ => unreachable!(),
+    }
 }
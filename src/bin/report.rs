@@ -0,0 +1,77 @@
+//! Renders the benchmark results as the markdown tables of `readme.md`.
+//!
+//! ```bash
+//! cargo bench --bench benchmark && report > report.md
+//! report --results results/profile-1571000000.json --output report.md
+//! report --criterion criterion --results results/benchmark-1571000000.json
+//! ```
+//!
+//! The benchmarks declare the rows as their throughput, the bytes of the MB/s are taken from the
+//! run records of the same scans if both directories are given.
+
+extern crate clap;
+extern crate nacs;
+
+use std::fs;
+use std::process;
+
+use clap::{App, Arg};
+
+use nacs::report::{fill_scanned_bytes, from_result_file, read_criterion_dir, render_markdown};
+use nacs::results::ResultFile;
+
+fn main() {
+    let m = App::new("report")
+        .about("Renders the benchmark results as markdown tables")
+        .arg(
+            Arg::with_name("criterion")
+                .long("criterion")
+                .takes_value(true)
+                .default_value("criterion")
+                .help("Directory of the criterion results"),
+        )
+        .arg(
+            Arg::with_name("results")
+                .long("results")
+                .takes_value(true)
+                .help(
+                    "Result file of `profile` or the benchmark, instead of criterion's unless \
+                     --criterion is given too",
+                ),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .help("Markdown file to write, defaults to stdout"),
+        )
+        .get_matches();
+
+    let criterion = m.value_of("criterion").unwrap();
+    let estimates = match m.value_of("results") {
+        Some(path) if m.occurrences_of("criterion") == 0 => {
+            ResultFile::read(path).map(|f| from_result_file(&f))
+        }
+        Some(path) => ResultFile::read(path).and_then(|f| {
+            let mut estimates = read_criterion_dir(criterion)?;
+            fill_scanned_bytes(&mut estimates, &from_result_file(&f));
+            Ok(estimates)
+        }),
+        None => read_criterion_dir(criterion),
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("failed to read the results: {}", e);
+        process::exit(2);
+    });
+    if estimates.is_empty() {
+        eprintln!("no scan results found");
+        process::exit(2);
+    }
+
+    let markdown = render_markdown(&estimates);
+    match m.value_of("output") {
+        Some(path) => fs::write(path, markdown).unwrap(),
+        None => print!("{}", markdown),
+    }
+}
@@ -0,0 +1,199 @@
+//! Shows and compares the run records saved by the benchmark and the `profile` binary.
+//!
+//! ```bash
+//! results show results/profile-1571000000.json
+//! results history --dir results --name forward_scan_20000_MiddleValue
+//! results compare results/profile-1571000000.json results/profile-1571003600.json
+//! ```
+//!
+//! `compare` exits with 1 if any task regressed significantly, so it can gate CI.
+
+#[macro_use]
+extern crate clap;
+extern crate nacs;
+
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use nacs::results::{compare_files, ResultFile, RunRecord, Verdict};
+
+const MB: f64 = 1024.0 * 1024.0;
+
+fn read_or_exit(path: &str) -> ResultFile {
+    ResultFile::read(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", path, e);
+        process::exit(2);
+    })
+}
+
+fn print_records<'a>(records: impl IntoIterator<Item = &'a RunRecord>) {
+    println!(
+        "{:<44} {:>10} {:>10} {:>12} {:>8} {:>12} {:>12} {:>12} {:>12} {:>10}",
+        "task",
+        "cache",
+        "timestamp",
+        "git rev",
+        "samples",
+        "mean(us)",
+        "p50(us)",
+        "p99(us)",
+        "rows/s",
+        "MB/s"
+    );
+    for r in records {
+        let rev = r
+            .git_rev
+            .as_ref()
+            .map_or("n/a", |rev| &rev[..rev.len().min(12)]);
+        println!(
+            "{:<44} {:>10} {:>10} {:>12} {:>8} {:>12.1} {:>12.1} {:>12.1} {:>12.0} {:>10.1}",
+            r.name,
+            r.params.cache_state,
+            r.timestamp,
+            rev,
+            r.latency.samples,
+            r.latency.mean_us,
+            r.latency.p50_us,
+            r.latency.p99_us,
+            r.throughput,
+            r.byte_throughput / MB
+        );
+    }
+}
+
+fn cmd_show(m: &ArgMatches<'_>) {
+    let file = read_or_exit(m.value_of("file").unwrap());
+    print_records(&file.records);
+}
+
+/// Lists the records of every result file in the directory, oldest first.
+fn cmd_history(m: &ArgMatches<'_>) {
+    let dir = Path::new(m.value_of("dir").unwrap());
+    let mut records = vec![];
+    for entry in fs::read_dir(dir).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", dir.display(), e);
+        process::exit(2);
+    }) {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(true, |ext| ext != "json") {
+            continue;
+        }
+        match ResultFile::read(&path) {
+            Ok(file) => records.extend(file.records),
+            Err(e) => eprintln!("skip {}: {}", path.display(), e),
+        }
+    }
+    if let Some(name) = m.value_of("name") {
+        records.retain(|r| r.name == name);
+    }
+    records.sort_by(|a, b| (a.timestamp, &a.name).cmp(&(b.timestamp, &b.name)));
+    print_records(&records);
+}
+
+fn cmd_compare(m: &ArgMatches<'_>) {
+    let base = read_or_exit(m.value_of("base").unwrap());
+    let new = read_or_exit(m.value_of("new").unwrap());
+    let alpha = value_t!(m, "alpha", f64).unwrap_or_else(|e| e.exit());
+    let noise = value_t!(m, "noise", f64).unwrap_or_else(|e| e.exit());
+
+    let comparisons = compare_files(&base, &new, alpha, noise);
+    println!(
+        "{:<44} {:>12} {:>12} {:>9} {:>9} {:>12}",
+        "task", "base(us)", "new(us)", "change", "p-value", "verdict"
+    );
+    for c in &comparisons {
+        let verdict = match c.verdict {
+            Verdict::Regression => "regression",
+            Verdict::Improvement => "improvement",
+            Verdict::NoChange => "no change",
+        };
+        println!(
+            "{:<44} {:>12.1} {:>12.1} {:>8.2}% {:>9.4} {:>12}",
+            c.name,
+            c.base_mean_us,
+            c.new_mean_us,
+            c.change * 100.0,
+            c.p_value,
+            verdict
+        );
+    }
+    for r in &new.records {
+        let matched = base
+            .records
+            .iter()
+            .any(|b| b.name == r.name && b.params.cache_state == r.params.cache_state);
+        if !matched {
+            println!("{:<44} not in the base file", r.name);
+        }
+    }
+
+    let regressions = comparisons
+        .iter()
+        .filter(|c| c.verdict == Verdict::Regression)
+        .count();
+    if regressions > 0 {
+        println!("{} regression(s) found", regressions);
+        process::exit(1);
+    }
+}
+
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("results")
+        .about("Shows and compares the saved run records")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("show")
+                .about("Prints the records of a result file")
+                .arg(Arg::with_name("file").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Prints the records of every result file in a directory, oldest first")
+                .arg(
+                    Arg::with_name("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .default_value("results")
+                        .help("Directory of the result files"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .takes_value(true)
+                        .help("Only prints the records of this task"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("compare")
+                .about("Compares the latencies of the same tasks in two result files")
+                .arg(Arg::with_name("base").required(true))
+                .arg(Arg::with_name("new").required(true))
+                .arg(
+                    Arg::with_name("alpha")
+                        .long("alpha")
+                        .takes_value(true)
+                        .default_value("0.05")
+                        .help("Significance level of Welch's t-test"),
+                )
+                .arg(
+                    Arg::with_name("noise")
+                        .long("noise")
+                        .takes_value(true)
+                        .default_value("0.02")
+                        .help("Changes of the mean below this ratio are ignored"),
+                ),
+        )
+}
+
+fn main() {
+    let matches = app().get_matches();
+    match matches.subcommand() {
+        ("show", Some(m)) => cmd_show(m),
+        ("history", Some(m)) => cmd_history(m),
+        ("compare", Some(m)) => cmd_compare(m),
+        _ => unreachable!(),
+    }
+}
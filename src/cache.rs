@@ -0,0 +1,336 @@
+//! Puts the block cache and the OS page cache into a known state before a measured scan.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rocksdb::DB;
+
+use crate::errors::{Error, Result};
+use crate::gen_db::{get_cf_handle, open_test_db_with_path};
+use crate::tikv_code::constexpr::{CF_DEFAULT, CF_LOCK, CF_WRITE};
+
+/// The environment variable to choose the cache state of the benchmarks, e.g.
+/// `BENCH_CACHE_STATE=cold`.
+pub const CACHE_STATE_ENV: &str = "BENCH_CACHE_STATE";
+
+/// The caches a measured scan starts with.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CacheState {
+    /// A fresh block cache and the SST files dropped from the page cache.
+    Cold,
+    /// A fresh block cache, the SST files are still in the page cache.
+    WarmOsCache,
+    /// The blocks read by the warmup are kept in the block cache.
+    #[default]
+    WarmBlockCache,
+}
+
+impl CacheState {
+    /// Reads the state from `BENCH_CACHE_STATE`, `WarmBlockCache` if it is not set or unknown.
+    pub fn from_env() -> CacheState {
+        std::env::var(CACHE_STATE_ENV)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CacheState::Cold => "cold",
+            CacheState::WarmOsCache => "warm-os",
+            CacheState::WarmBlockCache => "warm-block",
+        }
+    }
+
+    /// A cold scan gains nothing from the warmup, the caches are dropped anyway.
+    pub fn needs_warmup(self) -> bool {
+        self != CacheState::Cold
+    }
+
+    /// Whether the db is reopened before every measured scan.
+    pub fn reopens(self) -> bool {
+        self != CacheState::WarmBlockCache
+    }
+}
+
+impl FromStr for CacheState {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<CacheState, String> {
+        match s.to_lowercase().as_str() {
+            "cold" => Ok(CacheState::Cold),
+            "warm-os" => Ok(CacheState::WarmOsCache),
+            "warm-block" => Ok(CacheState::WarmBlockCache),
+            _ => Err(format!("unknown cache state {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for CacheState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A db created by `default_test_db_with_path`, reopened to reach the cache state.
+pub struct CachedDb {
+    path: PathBuf,
+    db: Option<Arc<DB>>,
+    state: CacheState,
+}
+
+impl CachedDb {
+    /// Takes the db at `path`. The memtables are flushed, so that the scans read the SST
+    /// files rather than a memtable replayed from the WAL after reopening.
+    pub fn new(db: DB, path: impl AsRef<Path>, state: CacheState) -> Result<CachedDb> {
+        for cf in &[CF_DEFAULT, CF_LOCK, CF_WRITE] {
+            db.flush_cf(get_cf_handle(&db, cf)?, true)
+                .map_err(Error::Engine)?;
+        }
+        Ok(CachedDb {
+            path: path.as_ref().to_owned(),
+            db: Some(Arc::new(db)),
+            state,
+        })
+    }
+
+    pub fn open(path: impl AsRef<Path>, state: CacheState) -> Result<CachedDb> {
+        let db = open_test_db_with_path(path.as_ref())?;
+        CachedDb::new(db, path, state)
+    }
+
+    pub fn db(&self) -> &Arc<DB> {
+        self.db.as_ref().unwrap()
+    }
+
+    pub fn state(&self) -> CacheState {
+        self.state
+    }
+
+    /// Puts the caches into the state before a measured scan. Every scanner of the db must
+    /// have been dropped if the db is reopened.
+    pub fn prepare(&mut self) -> Result<()> {
+        match self.state {
+            CacheState::Cold => self.reopen(true),
+            CacheState::WarmOsCache => self.reopen(false),
+            CacheState::WarmBlockCache => Ok(()),
+        }
+    }
+
+    fn reopen(&mut self, drop_page_cache: bool) -> Result<()> {
+        let db = self.db.take().unwrap();
+        if let Err(db) = Arc::try_unwrap(db) {
+            self.db = Some(db);
+            return Err(Error::DbInUse);
+        }
+        if drop_page_cache {
+            drop_os_cache(&self.path)?;
+        }
+        self.db = Some(Arc::new(open_test_db_with_path(&self.path)?));
+        Ok(())
+    }
+}
+
+/// Drops the files under `dir` from the page cache by `posix_fadvise(POSIX_FADV_DONTNEED)`,
+/// which is a no-op on the platforms without it.
+pub fn drop_os_cache(dir: impl AsRef<Path>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            let file = File::open(&path)?;
+            // Dirty pages are not dropped.
+            file.sync_data()?;
+            fadvise_dontneed(&file)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn fadvise_dontneed(file: &File) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let ret = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if ret != 0 {
+        return Err(std::io::Error::from_raw_os_error(ret).into());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fadvise_dontneed(_: &File) -> Result<()> {
+    Ok(())
+}
+
+/// Repeats a scan until its latency is stable, i.e. the coefficient of variation of the last
+/// `window` runs is at most `max_cv`, or `max_runs` runs.
+#[derive(Clone, Debug)]
+pub struct Warmup {
+    pub min_runs: usize,
+    pub max_runs: usize,
+    pub window: usize,
+    pub max_cv: f64,
+}
+
+impl Warmup {
+    pub fn new() -> Warmup {
+        Warmup {
+            min_runs: 5,
+            max_runs: 50,
+            window: 5,
+            max_cv: 0.05,
+        }
+    }
+
+    pub fn min_runs(mut self, min_runs: usize) -> Warmup {
+        self.min_runs = min_runs;
+        self
+    }
+
+    pub fn max_runs(mut self, max_runs: usize) -> Warmup {
+        self.max_runs = max_runs;
+        self
+    }
+
+    pub fn window(mut self, window: usize) -> Warmup {
+        self.window = window.max(2);
+        self
+    }
+
+    pub fn max_cv(mut self, max_cv: f64) -> Warmup {
+        self.max_cv = max_cv;
+        self
+    }
+
+    /// Runs `scan`, which returns the time it takes, until it is stable.
+    pub fn run(&self, mut scan: impl FnMut() -> Duration) -> WarmupReport {
+        let mut samples = Vec::with_capacity(self.max_runs);
+        let mut cv = None;
+        while samples.len() < self.max_runs {
+            samples.push(scan().as_secs_f64());
+            if samples.len() >= self.window {
+                cv = Some(coefficient_of_variation(
+                    &samples[samples.len() - self.window..],
+                ));
+            }
+            if samples.len() >= self.min_runs && cv.is_some_and(|cv| cv <= self.max_cv) {
+                return WarmupReport {
+                    runs: samples.len(),
+                    cv,
+                    stable: true,
+                };
+            }
+        }
+        WarmupReport {
+            runs: samples.len(),
+            cv,
+            stable: false,
+        }
+    }
+}
+
+impl Default for Warmup {
+    fn default() -> Warmup {
+        Warmup::new()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WarmupReport {
+    pub runs: usize,
+    /// The coefficient of variation of the last window, `None` if there are fewer runs.
+    pub cv: Option<f64>,
+    /// False if it stops at `max_runs`.
+    pub stable: bool,
+}
+
+impl fmt::Display for WarmupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} runs", self.runs)?;
+        if let Some(cv) = self.cv {
+            write!(f, ", cv {:.3}", cv)?;
+        }
+        if !self.stable {
+            write!(f, ", not stable")?;
+        }
+        Ok(())
+    }
+}
+
+fn coefficient_of_variation(samples: &[f64]) -> f64 {
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    if mean > 0.0 {
+        variance.sqrt() / mean
+    } else {
+        0.0
+    }
+}
+
+#[test]
+fn test_warmup_until_stable() {
+    let warmup = Warmup::new()
+        .min_runs(3)
+        .max_runs(20)
+        .window(3)
+        .max_cv(0.01);
+    // Slows down for the first runs and then stays at 100us.
+    let mut times = vec![500, 300, 200, 100, 100, 100, 100].into_iter();
+    let report = warmup.run(|| Duration::from_micros(times.next().unwrap()));
+    assert_eq!(report.runs, 6);
+    assert!(report.stable);
+    assert_eq!(report.cv, Some(0.0));
+
+    let mut n = 0;
+    let report = warmup.run(|| {
+        n += 1;
+        Duration::from_micros(if n % 2 == 0 { 100 } else { 200 })
+    });
+    assert_eq!(report.runs, 20);
+    assert!(!report.stable);
+}
+
+#[test]
+fn test_cached_db_reopen() {
+    use crate::drain::{drain_data, ValueType};
+    use crate::gen_db::default_test_db_with_path;
+    use crate::{forward_scan, Scanner, ScannerConfig};
+
+    let temp_dir = tempdir::TempDir::new("data").unwrap();
+    let mut db = default_test_db_with_path(temp_dir.path()).unwrap();
+    drain_data(&mut db, 100, ValueType::MiddleValue).unwrap();
+    let mut cached = CachedDb::new(db, temp_dir.path(), CacheState::Cold).unwrap();
+
+    let scan = |cached: &CachedDb| {
+        let mut scanner = Scanner::new(cached.db().clone(), ScannerConfig::default()).unwrap();
+        forward_scan(&mut scanner, u64::MAX).unwrap().rows
+    };
+    assert_eq!(scan(&cached), 100);
+    cached.prepare().unwrap();
+    assert_eq!(scan(&cached), 100);
+
+    let scanner = Scanner::new(cached.db().clone(), ScannerConfig::default()).unwrap();
+    match cached.prepare() {
+        Err(Error::DbInUse) => {}
+        res => panic!("expect db in use, got {:?}", res),
+    }
+    drop(scanner);
+    cached.prepare().unwrap();
+    assert_eq!(scan(&cached), 100);
+
+    // The files are dropped from the page cache, never changed.
+    let sst_dir = tempdir::TempDir::new("sst").unwrap();
+    let sst = sst_dir.path().join("000001.sst");
+    fs::write(&sst, vec![7; 4096]).unwrap();
+    drop_os_cache(sst_dir.path()).unwrap();
+    assert_eq!(fs::read(&sst).unwrap(), vec![7; 4096]);
+    assert!(drop_os_cache(sst_dir.path().join("missing")).is_err());
+
+    assert_eq!("warm-os".parse(), Ok(CacheState::WarmOsCache));
+    assert!("hot".parse::<CacheState>().is_err());
+}
@@ -1,7 +1,9 @@
+use std::collections::BTreeMap;
+
 use crate::tikv_code::cfg::ReadableDuration;
 use crate::tikv_code::cfg::ReadableSize;
 use crate::tikv_code::cfg::{GB, KB, MB};
-use crate::tikv_code::constexpr::CF_DEFAULT;
+use crate::tikv_code::constexpr::{CF_DEFAULT, CF_LOCK};
 
 use rocksdb::BlockBasedOptions;
 use rocksdb::Cache;
@@ -14,12 +16,15 @@ use rocksdb::{self, ColumnFamilyOptions, DBOptions, ReadOptions};
 
 const RAFT_MIN_MEM: usize = 256 * MB as usize;
 const RAFT_MAX_MEM: usize = 2 * GB as usize;
+const LOCKCF_MIN_MEM: usize = 256 * MB as usize;
+const LOCKCF_MAX_MEM: usize = GB as usize;
 
 fn memory_mb_for_cf(is_raft_db: bool, cf: &str) -> usize {
     let total_mem = sys_info::mem_info().unwrap().total * KB;
     let (ratio, min, max) = match (is_raft_db, cf) {
         (true, CF_DEFAULT) => (0.02, RAFT_MIN_MEM, RAFT_MAX_MEM),
         (false, CF_DEFAULT) => (0.25, 0, std::usize::MAX),
+        (false, CF_LOCK) => (0.02, LOCKCF_MIN_MEM, LOCKCF_MAX_MEM),
         _ => unreachable!(),
     };
     let mut size = (total_mem as f64 * ratio) as usize;
@@ -31,6 +36,28 @@ fn memory_mb_for_cf(is_raft_db: bool, cf: &str) -> usize {
     size / MB as usize
 }
 
+/// The name of the options built by this module, recorded with the benchmark results.
+pub const DB_OPTIONS_PROFILE: &str = "tikv-default";
+
+/// The options of `default_dcf_config` and `default_wcf_config` that matter most to scans.
+pub fn db_options_summary() -> BTreeMap<String, String> {
+    let mut summary = BTreeMap::new();
+    let mut set = |k: &str, v: String| summary.insert(k.to_owned(), v);
+    set("block_size", "64KB".to_owned());
+    set(
+        "block_cache",
+        format!("{}MB", memory_mb_for_cf(false, CF_DEFAULT)),
+    );
+    set("bloom_bits_per_key", "10".to_owned());
+    set("cache_index_and_filter_blocks", "true".to_owned());
+    set(
+        "compression_per_level",
+        "no:no:lz4:lz4:lz4:zstd:zstd".to_owned(),
+    );
+    set("write_buffer_size", "128MB".to_owned());
+    summary
+}
+
 /// Like db-config in line 708-797
 /// The config is copy from line 671
 pub fn default_db_options() -> DBOptions {
@@ -228,6 +255,55 @@ pub fn default_wcf_config() -> ColumnFamilyOptions {
     cf_opts
 }
 
+/// Config for lock cf, the locks are few and short lived, so the cf is small and never
+/// compressed.
+pub fn default_lcf_config() -> ColumnFamilyOptions {
+    let mut cf_opts = ColumnFamilyOptions::new();
+
+    let mut block_base_opts = BlockBasedOptions::new();
+    block_base_opts.set_block_size(ReadableSize::kb(16).0 as usize);
+    block_base_opts.set_no_block_cache(false);
+
+    let mut cache_opts = LRUCacheOptions::new();
+    cache_opts.set_capacity(ReadableSize::mb(memory_mb_for_cf(false, CF_LOCK) as u64).0 as usize);
+
+    block_base_opts.set_block_cache(&Cache::new_lru_cache(cache_opts));
+    block_base_opts.set_cache_index_and_filter_blocks(true);
+    block_base_opts.set_pin_l0_filter_and_index_blocks_in_cache(true);
+
+    block_base_opts.set_bloom_filter(10, false);
+    block_base_opts.set_read_amp_bytes_per_bit(0);
+
+    cf_opts.set_block_based_table_factory(&block_base_opts);
+
+    let compression_per_level = vec![DBCompressionType::No; 7];
+    cf_opts.compression_per_level(compression_per_level.as_slice());
+
+    cf_opts.set_write_buffer_size(ReadableSize::mb(32).0);
+    cf_opts.set_max_write_buffer_number(5);
+    cf_opts.set_min_write_buffer_number_to_merge(1);
+    cf_opts.set_max_bytes_for_level_base(ReadableSize::mb(128).0);
+    cf_opts.set_target_file_size_base(ReadableSize::mb(8).0);
+
+    cf_opts.set_level_zero_file_num_compaction_trigger(1);
+    cf_opts.set_level_zero_slowdown_writes_trigger(20);
+    cf_opts.set_level_zero_stop_writes_trigger(36);
+
+    cf_opts.set_max_compaction_bytes(ReadableSize::gb(2).0);
+    cf_opts.compaction_priority(CompactionPriority::ByCompensatedSize);
+    cf_opts.set_level_compaction_dynamic_level_bytes(true);
+    cf_opts.set_num_levels(7);
+    cf_opts.set_max_bytes_for_level_multiplier(10);
+
+    cf_opts.set_compaction_style(DBCompactionStyle::Level);
+    cf_opts.set_disable_auto_compactions(false);
+
+    cf_opts.set_soft_pending_compaction_bytes_limit(ReadableSize::gb(64).0);
+    cf_opts.set_hard_pending_compaction_bytes_limit(ReadableSize::gb(256).0);
+
+    cf_opts
+}
+
 pub fn build_read_opts(lower_bound: Vec<u8>, upper_bound: Vec<u8>) -> ReadOptions {
     let mut opts = ReadOptions::new();
     opts.set_total_order_seek(true);
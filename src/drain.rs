@@ -1,7 +1,14 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
 use rand::prelude::*;
 
-use crate::schema;
-use crate::tikv_code::{CF_DEFAULT, CF_WRITE};
+use crate::errors::{Error, Result};
+use crate::gen_db::get_cf_handle;
+use crate::schema::{self, ColumnType, Lock, LockType, WriteType, SHORT_VALUE_MAX_LEN};
+use crate::tikv_code::{CF_DEFAULT, CF_LOCK, CF_WRITE};
 use crate::Key;
 
 use rocksdb::{rocksdb::Writable, WriteBatch, DB};
@@ -32,51 +39,312 @@ impl ValueType {
     }
 }
 
-pub fn drain_data(db: &mut DB, data_scale: u64, vt: ValueType) {
+impl FromStr for ValueType {
+    type Err = String;
+
+    /// Parses the value length, `64`, `128` or `256`, or `mix`.
+    fn from_str(s: &str) -> std::result::Result<ValueType, String> {
+        match s.to_lowercase().as_str() {
+            "64" | "middle" => Ok(ValueType::MiddleValue),
+            "128" | "long" => Ok(ValueType::LongValue),
+            "256" | "longlong" => Ok(ValueType::LongLongValue),
+            "mix" => Ok(ValueType::MixValue),
+            _ => Err(format!("unknown value type {}", s)),
+        }
+    }
+}
+
+/// How the row keys are stored as user keys.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum KeyMode {
+    /// The row key is taken as an encoded key as is.
+    #[default]
+    Encoded,
+    /// The row key is a raw key, which is memcomparable-encoded by `Key::from_raw`
+    /// like TiKV does. Scanners have to decode it back.
+    Raw,
+}
+
+impl KeyMode {
+    /// Builds the user key of `row_key`, which can also be a scan bound.
+    pub(crate) fn to_key(self, row_key: &[u8]) -> Key {
+        match self {
+            KeyMode::Encoded => Key::from_encoded_slice(row_key),
+            KeyMode::Raw => Key::from_raw(row_key),
+        }
+    }
+}
+
+/// The weights of the write types of the generated records.
+///
+/// Only a put has a row in `CF_DEFAULT`. A rollback is written at its start ts, the other
+/// types at their commit ts.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WriteRatios {
+    pub put: u32,
+    pub delete: u32,
+    pub lock: u32,
+    pub rollback: u32,
+}
+
+impl WriteRatios {
+    pub fn new(put: u32, delete: u32, lock: u32, rollback: u32) -> WriteRatios {
+        assert!(
+            put + delete + lock + rollback > 0,
+            "at least one write type needs a weight"
+        );
+        WriteRatios {
+            put,
+            delete,
+            lock,
+            rollback,
+        }
+    }
+
+    pub fn puts_only() -> WriteRatios {
+        WriteRatios::new(1, 0, 0, 0)
+    }
+
+    pub fn is_puts_only(&self) -> bool {
+        self.delete == 0 && self.lock == 0 && self.rollback == 0
+    }
+
+    fn pick<R: Rng>(&self, rng: &mut R) -> WriteType {
+        let mut n = rng.gen_range(0, self.put + self.delete + self.lock + self.rollback);
+        for &(weight, write_type) in &[
+            (self.put, WriteType::Put),
+            (self.delete, WriteType::Delete),
+            (self.lock, WriteType::Lock),
+        ] {
+            if n < weight {
+                return write_type;
+            }
+            n -= weight;
+        }
+        WriteType::Rollback
+    }
+}
+
+impl Default for WriteRatios {
+    fn default() -> Self {
+        WriteRatios::puts_only()
+    }
+}
+
+impl FromStr for WriteRatios {
+    type Err = String;
+
+    /// Parses `put:delete:lock:rollback`, e.g. `1:3:0:0`.
+    fn from_str(s: &str) -> std::result::Result<WriteRatios, String> {
+        let weights = s
+            .split(':')
+            .map(|w| w.trim().parse::<u32>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| format!("bad write ratios {}: {}", s, e))?;
+        match weights.as_slice() {
+            &[put, delete, lock, rollback] if put + delete + lock + rollback > 0 => {
+                Ok(WriteRatios::new(put, delete, lock, rollback))
+            }
+            _ => Err(format!(
+                "bad write ratios {}, expect put:delete:lock:rollback",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for WriteRatios {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.put, self.delete, self.lock, self.rollback
+        )
+    }
+}
+
+/// Describes the dataset written by `drain_data_with_config`.
+#[derive(Clone, Debug)]
+pub struct DrainConfig {
+    pub data_scale: u64,
+    pub value_type: ValueType,
+    pub key_mode: KeyMode,
+    /// When not empty, `CF_DEFAULT` values are rows of these columns instead of
+    /// `value_type` sized blobs.
+    pub columns: Vec<ColumnType>,
+    /// The versions written each time a user key is drawn, `data_scale` still counts the
+    /// versions. The versions of a key don't overlap, even across the draws of the same key,
+    /// so both column families keep the same order.
+    pub versions_per_key: u64,
+    pub write_ratios: WriteRatios,
+    /// The ratios of the keys in a range of table ids, which replace `write_ratios`, e.g. to
+    /// write a range of mostly deleted versions.
+    pub range_ratios: Vec<(Range<i64>, WriteRatios)>,
+    /// The fraction of the user keys left locked in `CF_LOCK` by a transaction started after
+    /// their last version, which get no more versions. Like after a prewrite, a value too long
+    /// to be kept in the lock is put to `CF_DEFAULT` at the start ts of the lock, which breaks
+    /// the lockstep scans.
+    pub pending_locks: f64,
+}
+
+impl DrainConfig {
+    pub fn new(data_scale: u64, value_type: ValueType) -> DrainConfig {
+        DrainConfig {
+            data_scale,
+            value_type,
+            key_mode: KeyMode::default(),
+            columns: vec![],
+            versions_per_key: 1,
+            write_ratios: WriteRatios::default(),
+            range_ratios: vec![],
+            pending_locks: 0.0,
+        }
+    }
+
+    pub fn key_mode(mut self, key_mode: KeyMode) -> DrainConfig {
+        self.key_mode = key_mode;
+        self
+    }
+
+    /// Fails if a value of one of the columns can't be encoded.
+    pub fn columns(mut self, columns: Vec<ColumnType>) -> Result<DrainConfig> {
+        for ty in &columns {
+            ty.check()?;
+        }
+        self.columns = columns;
+        Ok(self)
+    }
+
+    pub fn versions_per_key(mut self, versions_per_key: u64) -> DrainConfig {
+        self.versions_per_key = versions_per_key.max(1);
+        self
+    }
+
+    pub fn write_ratios(mut self, write_ratios: WriteRatios) -> DrainConfig {
+        self.write_ratios = write_ratios;
+        self
+    }
+
+    /// Writes the keys of the tables in `tables` with `ratios`, the last range containing a
+    /// table wins.
+    pub fn ratios_in(mut self, tables: Range<i64>, ratios: WriteRatios) -> DrainConfig {
+        self.range_ratios.push((tables, ratios));
+        self
+    }
+
+    pub fn pending_locks(mut self, pending_locks: f64) -> DrainConfig {
+        self.pending_locks = pending_locks.clamp(0.0, 1.0);
+        self
+    }
+
+    fn ratios_of(&self, table_id: i64) -> &WriteRatios {
+        self.range_ratios
+            .iter()
+            .rev()
+            .find(|(tables, _)| tables.contains(&table_id))
+            .map_or(&self.write_ratios, |(_, ratios)| ratios)
+    }
+}
+
+pub fn drain_data(db: &mut DB, data_scale: u64, vt: ValueType) -> Result<()> {
+    drain_data_with_config(db, &DrainConfig::new(data_scale, vt))
+}
+
+/// Writes the dataset of `cfg`. The user keys are drawn with replacement, a key drawn again
+/// gets its versions after the ones written before, so no key of `CF_WRITE` or `CF_DEFAULT` is
+/// ever written twice and both column families keep the same order.
+pub fn drain_data_with_config(db: &mut DB, cfg: &DrainConfig) -> Result<()> {
     let mut rng = thread_rng();
+    // The ts the next version of a user key drawn before starts from.
+    let mut next_ts: HashMap<(i64, i64), u64> = HashMap::new();
+    // The user keys with a pending lock, which are never drawn again.
+    let mut locked: HashSet<(i64, i64)> = HashSet::new();
 
+    let write_cf = get_cf_handle(db, CF_WRITE)?;
+    let default_cf = get_cf_handle(db, CF_DEFAULT)?;
+    let lock_cf = get_cf_handle(db, CF_LOCK)?;
     let batch = WriteBatch::new();
 
-    for _ in 0..data_scale {
+    let mut written = 0;
+    while written < cfg.data_scale {
         let table_id = rng.gen_range(0, 30);
         let column_id = rng.gen_range(0, 2000);
-        let start_ts = rng.gen_range(0, 49998);
-        let commit_ts = rng.gen_range(start_ts, 50000);
-
+        if locked.contains(&(table_id, column_id)) {
+            continue;
+        }
         let write_key = schema::encode_row_key(table_id, column_id);
-        let commit_key = Key::from_encoded_slice(&write_key);
-        let commit_key = commit_key.append_ts(commit_ts).into_encoded();
-        let commit_value = schema::generate_write_value();
-
-        let start_key = Key::from_encoded_slice(&write_key);
-        let start_key = start_key.append_ts(start_ts).into_encoded();
-        let start_value = schema::generate_default_value(vt.value());
-
-        batch
-            .put_cf(
-                db.cf_handle(CF_WRITE.as_ref()).unwrap(),
-                commit_key.as_slice(),
-                commit_value.as_slice(),
-            )
-            .unwrap();
-
-        // key: write_key + start_ts
-        // value: data_vec 应该是纯粹数据了
-        batch
-            .put_cf(
-                db.cf_handle(CF_DEFAULT).unwrap(),
-                start_key.as_slice(),
-                start_value.as_slice(),
-            )
-            .unwrap();
-
-        if batch.data_size() > 100 {
-            db.write(&batch).unwrap();
-            batch.clear();
+        let user_key = cfg.key_mode.to_key(&write_key);
+
+        // Every version starts after the previous one is committed.
+        let ratios = cfg.ratios_of(table_id);
+        let versions = cfg.versions_per_key.min(cfg.data_scale - written);
+        let mut ts = match next_ts.get(&(table_id, column_id)) {
+            Some(&ts) => ts,
+            None => rng.gen_range(0, 50000),
+        };
+        for _ in 0..versions {
+            let start_ts = ts + rng.gen_range(0, 5);
+            let commit_ts = rng.gen_range(start_ts, start_ts + 5);
+            ts = commit_ts + 1;
+
+            let write_type = ratios.pick(&mut rng);
+            let commit_key = match write_type {
+                WriteType::Rollback => user_key.clone().append_ts(start_ts),
+                _ => user_key.clone().append_ts(commit_ts),
+            }
+            .into_encoded();
+            let commit_value = schema::generate_write_value(write_type, start_ts);
+            batch
+                .put_cf(write_cf, commit_key.as_slice(), commit_value.as_slice())
+                .map_err(Error::Engine)?;
+
+            if write_type == WriteType::Put {
+                let start_key = user_key.clone().append_ts(start_ts).into_encoded();
+                let start_value = if cfg.columns.is_empty() {
+                    schema::generate_default_value(cfg.value_type.value())
+                } else {
+                    schema::generate_row(&cfg.columns, &mut rng)
+                };
+
+                // key: write_key + start_ts
+                // value: data_vec 应该是纯粹数据了
+                batch
+                    .put_cf(default_cf, start_key.as_slice(), start_value.as_slice())
+                    .map_err(Error::Engine)?;
+            }
+
+            if batch.data_size() > 100 {
+                db.write(&batch).map_err(Error::Engine)?;
+                batch.clear();
+            }
+        }
+        written += versions;
+        next_ts.insert((table_id, column_id), ts);
+
+        // At least a user key is left unlocked for the rest of the versions.
+        let lockable = locked.len() + 1 < 30 * 2000;
+        if cfg.pending_locks > 0.0 && lockable && rng.gen_bool(cfg.pending_locks) {
+            let lock_ts = ts + rng.gen_range(0, 5);
+            let value = schema::generate_default_value(cfg.value_type.value());
+            let short_value = if value.len() <= SHORT_VALUE_MAX_LEN {
+                Some(value)
+            } else {
+                let start_key = user_key.clone().append_ts(lock_ts).into_encoded();
+                batch
+                    .put_cf(default_cf, start_key.as_slice(), value.as_slice())
+                    .map_err(Error::Engine)?;
+                None
+            };
+            locked.insert((table_id, column_id));
+            let lock = Lock::new(LockType::Put, write_key.clone(), lock_ts, 3000, short_value);
+            batch
+                .put_cf(lock_cf, user_key.as_encoded(), &lock.to_bytes())
+                .map_err(Error::Engine)?;
         }
     }
-    db.write(&batch).unwrap();
+    db.write(&batch).map_err(Error::Engine)?;
     println!("done");
+    Ok(())
 }
 
 #[test]
@@ -85,8 +353,8 @@ fn test_drain_data() {
     use rocksdb::SeekKey;
 
     let temp_dir = tempdir::TempDir::new("data").unwrap();
-    let mut db = default_test_db_with_path(temp_dir.path());
-    drain_data(&mut db, 1000, ValueType::LongValue);
+    let mut db = default_test_db_with_path(temp_dir.path()).unwrap();
+    drain_data(&mut db, 1000, ValueType::LongValue).unwrap();
 
     let mut iter = db.iter_cf(db.cf_handle(CF_DEFAULT).unwrap());
     iter.seek(SeekKey::Start);
@@ -98,3 +366,110 @@ fn test_drain_data() {
 
     assert_eq!(cnt, 1000, "cnt should be {:?}", cnt);
 }
+
+#[test]
+fn test_drain_data_key_reuse() {
+    use crate::test_util::TestDb;
+    use crate::{forward_scan, ScannerConfig};
+
+    // More rows than user keys, so that most keys are drawn more than once.
+    let db = TestDb::with_rows(80000);
+    let stats = forward_scan(&mut db.scanner(&ScannerConfig::default()), u64::MAX).unwrap();
+    assert_eq!(stats.rows, 80000);
+    assert_eq!(stats.default_keys, 80000);
+}
+
+#[test]
+fn test_write_ratios() {
+    use crate::test_util::TestDb;
+    use rocksdb::SeekKey;
+
+    assert_eq!("1:3:0:0".parse(), Ok(WriteRatios::new(1, 3, 0, 0)));
+    assert_eq!(WriteRatios::new(2, 0, 1, 1).to_string(), "2:0:1:1");
+    assert!("1:3".parse::<WriteRatios>().is_err());
+    assert!("0:0:0:0".parse::<WriteRatios>().is_err());
+    let mut rng = thread_rng();
+    let lock = WriteRatios::new(0, 0, 1, 0);
+    assert!((0..100).all(|_| lock.pick(&mut rng) == WriteType::Lock));
+
+    // Every key of tables 0 to 9 is deleted, the other keys only have puts.
+    let cfg = DrainConfig::new(1000, ValueType::MiddleValue)
+        .versions_per_key(4)
+        .ratios_in(0..10, WriteRatios::new(0, 1, 0, 0));
+    assert_eq!(cfg.ratios_of(9), &WriteRatios::new(0, 1, 0, 0));
+    assert_eq!(cfg.ratios_of(10), &WriteRatios::puts_only());
+    let test_db = TestDb::new(&cfg);
+    let db = &test_db.db;
+
+    let count = |cf: &str, table_id: i64| {
+        let mut iter = db.iter_cf(db.cf_handle(cf).unwrap());
+        iter.seek(SeekKey::Start);
+        let mut cnt = 0;
+        while iter.valid() {
+            let key = Key::truncate_ts_for(iter.key()).unwrap();
+            if key < schema::encode_row_key(table_id, 0).as_slice() {
+                cnt += 1;
+            }
+            iter.next();
+        }
+        cnt
+    };
+    assert_eq!(count(CF_DEFAULT, 10), 0);
+    assert!(count(CF_DEFAULT, 30) > 0);
+    assert!(count(CF_WRITE, 10) > 0);
+}
+
+#[test]
+fn test_pending_locks() {
+    use crate::schema::LockRef;
+    use crate::test_util::TestDb;
+    use rocksdb::SeekKey;
+
+    let cfg = DrainConfig::new(2000, ValueType::LongValue)
+        .versions_per_key(2)
+        .pending_locks(0.2);
+    let test_db = TestDb::new(&cfg);
+    let db = &test_db.db;
+
+    // Every lock is newer than the versions of its key and has its value in CF_DEFAULT.
+    let mut iter = db.iter_cf(db.cf_handle(CF_LOCK).unwrap());
+    let mut write_iter = db.iter_cf(db.cf_handle(CF_WRITE).unwrap());
+    iter.seek(SeekKey::Start);
+    let mut locks = 0;
+    while iter.valid() {
+        let lock = LockRef::parse(iter.value()).unwrap();
+        assert!(lock.short_value.is_none());
+        let start_key = Key::from_encoded_slice(iter.key()).append_ts(lock.ts);
+        let value = db.get_cf(db.cf_handle(CF_DEFAULT).unwrap(), start_key.as_encoded());
+        assert!(value.unwrap().is_some());
+
+        let newest = Key::from_encoded_slice(iter.key()).append_ts(u64::MAX);
+        write_iter.seek(SeekKey::Key(newest.as_encoded()));
+        assert!(Key::is_user_key_eq(write_iter.key(), iter.key()));
+        assert!(Key::decode_ts_from(write_iter.key()).unwrap() < lock.ts);
+        locks += 1;
+        iter.next();
+    }
+    assert!(locks > 0);
+
+    let mut iter = db.iter_cf(db.cf_handle(CF_DEFAULT).unwrap());
+    iter.seek(SeekKey::Start);
+    let mut cnt = 0;
+    while iter.valid() {
+        iter.next();
+        cnt += 1;
+    }
+    assert_eq!(cnt, 2000 + locks);
+}
+
+#[test]
+fn test_columns() {
+    let cfg = DrainConfig::new(10, ValueType::MiddleValue);
+    let decimal = |prec, frac| vec![ColumnType::Int, ColumnType::Decimal { prec, frac }];
+    assert!(cfg.clone().columns(decimal(38, 30)).is_ok());
+    assert!(cfg.clone().columns(decimal(39, 0)).is_err());
+    assert!(cfg.clone().columns(decimal(255, 0)).is_err());
+    assert!(cfg.clone().columns(decimal(10, 11)).is_err());
+    assert!(cfg.clone().columns(decimal(38, 31)).is_err());
+    assert!(cfg.columns(decimal(0, 0)).is_err());
+}
@@ -0,0 +1,68 @@
+use crate::tikv_code;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        ColumnFamilyNotFound(cf: String) {
+            description("column family not found")
+            display("column family {} not found", cf)
+        }
+        /// Error reported by `DBIterator::status` once the iterator becomes invalid.
+        Iterator(msg: String) {
+            description("iterator error")
+            display("iterator error: {}", msg)
+        }
+        /// Error returned by RocksDB when opening or writing the db.
+        Engine(msg: String) {
+            description("engine error")
+            display("engine error: {}", msg)
+        }
+        Codec(err: tikv_code::Error) {
+            from()
+            cause(err)
+            description("codec error")
+            display("codec error: {:?}", err)
+        }
+        /// The keys or rows of `CF_WRITE` and `CF_DEFAULT` don't follow the layout
+        /// written by `drain_data`.
+        KeyLayout(msg: String) {
+            description("key layout violated")
+            display("key layout violated: {}", msg)
+        }
+        /// A row value can't be decoded with the given columns.
+        Row(msg: String) {
+            description("corrupted row")
+            display("corrupted row: {}", msg)
+        }
+        /// The scan can't run on the scanner, e.g. it reads `CF_DEFAULT` but the scanner
+        /// is key only.
+        Unsupported(msg: String) {
+            description("unsupported scan")
+            display("unsupported scan: {}", msg)
+        }
+        /// A lock in `CF_LOCK` conflicts with the read: the encoded user key, the primary
+        /// and the start ts of the transaction holding the lock.
+        KeyIsLocked(key: Vec<u8>, primary: Vec<u8>, ts: u64) {
+            description("key is locked")
+            display("key {} is locked by primary {} at ts {}", hex::encode(key), hex::encode(primary), ts)
+        }
+        /// The db can't be reopened while a scanner still holds it.
+        DbInUse {
+            description("db in use")
+            display("the db is still referenced, it can't be reopened")
+        }
+        Io(err: std::io::Error) {
+            from()
+            cause(err)
+            description("io error")
+            display("io error: {}", err)
+        }
+        /// A result file can't be written or parsed.
+        Results(msg: String) {
+            description("bad result file")
+            display("bad result file: {}", msg)
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,31 +1,90 @@
-use rocksdb::{self, DB};
+use rocksdb::{self, CFHandle, DB};
 
 use std::path::Path;
 
-use crate::tikv_code::constexpr::{CF_DEFAULT, CF_WRITE};
+use crate::errors::{Error, Result};
+use crate::tikv_code::constexpr::{CF_DEFAULT, CF_LOCK, CF_WRITE};
 
 use crate::db_opts::*;
 
-pub fn default_test_db_with_path<P: AsRef<Path>>(path: P) -> DB {
+pub fn default_test_db_with_path<P: AsRef<Path>>(path: P) -> Result<DB> {
     let opt = default_db_options();
 
-    let mut db_res = DB::open(opt.clone(), path.as_ref().to_str().unwrap()).unwrap();
+    let mut db_res =
+        DB::open(opt.clone(), path.as_ref().to_str().unwrap()).map_err(Error::Engine)?;
     if db_res.cf_handle(CF_DEFAULT).is_none() {
         let default_cf_cfg = default_dcf_config();
         let cf_opts = default_cf_cfg;
         let ccd = rocksdb::rocksdb_options::ColumnFamilyDescriptor::new(CF_DEFAULT, cf_opts);
-        db_res.create_cf(ccd).unwrap();
+        db_res.create_cf(ccd).map_err(Error::Engine)?;
     }
     if db_res.cf_handle(CF_WRITE).is_none() {
         let write_cf_cfg = default_wcf_config();
         let cf_opts = write_cf_cfg;
         let ccd = rocksdb::rocksdb_options::ColumnFamilyDescriptor::new(CF_WRITE, cf_opts);
-        db_res.create_cf(ccd).unwrap();
+        db_res.create_cf(ccd).map_err(Error::Engine)?;
     }
+    if db_res.cf_handle(CF_LOCK).is_none() {
+        create_lock_cf(&mut db_res)?;
+    }
+
+    Ok(db_res)
+}
 
-    db_res
+fn create_lock_cf(db: &mut DB) -> Result<()> {
+    let ccd = rocksdb::rocksdb_options::ColumnFamilyDescriptor::new(CF_LOCK, default_lcf_config());
+    db.create_cf(ccd).map_err(Error::Engine)?;
+    Ok(())
+}
+
+/// Opens the db created by `default_test_db_with_path`, which fails if it doesn't exist.
+/// The dbs created before `CF_LOCK` is added get an empty one.
+pub fn open_test_db_with_path<P: AsRef<Path>>(path: P) -> Result<DB> {
+    let mut opt = default_db_options();
+    opt.create_if_missing(false);
+    let path = path.as_ref().to_str().unwrap();
+    let has_lock_cf = DB::list_column_families(&opt, path)
+        .map_err(Error::Engine)?
+        .iter()
+        .any(|cf| cf == CF_LOCK);
+    let mut cfs = vec![
+        (CF_DEFAULT, default_dcf_config()),
+        (CF_WRITE, default_wcf_config()),
+    ];
+    if has_lock_cf {
+        cfs.push((CF_LOCK, default_lcf_config()));
+    }
+    let mut db = DB::open_cf(opt, path, cfs).map_err(Error::Engine)?;
+    if !has_lock_cf {
+        create_lock_cf(&mut db)?;
+    }
+    Ok(db)
 }
 
-pub fn default_test_db() -> DB {
+pub fn default_test_db() -> Result<DB> {
     default_test_db_with_path("data")
 }
+
+/// Gets the handle of column family `cf`, which must be created before.
+pub fn get_cf_handle<'a>(db: &'a DB, cf: &str) -> Result<&'a CFHandle> {
+    db.cf_handle(cf)
+        .ok_or_else(|| Error::ColumnFamilyNotFound(cf.to_owned()))
+}
+
+#[test]
+fn test_open_test_db() {
+    use rocksdb::Writable;
+
+    let temp_dir = tempdir::TempDir::new("data").unwrap();
+    assert!(open_test_db_with_path(temp_dir.path()).is_err());
+
+    let db = default_test_db_with_path(temp_dir.path()).unwrap();
+    let write_cf = get_cf_handle(&db, CF_WRITE).unwrap();
+    db.put_cf(write_cf, b"k", b"v").unwrap();
+    drop(db);
+
+    let db = open_test_db_with_path(temp_dir.path()).unwrap();
+    let write_cf = get_cf_handle(&db, CF_WRITE).unwrap();
+    assert_eq!(&*db.get_cf(write_cf, b"k").unwrap().unwrap(), b"v");
+    assert!(get_cf_handle(&db, CF_LOCK).is_ok());
+}
@@ -0,0 +1,211 @@
+//! Splits a measured scan into a setup, a scan and a teardown phase, only the scan phase is
+//! measured.
+//!
+//! - Setup puts the caches into the cache state, creates the scanner, seeks it if the seek is
+//!   excluded, and allocates a write cache of its own for the batch scans.
+//! - Scan seeks the scanner if the seek is included, and then scans.
+//! - Teardown drops the scanner, which releases its snapshot and iterators, and the write cache.
+//!
+//! Every scan gets a fresh write cache, so the buffer grown by a scan never speeds up the
+//! next one. With criterion, `iter_batched_ref(|| harness.setup(mode), |input| harness.scan(
+//! input, ..), size)` runs the teardown outside the measurement by dropping the input.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rocksdb::DB;
+
+use crate::cache::{CacheState, CachedDb};
+use crate::errors::Result;
+use crate::{Scanner, ScannerConfig};
+
+/// The capacity of the write cache of every scan. A batch scan clears the cache after every
+/// batch, so it only holds a batch of rows.
+pub const WRITE_CACHE_CAPACITY: usize = 1024 * 1024;
+
+/// Whether the seeks of both iterators to the lower bound are measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SeekMode {
+    /// The iterators are positioned in the scan phase.
+    Included,
+    /// The iterators are positioned in the setup phase.
+    #[default]
+    Excluded,
+}
+
+impl SeekMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            SeekMode::Included => "seek-included",
+            SeekMode::Excluded => "seek-excluded",
+        }
+    }
+}
+
+impl FromStr for SeekMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<SeekMode, String> {
+        match s.to_lowercase().trim_start_matches("seek-") {
+            "included" => Ok(SeekMode::Included),
+            "excluded" => Ok(SeekMode::Excluded),
+            _ => Err(format!("unknown seek mode {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SeekMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What the setup phase builds for a single scan.
+pub struct ScanInput {
+    scanner: Scanner,
+    write_cache: Vec<u8>,
+    seek_mode: SeekMode,
+}
+
+impl ScanInput {
+    pub fn seek_mode(&self) -> SeekMode {
+        self.seek_mode
+    }
+}
+
+/// Scans a db with a config through the three phases.
+pub struct ScanHarness {
+    db: RefCell<CachedDb>,
+    cfg: ScannerConfig,
+}
+
+impl ScanHarness {
+    pub fn new(db: CachedDb, cfg: ScannerConfig) -> ScanHarness {
+        ScanHarness {
+            db: RefCell::new(db),
+            cfg,
+        }
+    }
+
+    pub fn cfg(&self) -> &ScannerConfig {
+        &self.cfg
+    }
+
+    pub fn cache_state(&self) -> CacheState {
+        self.db.borrow().state()
+    }
+
+    pub fn db(&self) -> Arc<DB> {
+        self.db.borrow().db().clone()
+    }
+
+    /// The setup phase. If the cache state reopens the db, every input set up before must
+    /// have been torn down.
+    pub fn setup(&self, seek_mode: SeekMode) -> Result<ScanInput> {
+        self.setup_with(seek_mode, &self.cfg)
+    }
+
+    /// Like `setup`, but the scanner is created with `cfg`, e.g. the config a strategy wants
+    /// by `ScanStrategy::scanner_config`.
+    pub fn setup_with(&self, seek_mode: SeekMode, cfg: &ScannerConfig) -> Result<ScanInput> {
+        self.db.borrow_mut().prepare()?;
+        let db = self.db();
+        let scanner = match seek_mode {
+            SeekMode::Included => Scanner::unpositioned(db, cfg.clone())?,
+            SeekMode::Excluded => Scanner::new(db, cfg.clone())?,
+        };
+        // Touch every page, so that the scan doesn't take the page faults. Zeroed memory may
+        // be mapped lazily.
+        let mut write_cache = vec![1; WRITE_CACHE_CAPACITY];
+        write_cache.clear();
+        Ok(ScanInput {
+            scanner,
+            write_cache,
+            seek_mode,
+        })
+    }
+
+    /// The scan phase, `scan` gets the scanner and the write cache of the input.
+    pub fn scan<R>(
+        &self,
+        input: &mut ScanInput,
+        scan: impl FnOnce(&mut Scanner, &mut Vec<u8>) -> R,
+    ) -> R {
+        if input.seek_mode == SeekMode::Included {
+            input.scanner.seek_to_lower_bound();
+        }
+        scan(&mut input.scanner, &mut input.write_cache)
+    }
+
+    /// The teardown phase.
+    pub fn teardown(&self, input: ScanInput) {
+        drop(input);
+    }
+
+    /// Runs the three phases and returns what `scan` returns with the time of the scan phase.
+    pub fn measure<R>(
+        &self,
+        seek_mode: SeekMode,
+        scan: impl FnOnce(&mut Scanner, &mut Vec<u8>) -> R,
+    ) -> Result<(R, Duration)> {
+        self.measure_with(seek_mode, &self.cfg, scan)
+    }
+
+    /// Like `measure`, but the scanner is created with `cfg`.
+    pub fn measure_with<R>(
+        &self,
+        seek_mode: SeekMode,
+        cfg: &ScannerConfig,
+        scan: impl FnOnce(&mut Scanner, &mut Vec<u8>) -> R,
+    ) -> Result<(R, Duration)> {
+        let mut input = self.setup_with(seek_mode, cfg)?;
+        let start = Instant::now();
+        let res = self.scan(&mut input, scan);
+        let elapsed = start.elapsed();
+        self.teardown(input);
+        Ok((res, elapsed))
+    }
+}
+
+#[test]
+fn test_scan_harness() {
+    use crate::drain::{drain_data, ValueType};
+    use crate::gen_db::default_test_db_with_path;
+    use crate::{forward_batch_scan, forward_scan};
+
+    let temp_dir = tempdir::TempDir::new("data").unwrap();
+    let mut db = default_test_db_with_path(temp_dir.path()).unwrap();
+    drain_data(&mut db, 100, ValueType::MiddleValue).unwrap();
+    let db = CachedDb::new(db, temp_dir.path(), CacheState::Cold).unwrap();
+    let harness = ScanHarness::new(db, ScannerConfig::default());
+
+    for &mode in &[SeekMode::Included, SeekMode::Excluded] {
+        let (stats, _) = harness
+            .measure(mode, |scanner, _| forward_scan(scanner, u64::MAX).unwrap())
+            .unwrap();
+        assert_eq!(stats.rows, 100);
+
+        let mut input = harness.setup(mode).unwrap();
+        assert_eq!(input.seek_mode(), mode);
+        assert!(input.write_cache.capacity() >= WRITE_CACHE_CAPACITY);
+        let batch = harness.scan(&mut input, |scanner, write_cache| {
+            forward_batch_scan(scanner, 16, u64::MAX, write_cache).unwrap()
+        });
+        assert_eq!(batch, stats);
+        assert!(input.write_cache.is_empty());
+        harness.teardown(input);
+    }
+
+    // Nothing is scanned before seeking.
+    let mut scanner = Scanner::unpositioned(harness.db(), ScannerConfig::default()).unwrap();
+    assert_eq!(forward_scan(&mut scanner, u64::MAX).unwrap().rows, 0);
+    scanner.seek_to_lower_bound();
+    assert_eq!(forward_scan(&mut scanner, u64::MAX).unwrap().rows, 100);
+    drop(scanner);
+
+    assert_eq!("included".parse(), Ok(SeekMode::Included));
+    assert_eq!("seek-excluded".parse(), Ok(SeekMode::Excluded));
+}
@@ -16,6 +16,8 @@ extern crate sys_info;
 
 extern crate hex;
 
+extern crate libc;
+
 extern crate rand;
 
 #[allow(unused)]
@@ -24,19 +26,41 @@ extern crate slog;
 
 extern crate test;
 
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
+
 mod db_opts;
-mod tikv_code;
+mod errors;
+pub mod tikv_code;
 
+pub mod access;
+pub mod cache;
 pub mod drain;
 pub mod gen_db;
+pub mod harness;
+pub mod lock;
+pub mod mvcc;
+pub mod prefetch;
+pub mod report;
+pub mod results;
+pub mod row;
 #[allow(unused)]
 mod schema;
+pub mod strategy;
+#[cfg(test)]
+mod test_util;
 
+use lock::LockChecker;
+use tikv_code::bytes;
 use tikv_code::key::Key;
 
 pub use db_opts::build_read_opts;
 pub use drain::*;
-pub use gen_db::default_test_db_with_path;
+pub use errors::{Error, Result};
+pub use gen_db::{default_test_db_with_path, get_cf_handle, open_test_db_with_path};
+pub use lock::{KeyLock, LockCheck};
+pub use schema::{encode_row_key, ColumnType, Lock, LockType};
 pub use tikv_code::constexpr::*;
 
 #[allow(unused)]
@@ -44,6 +68,7 @@ use rocksdb::rocksdb::{DBIterator, Snapshot, Writable};
 #[allow(unused)]
 use rocksdb::{ReadOptions, SeekKey, WriteBatch, WriteOptions, DB};
 
+use std::cmp;
 use std::sync::Arc;
 
 #[allow(unused)]
@@ -67,6 +92,18 @@ pub const DIST_QT3_KEY: [u8; 19] = [
 pub struct ScannerConfig {
     pub lower_bound: Vec<u8>,
     pub upper_bound: Vec<u8>,
+    /// Only scan `CF_WRITE`, `CF_DEFAULT` is never opened nor touched.
+    pub key_only: bool,
+    /// Must match the `KeyMode` the data is written with. The bounds are row keys
+    /// and get encoded in the same way.
+    pub key_mode: KeyMode,
+    /// The readahead size of both iterators in bytes, 0 keeps the readahead of RocksDB, which
+    /// grows by itself on sequential reads.
+    pub readahead_size: usize,
+    /// The ts the MVCC scans read at, the versions committed after it are invisible.
+    pub read_ts: u64,
+    /// Whether `CF_LOCK` is checked for the locks conflicting with a read at `read_ts`.
+    pub lock_check: LockCheck,
 }
 
 impl ScannerConfig {
@@ -84,8 +121,38 @@ impl ScannerConfig {
         ScannerConfig {
             lower_bound,
             upper_bound,
+            key_only: false,
+            key_mode: KeyMode::default(),
+            readahead_size: 0,
+            read_ts: u64::MAX,
+            lock_check: LockCheck::Off,
         }
     }
+
+    pub fn key_only(mut self, key_only: bool) -> ScannerConfig {
+        self.key_only = key_only;
+        self
+    }
+
+    pub fn key_mode(mut self, key_mode: KeyMode) -> ScannerConfig {
+        self.key_mode = key_mode;
+        self
+    }
+
+    pub fn readahead_size(mut self, readahead_size: usize) -> ScannerConfig {
+        self.readahead_size = readahead_size;
+        self
+    }
+
+    pub fn read_ts(mut self, read_ts: u64) -> ScannerConfig {
+        self.read_ts = read_ts;
+        self
+    }
+
+    pub fn lock_check(mut self, lock_check: LockCheck) -> ScannerConfig {
+        self.lock_check = lock_check;
+        self
+    }
 }
 
 impl Default for ScannerConfig {
@@ -98,96 +165,408 @@ impl Default for ScannerConfig {
 pub struct Scanner {
     pub snap: Snapshot<Arc<DB>>,
     pub iter_write: DBIterator<Arc<DB>>,
-    pub iter_default: DBIterator<Arc<DB>>,
+    /// `None` if the scanner is key only.
+    pub iter_default: Option<DBIterator<Arc<DB>>>,
 
     /// ScannerConfig here holds the lower and upper.
     /// In this poc, it doesn't need to parse the key and fetch the data
     /// from it. It just compare whether the values are equal.
     #[allow(unused)]
     pub cfg: ScannerConfig,
+    /// `None` if the lock check is off.
+    pub(crate) lock_checker: Option<LockChecker>,
+    /// The encoded lower bound the iterators seek to.
+    seek_key: Vec<u8>,
 }
 
 impl Scanner {
-    pub fn new(db_ref: Arc<DB>, cfg: ScannerConfig) -> Scanner {
+    /// Creates a scanner positioned at the lower bound.
+    pub fn new(db_ref: Arc<DB>, cfg: ScannerConfig) -> Result<Scanner> {
+        let mut scanner = Scanner::unpositioned(db_ref, cfg)?;
+        scanner.seek_to_lower_bound();
+        Ok(scanner)
+    }
+
+    /// Creates a scanner whose iterators are not positioned yet, it yields nothing until
+    /// `seek_to_lower_bound` is called.
+    pub fn unpositioned(db_ref: Arc<DB>, cfg: ScannerConfig) -> Result<Scanner> {
         let cloned_ref = db_ref.clone();
 
         let snap = Snapshot::new(cloned_ref.clone());
-        let mut read_write_opts = build_read_opts(cfg.lower_bound.clone(), cfg.upper_bound.clone());
+        let lower_bound = cfg.key_mode.to_key(&cfg.lower_bound).into_encoded();
+        let upper_bound = cfg.key_mode.to_key(&cfg.upper_bound).into_encoded();
+        let mut read_write_opts = build_read_opts(lower_bound.clone(), upper_bound.clone());
         read_write_opts.fill_cache(true);
+        if cfg.readahead_size > 0 {
+            read_write_opts.set_readahead_size(cfg.readahead_size);
+        }
 
-        let mut iter_write = DBIterator::new_cf(
+        let iter_write = DBIterator::new_cf(
             cloned_ref.clone(),
-            db_ref.cf_handle(CF_WRITE).unwrap(),
+            get_cf_handle(&db_ref, CF_WRITE)?,
             read_write_opts,
         );
-        iter_write.seek(SeekKey::Key(&cfg.lower_bound));
 
-        let mut read_default_opts = ReadOptions::new();
-        read_default_opts.fill_cache(true);
+        let iter_default = if cfg.key_only {
+            None
+        } else {
+            // Keys in both column families share the same user key layout, so the
+            // bounds of the write iterator also bound the default one.
+            let mut read_default_opts = build_read_opts(lower_bound.clone(), upper_bound.clone());
+            read_default_opts.fill_cache(true);
+            if cfg.readahead_size > 0 {
+                read_default_opts.set_readahead_size(cfg.readahead_size);
+            }
+
+            Some(DBIterator::new_cf(
+                cloned_ref.clone(),
+                get_cf_handle(&db_ref, CF_DEFAULT)?,
+                read_default_opts,
+            ))
+        };
 
-        let mut iter_default = DBIterator::new_cf(
-            cloned_ref.clone(),
-            db_ref.cf_handle(CF_DEFAULT).unwrap(),
-            read_default_opts,
-        );
-        iter_default.seek(SeekKey::Key(&cfg.lower_bound));
+        let lock_checker = if cfg.lock_check == LockCheck::Off {
+            None
+        } else {
+            // The locks are keyed by the user keys, which the bounds are too.
+            let read_lock_opts = build_read_opts(lower_bound.clone(), upper_bound);
+            let iter_lock =
+                DBIterator::new_cf(cloned_ref, get_cf_handle(&db_ref, CF_LOCK)?, read_lock_opts);
+            Some(LockChecker::new(iter_lock, cfg.lock_check, cfg.read_ts))
+        };
 
-        Scanner {
+        Ok(Scanner {
             snap,
             iter_write,
             iter_default,
 
             cfg,
+            lock_checker,
+            seek_key: lower_bound,
+        })
+    }
+
+    /// Seeks the iterators to the lower bound, so the scanner can be scanned again.
+    pub fn seek_to_lower_bound(&mut self) {
+        self.iter_write.seek(SeekKey::Key(&self.seek_key));
+        if let Some(ref mut iter) = self.iter_default {
+            iter.seek(SeekKey::Key(&self.seek_key));
+        }
+        if let Some(ref mut checker) = self.lock_checker {
+            checker.seek(&self.seek_key);
         }
     }
+
+    /// The conflicting locks found since the last seek if the lock check is
+    /// `LockCheck::Report`.
+    pub fn locks(&self) -> &[KeyLock] {
+        self.lock_checker.as_ref().map_or(&[], |c| &c.locks)
+    }
+}
+
+/// Returns the error of `iter` if it is invalid because of an error rather than
+/// running out of the range.
+pub(crate) fn check_iter_status(iter: &DBIterator<Arc<DB>>) -> Result<()> {
+    if iter.valid() {
+        return Ok(());
+    }
+    iter.status().map_err(Error::Iterator)
+}
+
+/// Checks that `CF_DEFAULT` runs out together with `CF_WRITE`.
+pub(crate) fn check_default_exhausted(scanner: &Scanner) -> Result<()> {
+    check_iter_status(&scanner.iter_write)?;
+    match scanner.iter_default {
+        Some(ref iter) if iter.valid() => Err(more_default_rows()),
+        Some(ref iter) => check_iter_status(iter),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn default_row_missing(iter_default: &DBIterator<Arc<DB>>) -> Error {
+    match check_iter_status(iter_default) {
+        Err(e) => e,
+        Ok(()) => less_default_rows(),
+    }
+}
+
+/// `CF_DEFAULT` still has rows once `CF_WRITE` runs out.
+pub(crate) fn more_default_rows() -> Error {
+    Error::KeyLayout("CF_DEFAULT has more rows than CF_WRITE".to_owned())
+}
+
+/// `CF_DEFAULT` runs out before `CF_WRITE`.
+pub(crate) fn less_default_rows() -> Error {
+    Error::KeyLayout("CF_DEFAULT has less rows than CF_WRITE".to_owned())
+}
+
+/// A row yielded by the forward scans.
+pub struct ScanRow<'a> {
+    pub write_key: &'a [u8],
+    /// The decoded user key if the keys are written in `KeyMode::Raw`.
+    pub raw_key: Option<&'a [u8]>,
+    pub write_value: &'a [u8],
+    /// `None` if the scanner is key only.
+    pub default_value: Option<&'a [u8]>,
+}
+
+/// What a scan has read, the bytes are the lengths of the keys and values read from
+/// each column family.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ScanStats {
+    pub rows: u64,
+    pub write_keys: u64,
+    pub write_bytes: u64,
+    pub default_keys: u64,
+    pub default_bytes: u64,
+    pub lock_keys: u64,
+    pub lock_bytes: u64,
+    /// The locks conflicting with the read.
+    pub locked: u64,
+}
+
+impl ScanStats {
+    /// The bytes read from every column family.
+    pub fn bytes(&self) -> u64 {
+        self.write_bytes + self.default_bytes + self.lock_bytes
+    }
+
+    pub(crate) fn add_write(&mut self, key: &[u8], value: &[u8]) {
+        self.write_keys += 1;
+        self.write_bytes += (key.len() + value.len()) as u64;
+    }
+
+    pub(crate) fn add_default(&mut self, key: &[u8], value: &[u8]) {
+        self.default_keys += 1;
+        self.default_bytes += (key.len() + value.len()) as u64;
+    }
+
+    pub(crate) fn add_lock(&mut self, key: &[u8], value: &[u8]) {
+        self.lock_keys += 1;
+        self.lock_bytes += (key.len() + value.len()) as u64;
+    }
 }
 
 use test::black_box;
 
-pub fn forward_scan(mut scanner: Scanner, loop_cnt: u64) {
-    for _ in 0..loop_cnt {
+/// Scans at most `limit` rows and returns what is actually scanned.
+pub fn forward_scan(scanner: &mut Scanner, limit: u64) -> Result<ScanStats> {
+    forward_scan_with(scanner, limit, |row| {
+        black_box((
+            row.write_key,
+            row.raw_key,
+            row.write_value,
+            row.default_value,
+        ));
+        true
+    })
+}
+
+/// Like `forward_scan`, but feeds every row to `f`. The scan stops early once
+/// `f` returns false, the row which stops the scan is still counted.
+pub fn forward_scan_with<F>(scanner: &mut Scanner, limit: u64, mut f: F) -> Result<ScanStats>
+where
+    F: FnMut(ScanRow<'_>) -> bool,
+{
+    let mut stats = ScanStats::default();
+    let mut raw_key = vec![];
+    while stats.rows < limit && scanner.iter_write.valid() {
+        if let Some(ref mut checker) = scanner.lock_checker {
+            checker.check_until(scanner.iter_write.key(), &mut stats)?;
+        }
+        let default_value = match scanner.iter_default {
+            Some(ref iter) if iter.valid() => {
+                stats.add_default(iter.key(), iter.value());
+                Some(iter.value())
+            }
+            Some(ref iter) => return Err(default_row_missing(iter)),
+            None => None,
+        };
+        let raw_key = match scanner.cfg.key_mode {
+            KeyMode::Raw => {
+                raw_key.clear();
+                bytes::decode_bytes_into(&mut scanner.iter_write.key(), &mut raw_key)?;
+                Some(raw_key.as_slice())
+            }
+            KeyMode::Encoded => None,
+        };
+        let row = ScanRow {
+            write_key: scanner.iter_write.key(),
+            raw_key,
+            write_value: scanner.iter_write.value(),
+            default_value,
+        };
+        stats.add_write(row.write_key, row.write_value);
+        stats.rows += 1;
+        if !f(row) {
+            return Ok(stats);
+        }
+
         // fetch next for "write" field
         scanner.iter_write.next();
-        black_box((scanner.iter_write.key(), scanner.iter_write.value()));
-
         // fetch next for "default" field
-        scanner.iter_default.next();
-        black_box((scanner.iter_default.key(), scanner.iter_default.value()));
+        if let Some(ref mut iter) = scanner.iter_default {
+            iter.next();
+        }
+    }
+
+    if stats.rows < limit {
+        check_default_exhausted(scanner)?;
+        check_rest_locks(scanner, &mut stats)?;
+    }
+    Ok(stats)
+}
+
+/// Checks the locks left in the range once a scan has reached its end.
+pub(crate) fn check_rest_locks(scanner: &mut Scanner, stats: &mut ScanStats) -> Result<()> {
+    match scanner.lock_checker {
+        Some(ref mut checker) => checker.check_rest(stats),
+        None => Ok(()),
     }
 }
 
+/// Scans at most `limit` rows, `batch_size` rows of `CF_WRITE` then the same rows
+/// of `CF_DEFAULT` at a time, at least one. Returns what is actually scanned.
 pub fn forward_batch_scan(
-    mut scanner: Scanner,
+    scanner: &mut Scanner,
     batch_size: u64,
-    loop_cnt: u64,
-    mut write_cache: &mut Vec<u8>,
-) {
-    for _ in 0..loop_cnt / batch_size {
-        for _ in 0..batch_size {
-            scanner.iter_write.next();
-            write_cache.extend_from_slice(scanner.iter_write.key());
+    limit: u64,
+    write_cache: &mut Vec<u8>,
+) -> Result<ScanStats> {
+    let batch_size = batch_size.max(1);
+    let mut stats = ScanStats::default();
+    while stats.rows < limit {
+        let expected = cmp::min(batch_size, limit - stats.rows);
+
+        let mut fetched = 0;
+        while fetched < expected && scanner.iter_write.valid() {
+            if let Some(ref mut checker) = scanner.lock_checker {
+                checker.check_until(scanner.iter_write.key(), &mut stats)?;
+            }
+            match scanner.cfg.key_mode {
+                KeyMode::Raw => {
+                    bytes::decode_bytes_into(&mut scanner.iter_write.key(), write_cache)?
+                }
+                KeyMode::Encoded => write_cache.extend_from_slice(scanner.iter_write.key()),
+            }
             write_cache.extend_from_slice(scanner.iter_write.value());
+            stats.add_write(scanner.iter_write.key(), scanner.iter_write.value());
+            scanner.iter_write.next();
+            fetched += 1;
         }
 
-        for _ in 0..batch_size {
-            scanner.iter_default.next();
-            black_box(scanner.iter_default.key());
-            write_cache.extend_from_slice(scanner.iter_default.value());
+        if let Some(ref mut iter_default) = scanner.iter_default {
+            for _ in 0..fetched {
+                if !iter_default.valid() {
+                    return Err(default_row_missing(iter_default));
+                }
+                black_box(iter_default.key());
+                write_cache.extend_from_slice(iter_default.value());
+                stats.add_default(iter_default.key(), iter_default.value());
+                iter_default.next();
+            }
         }
         write_cache.clear();
+
+        stats.rows += fetched;
+        if fetched < expected {
+            check_default_exhausted(scanner)?;
+            check_rest_locks(scanner, &mut stats)?;
+            break;
+        }
     }
+    Ok(stats)
+}
 
-    let sz = loop_cnt % batch_size;
-    for _ in 0..sz {
-        scanner.iter_write.next();
-        write_cache.extend_from_slice(scanner.iter_write.key());
-        write_cache.extend_from_slice(scanner.iter_write.value());
+#[test]
+fn test_forward_scan_limit() {
+    let db = test_util::TestDb::with_rows(1000);
+    let cfg = ScannerConfig::default();
+    let scanner = |cfg: &ScannerConfig| db.scanner(cfg);
+
+    let stats = forward_scan(&mut scanner(&cfg), std::u64::MAX).unwrap();
+    let total = stats.rows;
+    assert_eq!(total, 1000);
+    assert_eq!(stats.write_keys, total);
+    assert_eq!(stats.default_keys, total);
+    // Every default value is 64 bytes and comes with a key.
+    assert!(stats.default_bytes > total * 64, "{:?}", stats);
+    assert_eq!(stats.bytes(), stats.write_bytes + stats.default_bytes);
+    assert_eq!(forward_scan(&mut scanner(&cfg), 10).unwrap().rows, 10);
+
+    let key_only = cfg.clone().key_only(true);
+    let key_only_stats = forward_scan(&mut scanner(&key_only), std::u64::MAX).unwrap();
+    assert_eq!(key_only_stats.rows, total);
+    assert_eq!(key_only_stats.write_bytes, stats.write_bytes);
+    assert_eq!(key_only_stats.default_keys, 0);
+    assert_eq!(key_only_stats.default_bytes, 0);
+
+    let mut seen = 0;
+    let stopped = forward_scan_with(&mut scanner(&cfg), total, |row| {
+        assert!(row.default_value.is_some());
+        seen += 1;
+        seen < 5
+    })
+    .unwrap();
+    assert_eq!(stopped.rows, 5);
+    assert_eq!(stopped.default_keys, 5);
+
+    let mut write_cache = vec![];
+    for &(batch_size, limit) in &[
+        (64, std::u64::MAX),
+        (64, 100),
+        (7, 20),
+        (1024, 999),
+        (0, 30),
+    ] {
+        let batch =
+            forward_batch_scan(&mut scanner(&cfg), batch_size, limit, &mut write_cache).unwrap();
+        assert_eq!(batch, forward_scan(&mut scanner(&cfg), limit).unwrap());
+        assert_eq!(batch.rows, cmp::min(limit, total));
     }
+}
 
-    for _ in 0..sz {
-        scanner.iter_default.next();
-        black_box(scanner.iter_default.key());
-        write_cache.extend_from_slice(scanner.iter_default.value());
+#[test]
+fn test_scan_key_layout_violation() {
+    let db = test_util::TestDb::with_rows(100);
+    // A default row without the matching write row.
+    db.put_stray_row(CF_DEFAULT, 1, b"v");
+    let cfg = ScannerConfig::default();
+
+    match forward_scan(&mut db.scanner(&cfg), std::u64::MAX) {
+        Err(Error::KeyLayout(_)) => {}
+        res => panic!("expect key layout error, got {:?}", res),
+    }
+    match forward_batch_scan(&mut db.scanner(&cfg), 64, std::u64::MAX, &mut vec![]) {
+        Err(Error::KeyLayout(_)) => {}
+        res => panic!("expect key layout error, got {:?}", res),
     }
-    write_cache.clear();
+    // Key only scans never look at CF_DEFAULT.
+    let mut scanner = db.scanner(&cfg.key_only(true));
+    assert_eq!(forward_scan(&mut scanner, std::u64::MAX).unwrap().rows, 100);
+}
+
+#[test]
+fn test_scan_raw_keys() {
+    let drain_cfg = DrainConfig::new(500, ValueType::MiddleValue).key_mode(KeyMode::Raw);
+    let db = test_util::TestDb::new(&drain_cfg);
+
+    let cfg = ScannerConfig::new(Some(DIST_QT1_KEY.to_vec()), Some(DIST_QT3_KEY.to_vec()));
+    let cfg = cfg.key_mode(KeyMode::Raw);
+    let mut raw_keys = vec![];
+    let stats = forward_scan_with(&mut db.scanner(&cfg), 500, |row| {
+        raw_keys.push(row.raw_key.unwrap().to_vec());
+        true
+    })
+    .unwrap();
+    let rows = stats.rows;
+    assert!(rows > 0 && rows < 500, "rows {}", rows);
+    for raw_key in &raw_keys {
+        assert!(raw_key.as_slice() >= &DIST_QT1_KEY[..] && raw_key.as_slice() < &DIST_QT3_KEY[..]);
+    }
+
+    assert_eq!(
+        forward_batch_scan(&mut db.scanner(&cfg), 64, 500, &mut vec![]).unwrap(),
+        stats
+    );
 }
@@ -0,0 +1,247 @@
+//! Checks `CF_LOCK` for the locks a snapshot read conflicts with, like the scanner of TiKV.
+//!
+//! A transaction locks its keys in `CF_LOCK` at its start ts until it commits. A read at
+//! `ScannerConfig::read_ts` can't tell whether the value of a key locked at or before the read
+//! ts is committed before it, so TiKV returns the lock for the reader to resolve. The locks of
+//! `LockType::Lock` and `LockType::Pessimistic` write no value and never conflict.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rocksdb::rocksdb::DBIterator;
+use rocksdb::{SeekKey, DB};
+
+use crate::errors::{Error, Result};
+use crate::schema::{Lock, LockRef, LockType};
+use crate::tikv_code::key::Key;
+use crate::{check_iter_status, ScanStats, Scanner};
+
+/// What the scans do with the conflicting locks in the scanned range.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LockCheck {
+    /// `CF_LOCK` is never opened nor touched.
+    #[default]
+    Off,
+    /// The conflicting locks are counted and collected in `Scanner::locks`.
+    Report,
+    /// The scan fails with `Error::KeyIsLocked` at the first conflicting lock.
+    Fail,
+}
+
+impl LockCheck {
+    pub fn name(self) -> &'static str {
+        match self {
+            LockCheck::Off => "off",
+            LockCheck::Report => "report",
+            LockCheck::Fail => "fail",
+        }
+    }
+}
+
+impl FromStr for LockCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<LockCheck, String> {
+        match s {
+            "off" => Ok(LockCheck::Off),
+            "report" => Ok(LockCheck::Report),
+            "fail" => Ok(LockCheck::Fail),
+            _ => Err(format!("unknown lock check {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for LockCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A conflicting lock, `key` is the encoded user key it locks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyLock {
+    pub key: Vec<u8>,
+    pub lock: Lock,
+}
+
+/// Whether a read at `read_ts` conflicts with `lock`.
+pub fn is_conflicting(lock: &LockRef<'_>, read_ts: u64) -> bool {
+    lock.ts <= read_ts && (lock.lock_type == LockType::Put || lock.lock_type == LockType::Delete)
+}
+
+/// Steps the lock iterator of a scanner along with the scanned keys.
+pub(crate) struct LockChecker {
+    iter: DBIterator<Arc<DB>>,
+    check: LockCheck,
+    read_ts: u64,
+    pub(crate) locks: Vec<KeyLock>,
+}
+
+impl LockChecker {
+    pub(crate) fn new(iter: DBIterator<Arc<DB>>, check: LockCheck, read_ts: u64) -> LockChecker {
+        LockChecker {
+            iter,
+            check,
+            read_ts,
+            locks: vec![],
+        }
+    }
+
+    /// Seeks to `key` and forgets the locks reported before.
+    pub(crate) fn seek(&mut self, key: &[u8]) {
+        self.iter.seek(SeekKey::Key(key));
+        self.locks.clear();
+    }
+
+    /// Checks the locks up to the user key of `write_key`, a key of `CF_WRITE`.
+    #[inline]
+    pub(crate) fn check_until(&mut self, write_key: &[u8], stats: &mut ScanStats) -> Result<()> {
+        if !self.iter.valid() {
+            return Ok(());
+        }
+        let user_key = Key::truncate_ts_for(write_key)?;
+        while self.iter.valid() && self.iter.key() <= user_key {
+            self.check_current(stats)?;
+            self.iter.next();
+        }
+        Ok(())
+    }
+
+    /// Checks the locks left in the range, once the scan has reached its end.
+    pub(crate) fn check_rest(&mut self, stats: &mut ScanStats) -> Result<()> {
+        while self.iter.valid() {
+            self.check_current(stats)?;
+            self.iter.next();
+        }
+        check_iter_status(&self.iter)
+    }
+
+    fn check_current(&mut self, stats: &mut ScanStats) -> Result<()> {
+        let (key, value) = (self.iter.key(), self.iter.value());
+        stats.add_lock(key, value);
+        let lock = LockRef::parse(value)?;
+        if !is_conflicting(&lock, self.read_ts) {
+            return Ok(());
+        }
+        stats.locked += 1;
+        match self.check {
+            LockCheck::Fail => Err(Error::KeyIsLocked(
+                key.to_vec(),
+                lock.primary.to_vec(),
+                lock.ts,
+            )),
+            _ => {
+                self.locks.push(KeyLock {
+                    key: key.to_vec(),
+                    lock: lock.to_owned(),
+                });
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Fails if the scanner checks the locks, which `scan` doesn't.
+pub(crate) fn check_lock_unsupported(scanner: &Scanner, scan: &str) -> Result<()> {
+    if scanner.cfg.lock_check == LockCheck::Off {
+        return Ok(());
+    }
+    Err(Error::Unsupported(format!(
+        "{} doesn't check CF_LOCK, the lock check must be off",
+        scan
+    )))
+}
+
+#[test]
+fn test_lock_check() {
+    use rocksdb::Writable;
+
+    use crate::drain::{DrainConfig, ValueType};
+    use crate::gen_db::get_cf_handle;
+    use crate::mvcc::mvcc_scan;
+    use crate::test_util::TestDb;
+    use crate::tikv_code::constexpr::CF_LOCK;
+    use crate::{forward_batch_scan, forward_scan, ScannerConfig};
+
+    assert_eq!("report".parse(), Ok(LockCheck::Report));
+    assert_eq!(LockCheck::Fail.to_string(), "fail");
+    assert!("on".parse::<LockCheck>().is_err());
+
+    // The locks are checked with the same rows scanned as without.
+    let drain_cfg = DrainConfig::new(2000, ValueType::MiddleValue)
+        .versions_per_key(2)
+        .pending_locks(0.1);
+    let db = TestDb::new(&drain_cfg);
+    let cfg = ScannerConfig::default();
+    let scanner = |cfg: &ScannerConfig| db.scanner(cfg);
+
+    let mut iter = db.db.iter_cf(get_cf_handle(&db.db, CF_LOCK).unwrap());
+    iter.seek(SeekKey::Start);
+    let mut locks = vec![];
+    while iter.valid() {
+        locks.push((
+            iter.key().to_vec(),
+            LockRef::parse(iter.value()).unwrap().ts,
+        ));
+        iter.next();
+    }
+    assert!(!locks.is_empty());
+
+    let forward = forward_scan(&mut scanner(&cfg), u64::MAX).unwrap();
+    assert_eq!((forward.lock_keys, forward.locked), (0, 0));
+    let report = cfg.clone().lock_check(LockCheck::Report);
+    let mut reporting = scanner(&report);
+    let stats = forward_scan(&mut reporting, u64::MAX).unwrap();
+    assert_eq!(stats.rows, forward.rows);
+    assert_eq!(stats.lock_keys, locks.len() as u64);
+    assert_eq!(stats.locked, locks.len() as u64);
+    assert_eq!(stats.bytes(), forward.bytes() + stats.lock_bytes);
+    let reported: Vec<_> = reporting.locks().iter().map(|l| l.key.clone()).collect();
+    assert_eq!(
+        reported,
+        locks.iter().map(|l| l.0.clone()).collect::<Vec<_>>()
+    );
+    let mut write_cache = vec![];
+    let batch = forward_batch_scan(&mut scanner(&report), 64, u64::MAX, &mut write_cache).unwrap();
+    assert_eq!(batch, stats);
+    let mvcc = mvcc_scan(&mut scanner(&report), u64::MAX).unwrap();
+    assert_eq!(mvcc.locked, stats.locked);
+
+    // Only the locks at or before the read ts conflict.
+    let read_ts = locks[locks.len() / 2].1;
+    let conflicting = locks.iter().filter(|l| l.1 <= read_ts).count() as u64;
+    let stats = mvcc_scan(&mut scanner(&report.clone().read_ts(read_ts)), u64::MAX).unwrap();
+    assert_eq!(stats.lock_keys, locks.len() as u64);
+    assert_eq!(stats.locked, conflicting);
+
+    // A limited scan only checks the locks up to its last row.
+    let limited = forward_scan(&mut scanner(&report), 10).unwrap();
+    assert!(limited.lock_keys < locks.len() as u64);
+
+    let fail = cfg.clone().lock_check(LockCheck::Fail);
+    match forward_scan(&mut scanner(&fail), u64::MAX) {
+        Err(Error::KeyIsLocked(key, _, ts)) => assert_eq!((key, ts), locks[0].clone()),
+        res => panic!("expect key is locked, got {:?}", res),
+    }
+    match forward_scan(&mut scanner(&fail.clone().read_ts(0)), u64::MAX) {
+        Ok(stats) if stats.rows == forward.rows => {}
+        res => panic!("expect no conflict, got {:?}", res),
+    }
+
+    // The locks without a value never conflict.
+    let lock_cf = get_cf_handle(&db.db, CF_LOCK).unwrap();
+    for (key, ts) in &locks {
+        let lock = Lock::new(LockType::Pessimistic, b"pk".to_vec(), *ts, 3000, None);
+        db.db.put_cf(lock_cf, key, &lock.to_bytes()).unwrap();
+    }
+    assert_eq!(
+        forward_scan(&mut scanner(&fail), u64::MAX).unwrap().rows,
+        forward.rows
+    );
+
+    match crate::access::write_only_scan(&mut scanner(&fail), 10) {
+        Err(Error::Unsupported(_)) => {}
+        res => panic!("expect unsupported, got {:?}", res),
+    }
+}
@@ -0,0 +1,250 @@
+//! `mvcc_scan` reads the version of every user key visible at `ScannerConfig::read_ts`, like
+//! the forward scanner of TiKV.
+//!
+//! The versions of a user key are ordered from the newest commit ts. The newest version
+//! committed at or before the read ts decides the row: a put yields it, a delete hides the
+//! key, and locks and rollbacks are skipped for the next older version. The locks of
+//! `CF_LOCK` are checked at every user key if `ScannerConfig::lock_check` is on.
+
+use std::sync::Arc;
+
+use rocksdb::rocksdb::DBIterator;
+use rocksdb::{SeekKey, DB};
+use test::black_box;
+
+use crate::access::default_key_missing;
+use crate::errors::Result;
+use crate::schema::{WriteRef, WriteType};
+use crate::tikv_code::bytes;
+use crate::tikv_code::key::Key;
+use crate::tikv_code::number::NumberEncoder;
+use crate::{check_iter_status, KeyMode, ScanRow, ScanStats, Scanner};
+
+/// The older versions of a user key are skipped by `next` this many times before seeking
+/// past them.
+pub const SEEK_BOUND: usize = 8;
+
+/// Scans at most `limit` visible rows and returns what is actually scanned. `rows` counts
+/// the visible rows, the write stats count every version whose value is read.
+pub fn mvcc_scan(scanner: &mut Scanner, limit: u64) -> Result<ScanStats> {
+    mvcc_scan_with(scanner, limit, |row| {
+        black_box((
+            row.write_key,
+            row.raw_key,
+            row.write_value,
+            row.default_value,
+        ));
+        true
+    })
+}
+
+/// Like `mvcc_scan`, but feeds every visible row to `f`, the `write_key` of which is the key
+/// of the put. The scan stops early once `f` returns false.
+pub fn mvcc_scan_with<F>(scanner: &mut Scanner, limit: u64, mut f: F) -> Result<ScanStats>
+where
+    F: FnMut(ScanRow<'_>) -> bool,
+{
+    let read_ts = scanner.cfg.read_ts;
+    let key_mode = scanner.cfg.key_mode;
+    let iter_write = &mut scanner.iter_write;
+    let mut iter_default = scanner.iter_default.as_mut();
+    let mut lock_checker = scanner.lock_checker.as_mut();
+
+    let mut stats = ScanStats::default();
+    let mut user_key = vec![];
+    let mut default_key = vec![];
+    let mut raw_key = vec![];
+    while stats.rows < limit && iter_write.valid() {
+        user_key.clear();
+        user_key.extend_from_slice(Key::truncate_ts_for(iter_write.key())?);
+        if let Some(checker) = lock_checker.as_mut() {
+            checker.check_until(iter_write.key(), &mut stats)?;
+        }
+
+        // Moves to the put deciding the row, or past the delete hiding it.
+        let mut put = false;
+        while iter_write.valid() && Key::is_user_key_eq(iter_write.key(), &user_key) {
+            if Key::decode_ts_from(iter_write.key())? <= read_ts {
+                stats.add_write(iter_write.key(), iter_write.value());
+                match WriteRef::parse(iter_write.value())?.write_type {
+                    WriteType::Put => {
+                        put = true;
+                        break;
+                    }
+                    WriteType::Delete => {
+                        iter_write.next();
+                        break;
+                    }
+                    WriteType::Lock | WriteType::Rollback => {}
+                }
+            }
+            iter_write.next();
+        }
+
+        if put {
+            let write = WriteRef::parse(iter_write.value())?;
+            let default_value = match (write.short_value, iter_default.as_mut()) {
+                (Some(v), _) => Some(v),
+                (None, None) => None,
+                (None, Some(iter)) => {
+                    default_key.clear();
+                    default_key.extend_from_slice(&user_key);
+                    default_key.encode_u64_desc(write.start_ts)?;
+                    if !iter.seek(SeekKey::Key(&default_key))
+                        || iter.key() != default_key.as_slice()
+                    {
+                        check_iter_status(iter)?;
+                        return Err(default_key_missing(&default_key));
+                    }
+                    stats.add_default(iter.key(), iter.value());
+                    Some(iter.value())
+                }
+            };
+            let raw_key = match key_mode {
+                KeyMode::Raw => {
+                    raw_key.clear();
+                    bytes::decode_bytes_into(&mut iter_write.key(), &mut raw_key)?;
+                    Some(raw_key.as_slice())
+                }
+                KeyMode::Encoded => None,
+            };
+            let row = ScanRow {
+                write_key: iter_write.key(),
+                raw_key,
+                write_value: iter_write.value(),
+                default_value,
+            };
+            stats.rows += 1;
+            if !f(row) {
+                return Ok(stats);
+            }
+            iter_write.next();
+        }
+
+        skip_versions(iter_write, &user_key)?;
+    }
+
+    if stats.rows < limit {
+        check_iter_status(iter_write)?;
+        if let Some(checker) = lock_checker {
+            checker.check_rest(&mut stats)?;
+        }
+    }
+    Ok(stats)
+}
+
+/// Moves the write iterator past the remaining versions of `user_key`.
+fn skip_versions(iter_write: &mut DBIterator<Arc<DB>>, user_key: &[u8]) -> Result<()> {
+    for _ in 0..SEEK_BOUND {
+        if !iter_write.valid() || !Key::is_user_key_eq(iter_write.key(), user_key) {
+            return Ok(());
+        }
+        iter_write.next();
+    }
+    if !iter_write.valid() || !Key::is_user_key_eq(iter_write.key(), user_key) {
+        return Ok(());
+    }
+    // The version at ts 0 is the last one a user key can have.
+    let mut last = user_key.to_vec();
+    last.encode_u64_desc(0)?;
+    if iter_write.seek(SeekKey::Key(&last)) && iter_write.key() == last.as_slice() {
+        iter_write.next();
+    }
+    Ok(())
+}
+
+/// Checks that `mvcc_scan` yields what a model of the MVCC reads expects.
+#[test]
+fn test_mvcc_scan() {
+    use std::collections::BTreeMap;
+
+    use crate::drain::{DrainConfig, ValueType, WriteRatios};
+    use crate::errors::Error;
+    use crate::gen_db::get_cf_handle;
+    use crate::schema::generate_write_value;
+    use crate::test_util::TestDb;
+    use crate::tikv_code::constexpr::{CF_DEFAULT, CF_WRITE};
+    use crate::{forward_scan, ScannerConfig};
+
+    // Every key of a put only dataset is yielded once, at its newest version.
+    let db = TestDb::with_rows(300);
+    let cfg = ScannerConfig::default();
+    let forward = forward_scan(&mut db.scanner(&cfg), u64::MAX).unwrap();
+    let stats = mvcc_scan(&mut db.scanner(&cfg), u64::MAX).unwrap();
+    assert!(stats.rows > 0 && stats.rows <= forward.rows);
+
+    // The expected rows are computed from all the versions read by a plain iterator.
+    let model = |db: &TestDb, read_ts: u64| {
+        let db = &db.db;
+        let mut versions: BTreeMap<Vec<u8>, Vec<(u64, WriteType, u64)>> = BTreeMap::new();
+        let mut iter = db.iter_cf(get_cf_handle(db, CF_WRITE).unwrap());
+        iter.seek(SeekKey::Start);
+        while iter.valid() {
+            let (user_key, commit_ts) = Key::split_on_ts_for(iter.key()).unwrap();
+            let write = WriteRef::parse(iter.value()).unwrap();
+            versions.entry(user_key.to_vec()).or_default().push((
+                commit_ts,
+                write.write_type,
+                write.start_ts,
+            ));
+            iter.next();
+        }
+        let mut rows = vec![];
+        for (user_key, mut versions) in versions {
+            versions.sort_by(|a, b| b.0.cmp(&a.0));
+            let decided = versions
+                .iter()
+                .filter(|v| v.0 <= read_ts)
+                .find(|v| v.1 == WriteType::Put || v.1 == WriteType::Delete);
+            if let Some(&(_, WriteType::Put, start_ts)) = decided {
+                let default_key = Key::from_encoded(user_key).append_ts(start_ts);
+                let value = db
+                    .get_cf(
+                        get_cf_handle(db, CF_DEFAULT).unwrap(),
+                        default_key.as_encoded(),
+                    )
+                    .unwrap()
+                    .unwrap();
+                rows.push((default_key.truncate_ts().into_encoded(), value.to_vec()));
+            }
+        }
+        rows
+    };
+    let scan = |db: &TestDb, cfg: &ScannerConfig, limit: u64| {
+        let mut rows = vec![];
+        let stats = mvcc_scan_with(&mut db.scanner(cfg), limit, |row| {
+            let user_key = Key::truncate_ts_for(row.write_key).unwrap();
+            rows.push((user_key.to_vec(), row.default_value.unwrap().to_vec()));
+            true
+        })
+        .unwrap();
+        assert_eq!(stats.rows, rows.len() as u64);
+        rows
+    };
+    assert_eq!(scan(&db, &cfg, u64::MAX), model(&db, u64::MAX));
+
+    // Many versions of every type, with a range of mostly deleted keys.
+    let drain_cfg = DrainConfig::new(3000, ValueType::MiddleValue)
+        .versions_per_key(20)
+        .write_ratios(WriteRatios::new(2, 1, 1, 1))
+        .ratios_in(10..20, WriteRatios::new(1, 8, 0, 1));
+    let db = TestDb::new(&drain_cfg);
+    for &read_ts in &[u64::MAX, 30000, 10] {
+        let cfg = cfg.clone().read_ts(read_ts);
+        let expected = model(&db, read_ts);
+        assert_eq!(scan(&db, &cfg, u64::MAX), expected);
+        assert_eq!(scan(&db, &cfg, 7), &expected[..expected.len().min(7)]);
+    }
+
+    // Key only scans never touch CF_DEFAULT.
+    let stats = mvcc_scan(&mut db.scanner(&cfg.clone().key_only(true)), u64::MAX).unwrap();
+    assert_eq!(stats.rows, model(&db, u64::MAX).len() as u64);
+    assert_eq!(stats.default_keys, 0);
+
+    // A put without its default row.
+    db.put_stray_row(CF_WRITE, 2, &generate_write_value(WriteType::Put, 1));
+    match mvcc_scan(&mut db.scanner(&cfg), u64::MAX) {
+        Err(Error::KeyLayout(_)) => {}
+        res => panic!("expect key layout error, got {:?}", res),
+    }
+}
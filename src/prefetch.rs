@@ -0,0 +1,305 @@
+//! `PrefetchScanner` reads `CF_DEFAULT` on a background thread while the scanning thread reads
+//! `CF_WRITE`, so the two column families are read in parallel.
+//!
+//! The background thread copies the values of `slot_rows` rows at a time into a slot of a ring
+//! of `slots` slots. The scanning thread hands a slot back once it has taken every row of it,
+//! so the background thread never runs more than `slots * slot_rows` rows ahead.
+
+use std::sync::mpsc;
+use std::thread;
+
+use test::black_box;
+
+use crate::access::key_only_unsupported;
+use crate::errors::Result;
+use crate::lock::check_lock_unsupported;
+use crate::tikv_code::bytes;
+use crate::{
+    check_iter_status, less_default_rows, more_default_rows, KeyMode, ScanRow, ScanStats, Scanner,
+};
+
+/// The values of the rows of `CF_DEFAULT` read ahead.
+#[derive(Default)]
+struct Slot {
+    values: Vec<u8>,
+    /// The end of the value in `values` and the length of the key of every row.
+    rows: Vec<(usize, usize)>,
+}
+
+impl Slot {
+    fn with_capacity(rows: usize) -> Slot {
+        Slot {
+            values: vec![],
+            rows: Vec::with_capacity(rows),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.rows.clear();
+    }
+
+    fn push(&mut self, key: &[u8], value: &[u8]) {
+        self.values.extend_from_slice(value);
+        self.rows.push((self.values.len(), key.len()));
+    }
+
+    /// The key length and the value of the `i`th row.
+    fn row(&self, i: usize) -> (usize, &[u8]) {
+        let start = if i == 0 { 0 } else { self.rows[i - 1].0 };
+        let (end, key_len) = self.rows[i];
+        (key_len, &self.values[start..end])
+    }
+}
+
+/// How the scanning thread stopped taking rows.
+enum Stop {
+    /// The limit is reached or `CF_WRITE` is exhausted.
+    Done,
+    /// `f` returned false.
+    Stopped,
+    /// The background thread sent no more rows.
+    DefaultExhausted,
+}
+
+/// Scans like `forward_scan` with `CF_DEFAULT` read ahead on a background thread.
+#[derive(Debug, Clone)]
+pub struct PrefetchScanner {
+    slots: usize,
+    slot_rows: usize,
+}
+
+impl PrefetchScanner {
+    pub fn new() -> PrefetchScanner {
+        PrefetchScanner {
+            slots: 4,
+            slot_rows: 64,
+        }
+    }
+
+    /// The number of slots in the ring.
+    pub fn slots(mut self, slots: usize) -> PrefetchScanner {
+        self.slots = slots.max(1);
+        self
+    }
+
+    /// The number of rows the background thread copies into a slot.
+    pub fn slot_rows(mut self, slot_rows: usize) -> PrefetchScanner {
+        self.slot_rows = slot_rows.max(1);
+        self
+    }
+
+    /// Scans at most `limit` rows and returns what is actually scanned.
+    pub fn scan(&self, scanner: &mut Scanner, limit: u64) -> Result<ScanStats> {
+        self.scan_with(scanner, limit, |row| {
+            black_box((
+                row.write_key,
+                row.raw_key,
+                row.write_value,
+                row.default_value,
+            ));
+            true
+        })
+    }
+
+    /// Like `forward_scan_with`, the scan stops early once `f` returns false. Spawning the
+    /// background thread is part of the scan.
+    pub fn scan_with<F>(&self, scanner: &mut Scanner, limit: u64, mut f: F) -> Result<ScanStats>
+    where
+        F: FnMut(ScanRow<'_>) -> bool,
+    {
+        check_lock_unsupported(scanner, "PrefetchScanner")?;
+        let key_mode = scanner.cfg.key_mode;
+        let iter_write = &mut scanner.iter_write;
+        let iter_default = scanner
+            .iter_default
+            .as_mut()
+            .ok_or_else(|| key_only_unsupported("PrefetchScanner"))?;
+        let slot_rows = self.slot_rows;
+        let (filled_tx, filled_rx) = mpsc::sync_channel::<Slot>(self.slots);
+        let (free_tx, free_rx) = mpsc::channel::<Slot>();
+        for _ in 0..self.slots {
+            free_tx.send(Slot::with_capacity(slot_rows)).unwrap();
+        }
+
+        // The channels move into the scope, so that they are closed if the scan returns early
+        // and the background thread stops.
+        thread::scope(move |s| {
+            let reader = s.spawn(move || -> Result<()> {
+                let mut read = 0;
+                while read < limit && iter_default.valid() {
+                    // Both channels are closed once the scanning thread stops early.
+                    let mut slot = match free_rx.recv() {
+                        Ok(slot) => slot,
+                        Err(_) => return Ok(()),
+                    };
+                    slot.clear();
+                    while slot.rows.len() < slot_rows && read < limit && iter_default.valid() {
+                        slot.push(iter_default.key(), iter_default.value());
+                        iter_default.next();
+                        read += 1;
+                    }
+                    if filled_tx.send(slot).is_err() {
+                        return Ok(());
+                    }
+                }
+                if read < limit {
+                    check_iter_status(iter_default)?;
+                }
+                Ok(())
+            });
+
+            let mut stats = ScanStats::default();
+            let mut slot: Option<Slot> = None;
+            let mut pos = 0;
+            let mut raw_key = vec![];
+            let stop = loop {
+                if stats.rows >= limit || !iter_write.valid() {
+                    break Stop::Done;
+                }
+                if slot.as_ref().is_none_or(|s| pos == s.rows.len()) {
+                    if let Some(used) = slot.take() {
+                        let _ = free_tx.send(used);
+                    }
+                    match filled_rx.recv() {
+                        Ok(filled) => slot = Some(filled),
+                        Err(_) => break Stop::DefaultExhausted,
+                    }
+                    pos = 0;
+                }
+                let (key_len, default_value) = slot.as_ref().unwrap().row(pos);
+                pos += 1;
+                stats.default_keys += 1;
+                stats.default_bytes += (key_len + default_value.len()) as u64;
+                let raw_key = match key_mode {
+                    KeyMode::Raw => {
+                        raw_key.clear();
+                        bytes::decode_bytes_into(&mut iter_write.key(), &mut raw_key)?;
+                        Some(raw_key.as_slice())
+                    }
+                    KeyMode::Encoded => None,
+                };
+                let row = ScanRow {
+                    write_key: iter_write.key(),
+                    raw_key,
+                    write_value: iter_write.value(),
+                    default_value: Some(default_value),
+                };
+                stats.add_write(row.write_key, row.write_value);
+                stats.rows += 1;
+                if !f(row) {
+                    break Stop::Stopped;
+                }
+                iter_write.next();
+            };
+
+            match stop {
+                Stop::Done if stats.rows < limit => {
+                    check_iter_status(iter_write)?;
+                    // Every row left in the ring has no write row. Handing the slots back
+                    // lets the background thread run to the end of CF_DEFAULT.
+                    let mut left = 0;
+                    if let Some(used) = slot.take() {
+                        left += used.rows.len() - pos;
+                        let _ = free_tx.send(used);
+                    }
+                    for filled in &filled_rx {
+                        left += filled.rows.len();
+                        let _ = free_tx.send(filled);
+                    }
+                    reader.join().unwrap()?;
+                    if left > 0 {
+                        return Err(more_default_rows());
+                    }
+                }
+                Stop::Done | Stop::Stopped => {
+                    // The background thread may be reading rows never taken, let it stop.
+                    drop(filled_rx);
+                    drop(free_tx);
+                    let _ = reader.join().unwrap();
+                }
+                Stop::DefaultExhausted => {
+                    reader.join().unwrap()?;
+                    return Err(less_default_rows());
+                }
+            }
+            Ok(stats)
+        })
+    }
+}
+
+impl Default for PrefetchScanner {
+    fn default() -> Self {
+        PrefetchScanner::new()
+    }
+}
+
+#[test]
+fn test_prefetch_scanner() {
+    use crate::errors::Error;
+    use crate::test_util::TestDb;
+    use crate::tikv_code::constexpr::{CF_DEFAULT, CF_WRITE};
+    use crate::{forward_scan, forward_scan_with, ScannerConfig};
+
+    let db = TestDb::with_rows(500);
+    let cfg = ScannerConfig::default();
+    let scanner = |cfg: &ScannerConfig| db.scanner(cfg);
+
+    for &limit in &[u64::MAX, 300, 1] {
+        let forward = forward_scan(&mut scanner(&cfg), limit).unwrap();
+        for &(slots, slot_rows) in &[(1, 1), (2, 7), (4, 64), (4, 1000)] {
+            let prefetch = PrefetchScanner::new().slots(slots).slot_rows(slot_rows);
+            assert_eq!(prefetch.scan(&mut scanner(&cfg), limit).unwrap(), forward);
+            let readahead = cfg.clone().readahead_size(2 * 1024 * 1024);
+            assert_eq!(
+                prefetch.scan(&mut scanner(&readahead), limit).unwrap(),
+                forward
+            );
+        }
+    }
+
+    // The rows and the early stop match `forward_scan_with`.
+    let collect = |scan: &mut dyn FnMut(&mut dyn FnMut(ScanRow<'_>) -> bool) -> ScanStats| {
+        let mut rows = vec![];
+        let stats = scan(&mut |row| {
+            rows.push((row.write_key.to_vec(), row.default_value.unwrap().to_vec()));
+            rows.len() < 100
+        });
+        (stats, rows)
+    };
+    let forward = collect(&mut |f| forward_scan_with(&mut scanner(&cfg), u64::MAX, f).unwrap());
+    let prefetch = collect(&mut |f| {
+        PrefetchScanner::new()
+            .slot_rows(16)
+            .scan_with(&mut scanner(&cfg), u64::MAX, f)
+            .unwrap()
+    });
+    assert_eq!(prefetch, forward);
+    assert_eq!(prefetch.0.rows, 100);
+
+    match PrefetchScanner::new().scan(&mut scanner(&cfg.clone().key_only(true)), 10) {
+        Err(Error::Unsupported(_)) => {}
+        res => panic!("expect unsupported, got {:?}", res),
+    }
+
+    // A default row without the matching write row, and then a write row without the
+    // matching default row.
+    db.put_stray_row(CF_DEFAULT, 1, b"v");
+    match PrefetchScanner::new().scan(&mut scanner(&cfg), u64::MAX) {
+        Err(Error::KeyLayout(_)) => {}
+        res => panic!("expect key layout error, got {:?}", res),
+    }
+    db.put_stray_row(CF_WRITE, 1, b"w");
+    assert_eq!(
+        PrefetchScanner::new()
+            .scan(&mut scanner(&cfg), u64::MAX)
+            .unwrap()
+            .rows,
+        501
+    );
+    db.put_stray_row(CF_WRITE, 2, b"w");
+    match PrefetchScanner::new().scan(&mut scanner(&cfg), u64::MAX) {
+        Err(Error::KeyLayout(_)) => {}
+        res => panic!("expect key layout error, got {:?}", res),
+    }
+}
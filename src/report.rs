@@ -0,0 +1,616 @@
+//! Renders the benchmark results as the markdown tables of `readme.md`.
+//!
+//! The results are read from criterion's `criterion/*/new/estimates.json`, or from the run
+//! records of `crate::results`, and grouped by dataset, i.e. the data size, value length and
+//! the other fields of the benchmark id, and by cache state and seek mode.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::path::Path;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::cache::CacheState;
+use crate::errors::{Error, Result};
+use crate::harness::SeekMode;
+use crate::results::ResultFile;
+
+/// An estimate with its confidence interval, in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub lower: f64,
+    pub point: f64,
+    pub upper: f64,
+}
+
+/// How a benchmark scans.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ScanMode {
+    NonBatch,
+    /// The batch size is unknown for the criterion results of a single batch size.
+    Batch(Option<u64>),
+    /// Another `ScanStrategy`, by its id, e.g. `prefetch_scan_16`.
+    Other(String),
+}
+
+impl ScanMode {
+    /// The mode of a strategy with its parameter values.
+    fn new(strategy: &str, params: &[u64]) -> ScanMode {
+        match strategy {
+            "forward_scan" => ScanMode::NonBatch,
+            "forward_batch_scan" => ScanMode::Batch(params.first().cloned()),
+            _ => {
+                let mut id = strategy.to_owned();
+                for value in params {
+                    id.push_str(&format!("_{}", value));
+                }
+                ScanMode::Other(id)
+            }
+        }
+    }
+}
+
+/// The time of a scan over a dataset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanEstimate {
+    pub rows: u64,
+    /// The value length, e.g. `64`.
+    pub value: String,
+    /// The other fields of the benchmark id, e.g. `versions 8, writes mixed`, empty for the
+    /// datasets of `bench_scan`.
+    pub labels: String,
+    /// The `CacheState` the scans start with.
+    pub cache_state: String,
+    /// The `SeekMode` of the scans.
+    pub seek_mode: String,
+    pub mode: ScanMode,
+    pub time: Estimate,
+    /// Rows read by a scan, if the benchmark declares it.
+    pub scanned_rows: Option<u64>,
+    /// Bytes read from both CFs by a scan, if the benchmark declares it.
+    pub scanned_bytes: Option<u64>,
+}
+
+impl ScanEstimate {
+    fn per_second(&self, n: Option<u64>) -> Option<f64> {
+        n.map(|n| n as f64 / self.time.point * 1e9)
+    }
+
+    /// Whether both are the same scan over the same dataset.
+    fn is_same_scan(&self, other: &ScanEstimate) -> bool {
+        self.rows == other.rows
+            && self.value == other.value
+            && self.labels == other.labels
+            && self.cache_state == other.cache_state
+            && self.seek_mode == other.seek_mode
+            && self.mode == other.mode
+    }
+}
+
+#[derive(Deserialize)]
+struct CriterionEstimates {
+    #[serde(rename = "Mean")]
+    mean: CriterionEstimate,
+}
+
+#[derive(Deserialize)]
+struct CriterionEstimate {
+    confidence_interval: CriterionInterval,
+    point_estimate: f64,
+}
+
+#[derive(Deserialize)]
+struct CriterionInterval {
+    lower_bound: f64,
+    upper_bound: f64,
+}
+
+#[derive(Deserialize)]
+struct CriterionBenchmark {
+    full_id: String,
+    #[serde(default)]
+    throughput: Option<CriterionThroughput>,
+}
+
+#[derive(Deserialize)]
+enum CriterionThroughput {
+    Bytes(u64),
+    Elements(u64),
+}
+
+/// What a benchmark id of `benches/benchmark.rs` tells.
+#[derive(Debug, PartialEq)]
+struct BenchId {
+    rows: u64,
+    value: String,
+    labels: String,
+    cache_state: String,
+    seek_mode: String,
+    mode: ScanMode,
+}
+
+/// Parses a benchmark id of `benches/benchmark.rs`, e.g.
+/// `forward_batch_scan(rocks db data size 20000, value length 64, cache cold, seek included)/rows/256`,
+/// i.e. the strategy name, the dataset and the parameter values of the strategy. The fields
+/// of the datasets of the other benchmarks, e.g. `versions 8, writes mixed`, follow the value
+/// length. The cache state and seek mode are omitted if they are the defaults, the older ids
+/// have no throughput unit.
+fn parse_bench_id(id: &str) -> Option<BenchId> {
+    let re = Regex::new(
+        r"^([a-z_]+)\(rocks db data size (\d+), value length (\d+|mix)((?:, (?:key mode|versions|writes|pending locks|lock check) [\w.]+)*)(?:, cache ([a-z-]+))?(?:, seek ([a-z]+))?\)(?:/(?:rows|bytes))?((?:/\d+)*)$",
+    )
+    .unwrap();
+    let caps = re.captures(id)?;
+    let cache_state = caps
+        .get(5)
+        .map_or(CacheState::default().name(), |m| m.as_str());
+    let seek_mode = match caps.get(6) {
+        Some(m) => m.as_str().parse::<SeekMode>().ok()?,
+        None => SeekMode::default(),
+    };
+    let params = caps[7]
+        .split('/')
+        .skip(1)
+        .map(|v| v.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    let mode = ScanMode::new(&caps[1], &params);
+    Some(BenchId {
+        rows: caps[2].parse().ok()?,
+        value: caps[3].to_owned(),
+        labels: caps[4].trim_start_matches(", ").to_owned(),
+        cache_state: cache_state.to_owned(),
+        seek_mode: seek_mode.to_string(),
+        mode,
+    })
+}
+
+/// Reads the mean of every scan benchmark under the criterion directory, the ids of the others
+/// are printed and skipped. A scan benchmarked for the throughput in both rows and bytes, as by
+/// the older benchmarks, is merged into one timed by the benchmark in rows.
+pub fn read_criterion_dir(dir: impl AsRef<Path>) -> Result<Vec<ScanEstimate>> {
+    let mut estimates = vec![];
+    read_criterion_dir_into(dir.as_ref(), dir.as_ref(), &mut estimates)?;
+    Ok(merge_throughputs(estimates))
+}
+
+fn merge_throughputs(estimates: Vec<ScanEstimate>) -> Vec<ScanEstimate> {
+    let mut merged: Vec<ScanEstimate> = vec![];
+    for e in estimates {
+        match merged.iter_mut().find(|m| m.is_same_scan(&e)) {
+            Some(m) => {
+                if e.scanned_rows.is_some() {
+                    m.time = e.time;
+                    m.scanned_rows = e.scanned_rows;
+                }
+                m.scanned_bytes = m.scanned_bytes.or(e.scanned_bytes);
+            }
+            None => merged.push(e),
+        }
+    }
+    merged
+}
+
+fn read_criterion_dir_into(
+    root: &Path,
+    dir: &Path,
+    estimates: &mut Vec<ScanEstimate>,
+) -> Result<()> {
+    let new = dir.join("new");
+    if new.join("estimates.json").is_file() {
+        // The id in `benchmark.json` keeps the characters which are escaped in the path.
+        let (id, throughput) = match File::open(new.join("benchmark.json")) {
+            Ok(f) => {
+                let bench: CriterionBenchmark =
+                    serde_json::from_reader(f).map_err(|e| Error::Results(e.to_string()))?;
+                (bench.full_id, bench.throughput)
+            }
+            Err(_) => {
+                let id = dir.strip_prefix(root).unwrap().to_string_lossy();
+                (id.into_owned(), None)
+            }
+        };
+        match parse_bench_id(&id) {
+            Some(bench) => {
+                let f = File::open(new.join("estimates.json"))?;
+                let e: CriterionEstimates =
+                    serde_json::from_reader(f).map_err(|e| Error::Results(e.to_string()))?;
+                estimates.push(ScanEstimate {
+                    rows: bench.rows,
+                    value: bench.value,
+                    labels: bench.labels,
+                    cache_state: bench.cache_state,
+                    seek_mode: bench.seek_mode,
+                    mode: bench.mode,
+                    time: Estimate {
+                        lower: e.mean.confidence_interval.lower_bound,
+                        point: e.mean.point_estimate,
+                        upper: e.mean.confidence_interval.upper_bound,
+                    },
+                    scanned_rows: match throughput {
+                        Some(CriterionThroughput::Elements(n)) => Some(n),
+                        _ => None,
+                    },
+                    scanned_bytes: match throughput {
+                        Some(CriterionThroughput::Bytes(n)) => Some(n),
+                        _ => None,
+                    },
+                });
+            }
+            None => eprintln!("skipped benchmark {}", id),
+        }
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() && path.file_name().is_some_and(|n| n != "new" && n != "base") {
+            read_criterion_dir_into(root, &path, estimates)?;
+        }
+    }
+    Ok(())
+}
+
+/// The mean latencies of the run records with the 95% confidence interval of the normal
+/// approximation.
+pub fn from_result_file(file: &ResultFile) -> Vec<ScanEstimate> {
+    file.records
+        .iter()
+        .map(|r| {
+            let l = &r.latency;
+            let margin = 1.96 * l.stddev_us / (l.samples.max(1) as f64).sqrt();
+            let mode = match (r.params.variant.as_str(), r.params.strategy.as_str()) {
+                ("forward_scan", _) => ScanMode::NonBatch,
+                ("forward_batch_scan", _) => ScanMode::Batch(r.params.batch_size),
+                (variant, "") => ScanMode::Other(variant.to_owned()),
+                (_, strategy) => ScanMode::Other(strategy.to_owned()),
+            };
+            ScanEstimate {
+                rows: r.params.rows,
+                value: value_length(&r.params.value_type),
+                labels: match r.params.lock_check.as_str() {
+                    "" | "off" => r.params.dataset.clone(),
+                    lock_check if r.params.dataset.is_empty() => {
+                        format!("lock check {}", lock_check)
+                    }
+                    lock_check => format!("{}, lock check {}", r.params.dataset, lock_check),
+                },
+                cache_state: match r.params.cache_state.as_str() {
+                    "" => CacheState::default().to_string(),
+                    state => state.to_owned(),
+                },
+                seek_mode: match r.params.seek_mode.as_str() {
+                    "" => SeekMode::default().to_string(),
+                    mode => mode.to_owned(),
+                },
+                mode,
+                time: Estimate {
+                    lower: (l.mean_us - margin) * 1e3,
+                    point: l.mean_us * 1e3,
+                    upper: (l.mean_us + margin) * 1e3,
+                },
+                scanned_rows: Some(r.rows),
+                // Missing in the records saved before the bytes are counted.
+                scanned_bytes: Some(r.bytes).filter(|&b| b > 0),
+            }
+        })
+        .collect()
+}
+
+/// Takes the bytes read by the scans of `estimates` from `records`, the estimates of the run
+/// records of the same scans, as the benchmarks only declare the rows as their throughput.
+pub fn fill_scanned_bytes(estimates: &mut [ScanEstimate], records: &[ScanEstimate]) {
+    for e in estimates.iter_mut().filter(|e| e.scanned_bytes.is_none()) {
+        e.scanned_bytes = records
+            .iter()
+            .find(|r| r.is_same_scan(e))
+            .and_then(|r| r.scanned_bytes);
+    }
+}
+
+/// Maps the `ValueType` names of the run records to the value lengths of the benchmark ids.
+fn value_length(value_type: &str) -> String {
+    match value_type {
+        "MiddleValue" => "64",
+        "LongValue" => "128",
+        "LongLongValue" => "256",
+        "MixValue" => "mix",
+        other => other,
+    }
+    .to_owned()
+}
+
+/// Formats nanoseconds like criterion, e.g. `3.4316 ms`.
+fn format_time(ns: f64) -> String {
+    let (v, unit) = if ns < 1e3 {
+        (ns, "ns")
+    } else if ns < 1e6 {
+        (ns / 1e3, "us")
+    } else if ns < 1e9 {
+        (ns / 1e6, "ms")
+    } else {
+        (ns / 1e9, "s")
+    };
+    let s = if v < 10.0 {
+        format!("{:.4}", v)
+    } else if v < 100.0 {
+        format!("{:.3}", v)
+    } else if v < 1000.0 {
+        format!("{:.2}", v)
+    } else {
+        format!("{:.1}", v)
+    };
+    format!("{} {}", s, unit)
+}
+
+fn format_interval(e: &Estimate) -> String {
+    format!(
+        "`[{} {} {}]`",
+        format_time(e.lower),
+        format_time(e.point),
+        format_time(e.upper)
+    )
+}
+
+/// The speedup of `batch` over `base`, with the interval given by the bounds of both.
+fn format_speedup(base: &Estimate, batch: &Estimate) -> String {
+    format!(
+        "{:.3}x `[{:.3}x {:.3}x]`",
+        base.point / batch.point,
+        base.lower / batch.upper,
+        base.upper / batch.lower
+    )
+}
+
+/// Renders a table of every dataset, cache state and seek mode, sorted by the data size and
+/// value length.
+pub fn render_markdown(estimates: &[ScanEstimate]) -> String {
+    let mut datasets: BTreeMap<_, Vec<&ScanEstimate>> = BTreeMap::new();
+    for e in estimates {
+        // Sort the value lengths numerically, `mix` goes last.
+        let value = e.value.parse().unwrap_or(u64::MAX);
+        let key = (
+            e.rows,
+            value,
+            e.value.as_str(),
+            e.labels.as_str(),
+            e.cache_state.as_str(),
+            e.seek_mode.as_str(),
+        );
+        datasets.entry(key).or_default().push(e);
+    }
+
+    let mut out = String::from("# 实验报告\n");
+    for ((rows, _, value, labels, cache_state, seek_mode), mut group) in datasets {
+        group.sort_by(|a, b| a.mode.cmp(&b.mode));
+        let base = group
+            .iter()
+            .find(|e| e.mode == ScanMode::NonBatch)
+            .map(|e| e.time);
+
+        writeln!(out).unwrap();
+        if labels.is_empty() {
+            writeln!(out, "| 数据量 | 字段长 | 缓存 | Seek |").unwrap();
+            writeln!(out, "| :----: | :----: | :----: | :----: |").unwrap();
+            writeln!(
+                out,
+                "| {} | {} | {} | {} |",
+                rows, value, cache_state, seek_mode
+            )
+            .unwrap();
+        } else {
+            writeln!(out, "| 数据量 | 字段长 | 数据集 | 缓存 | Seek |").unwrap();
+            writeln!(out, "| :----: | :----: | :----: | :----: | :----: |").unwrap();
+            writeln!(
+                out,
+                "| {} | {} | {} | {} | {} |",
+                rows, value, labels, cache_state, seek_mode
+            )
+            .unwrap();
+        }
+        writeln!(out).unwrap();
+        writeln!(out, "| 时间 | 模式 | Batch-Size | 加速比 | rows/s | MB/s |").unwrap();
+        writeln!(
+            out,
+            "| :----: | :----: | :----: | :----: | :----: | :----: |"
+        )
+        .unwrap();
+        for e in group {
+            let (mode, batch_size) = match e.mode {
+                ScanMode::NonBatch => ("non-batch", "-".to_owned()),
+                ScanMode::Batch(size) => ("batch", size.map_or("-".to_owned(), |s| s.to_string())),
+                ScanMode::Other(ref id) => (id.as_str(), "-".to_owned()),
+            };
+            let speedup = match (&e.mode, base) {
+                (ScanMode::NonBatch, _) | (_, None) => "-".to_owned(),
+                (_, Some(base)) => format_speedup(&base, &e.time),
+            };
+            let rows_per_second = e
+                .per_second(e.scanned_rows)
+                .map_or("-".to_owned(), |r| format!("{:.0}", r));
+            let mb_per_second = e
+                .per_second(e.scanned_bytes)
+                .map_or("-".to_owned(), |b| format!("{:.1}", b / (1024.0 * 1024.0)));
+            writeln!(
+                out,
+                "| {} | {} | {} | {} | {} | {} |",
+                format_interval(&e.time),
+                mode,
+                batch_size,
+                speedup,
+                rows_per_second,
+                mb_per_second
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+#[test]
+fn test_parse_bench_id() {
+    let bench_id = |rows, value: &str, cache_state: &str, seek_mode: &str, mode| BenchId {
+        rows,
+        value: value.to_owned(),
+        labels: String::new(),
+        cache_state: cache_state.to_owned(),
+        seek_mode: seek_mode.to_owned(),
+        mode,
+    };
+    assert_eq!(
+        parse_bench_id("forward_scan(rocks db data size 20000, value length 64)"),
+        Some(bench_id(
+            20000,
+            "64",
+            "warm-block",
+            "seek-excluded",
+            ScanMode::NonBatch
+        ))
+    );
+    assert_eq!(
+        parse_bench_id("forward_batch_scan(rocks db data size 100000, value length 256)/128"),
+        Some(bench_id(
+            100000,
+            "256",
+            "warm-block",
+            "seek-excluded",
+            ScanMode::Batch(Some(128))
+        ))
+    );
+    assert_eq!(
+        parse_bench_id(
+            "forward_batch_scan(rocks db data size 100000, value length 256, cache cold)/bytes/64"
+        ),
+        Some(bench_id(
+            100000,
+            "256",
+            "cold",
+            "seek-excluded",
+            ScanMode::Batch(Some(64))
+        ))
+    );
+    assert_eq!(
+        parse_bench_id(
+            "forward_scan(rocks db data size 20000, value length 64, cache warm-os, seek included)/rows"
+        ),
+        Some(bench_id(
+            20000,
+            "64",
+            "warm-os",
+            "seek-included",
+            ScanMode::NonBatch
+        ))
+    );
+    assert_eq!(
+        parse_bench_id("forward_batch_scan(rocks db data size 10000, value length 128)"),
+        Some(bench_id(
+            10000,
+            "128",
+            "warm-block",
+            "seek-excluded",
+            ScanMode::Batch(None)
+        ))
+    );
+    assert_eq!(
+        parse_bench_id("prefetch_scan(rocks db data size 20000, value length 64)/bytes/16")
+            .map(|b| b.mode),
+        Some(ScanMode::Other("prefetch_scan_16".to_owned()))
+    );
+    assert_eq!(
+        parse_bench_id(
+            "mvcc_scan(rocks db data size 20000, value length 64, versions 8, writes mixed, cache cold, seek included)/rows"
+        ),
+        Some(BenchId {
+            labels: "versions 8, writes mixed".to_owned(),
+            ..bench_id(20000, "64", "cold", "seek-included", ScanMode::Other("mvcc_scan".to_owned()))
+        })
+    );
+    assert_eq!(
+        parse_bench_id(
+            "forward_batch_scan(rocks db data size 20000, value length mix, pending locks 0.01, lock check report)/bytes/256"
+        ),
+        Some(BenchId {
+            labels: "pending locks 0.01, lock check report".to_owned(),
+            ..bench_id(20000, "mix", "warm-block", "seek-excluded", ScanMode::Batch(Some(256)))
+        })
+    );
+    assert_eq!(
+        parse_bench_id("forward_scan(rocks db data size 20000, value length 64, key mode Raw)")
+            .map(|b| b.labels),
+        Some("key mode Raw".to_owned())
+    );
+    assert_eq!(parse_bench_id("scan_keys/raw"), None);
+}
+
+#[test]
+fn test_render_markdown() {
+    let estimate = |mode, point, scanned_rows, scanned_bytes| ScanEstimate {
+        rows: 20000,
+        value: "64".to_owned(),
+        labels: String::new(),
+        cache_state: "warm-os".to_owned(),
+        seek_mode: "seek-excluded".to_owned(),
+        mode,
+        time: Estimate {
+            lower: point * 0.9,
+            point,
+            upper: point * 1.1,
+        },
+        scanned_rows,
+        scanned_bytes,
+    };
+    let batch = ScanMode::Batch(Some(64));
+    // The benchmarks of a scan in rows and in bytes.
+    let merged = merge_throughputs(vec![
+        estimate(batch.clone(), 2.6e6, None, Some(1_500_000)),
+        estimate(batch.clone(), 2.5e6, Some(10000), None),
+        estimate(ScanMode::NonBatch, 3.9184e6, Some(10000), Some(1_500_000)),
+        estimate(
+            ScanMode::Other("write_only_scan".to_owned()),
+            1.9592e6,
+            Some(10000),
+            None,
+        ),
+    ]);
+    assert_eq!(merged.len(), 3);
+    assert_eq!(
+        merged[0],
+        estimate(batch, 2.5e6, Some(10000), Some(1_500_000))
+    );
+
+    let md = render_markdown(&merged);
+    let lines: Vec<_> = md.lines().collect();
+    assert!(lines.contains(&"| 20000 | 64 | warm-os | seek-excluded |"));
+    assert!(lines
+        .contains(&"| `[3.5266 ms 3.9184 ms 4.3102 ms]` | non-batch | - | - | 2552062 | 365.1 |"));
+    assert!(lines.contains(
+        &"| `[2.2500 ms 2.5000 ms 2.7500 ms]` | batch | 64 | 1.567x `[1.282x 1.916x]` | 4000000 | 572.2 |"
+    ));
+    assert!(lines.contains(
+        &"| `[1.7633 ms 1.9592 ms 2.1551 ms]` | write_only_scan | - | 2.000x `[1.636x 2.444x]` | 5104124 | - |"
+    ));
+    // The non-batch row goes first, the other strategies last.
+    let position = |s| lines.iter().position(|l| l.contains(s)).unwrap();
+    assert!(position("non-batch") < position("| batch"));
+    assert!(position("| batch") < position("write_only_scan"));
+
+    // The bytes of the benchmarks in rows are taken from the run records.
+    let mut estimates = vec![estimate(ScanMode::NonBatch, 3.9184e6, Some(10000), None)];
+    let records = vec![
+        estimate(ScanMode::Batch(None), 1e6, Some(10000), Some(1)),
+        estimate(ScanMode::NonBatch, 4e6, Some(10000), Some(1_500_000)),
+    ];
+    fill_scanned_bytes(&mut estimates, &records);
+    assert_eq!(estimates[0], merged[1]);
+
+    // The datasets of the other benchmarks get their own tables.
+    let mixed = ScanEstimate {
+        labels: "versions 8, writes mixed".to_owned(),
+        ..estimate(ScanMode::NonBatch, 3.9184e6, Some(10000), None)
+    };
+    let merged = merge_throughputs(vec![merged[1].clone(), mixed]);
+    assert_eq!(merged.len(), 2);
+    let md = render_markdown(&merged);
+    let lines: Vec<_> = md.lines().collect();
+    assert!(lines.contains(&"| 20000 | 64 | warm-os | seek-excluded |"));
+    assert!(lines.contains(&"| 20000 | 64 | versions 8, writes mixed | warm-os | seek-excluded |"));
+}
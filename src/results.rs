@@ -0,0 +1,558 @@
+//! Machine-readable records of the benchmark and profile runs.
+//!
+//! A run is saved as `{prefix}-{timestamp}.json` with a flattened `.csv` beside it, and two
+//! saved runs can be compared with Welch's t-test by `src/bin/results.rs`.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::db_opts::{db_options_summary, DB_OPTIONS_PROFILE};
+use crate::errors::{Error, Result};
+use crate::schema::{self, RECORD_ROW_KEY_LEN};
+use crate::ScanStats;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostInfo {
+    pub hostname: String,
+    pub os_type: String,
+    pub os_release: String,
+    pub cpu_num: u32,
+    pub cpu_speed_mhz: u64,
+    pub mem_total_kb: u64,
+}
+
+impl HostInfo {
+    /// Collects the host info, a field is left empty or zero if it can't be read.
+    pub fn collect() -> HostInfo {
+        HostInfo {
+            hostname: sys_info::hostname().unwrap_or_default(),
+            os_type: sys_info::os_type().unwrap_or_default(),
+            os_release: sys_info::os_release().unwrap_or_default(),
+            cpu_num: sys_info::cpu_num().unwrap_or_default(),
+            cpu_speed_mhz: sys_info::cpu_speed().unwrap_or_default(),
+            mem_total_kb: sys_info::mem_info().map(|m| m.total).unwrap_or_default(),
+        }
+    }
+}
+
+/// Formats a scan bound of `RunParams` as `table_id:handle`, like the `--lower` and `--upper`
+/// of `profile` take it, or in hex if it isn't a row key.
+pub fn format_bound(key: &[u8]) -> String {
+    match schema::decode_row_key(key) {
+        Ok((table_id, handle)) if key.len() == RECORD_ROW_KEY_LEN => {
+            format!("{}:{}", table_id, handle)
+        }
+        _ => hex::encode(key),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DbOptionsInfo {
+    pub profile: String,
+    pub settings: BTreeMap<String, String>,
+}
+
+impl DbOptionsInfo {
+    /// The options of the dbs opened by `gen_db`.
+    pub fn current() -> DbOptionsInfo {
+        DbOptionsInfo {
+            profile: DB_OPTIONS_PROFILE.to_owned(),
+            settings: db_options_summary(),
+        }
+    }
+}
+
+/// What a run scans.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunParams {
+    pub rows: u64,
+    pub value_type: String,
+    pub variant: String,
+    pub batch_size: Option<u64>,
+    pub lower: String,
+    pub upper: String,
+    /// The runs the warmup took.
+    pub warmup: usize,
+    /// The `CacheState` a sample starts with, empty in the records saved before it is chosen.
+    #[serde(default)]
+    pub cache_state: String,
+    /// The `SeekMode` of the samples, empty in the records saved before it is chosen.
+    #[serde(default)]
+    pub seek_mode: String,
+    /// The `ScanStrategy::id`, e.g. `prefetch_scan_16`, empty in the records saved before
+    /// the strategies.
+    #[serde(default)]
+    pub strategy: String,
+    /// The `LockCheck` of the scans, empty in the records saved before it is chosen.
+    #[serde(default)]
+    pub lock_check: String,
+    /// The other fields of the dataset, e.g. `versions 8, writes mixed`, empty for the datasets
+    /// of `bench_scan` and in the records saved before.
+    #[serde(default)]
+    pub dataset: String,
+}
+
+/// The latencies of the samples of a run, in microseconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub samples: usize,
+    pub min_us: f64,
+    pub mean_us: f64,
+    pub stddev_us: f64,
+    pub p50_us: f64,
+    pub p90_us: f64,
+    pub p99_us: f64,
+    pub max_us: f64,
+}
+
+impl LatencyStats {
+    pub fn from_samples(samples_us: &[f64]) -> LatencyStats {
+        if samples_us.is_empty() {
+            return LatencyStats::default();
+        }
+        let mut sorted = samples_us.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let (mean, variance) = mean_variance(&sorted);
+        LatencyStats {
+            samples: sorted.len(),
+            min_us: sorted[0],
+            mean_us: mean,
+            stddev_us: variance.sqrt(),
+            p50_us: percentile(&sorted, 50.0),
+            p90_us: percentile(&sorted, 90.0),
+            p99_us: percentile(&sorted, 99.0),
+            max_us: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+/// The nearest-rank percentile of sorted samples.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.max(1) - 1]
+}
+
+/// The mean and the unbiased sample variance.
+fn mean_variance(samples: &[f64]) -> (f64, f64) {
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    if samples.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// Identifies the same task across runs, e.g. `forward_scan_20000_MiddleValue`.
+    pub name: String,
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    pub git_rev: Option<String>,
+    pub host: HostInfo,
+    pub db_options: DbOptionsInfo,
+    pub params: RunParams,
+    /// Rows scanned by each sample.
+    pub rows: u64,
+    /// Bytes read from both column families by each sample.
+    #[serde(default)]
+    pub bytes: u64,
+    /// Rows per second at the mean latency.
+    pub throughput: f64,
+    /// Bytes per second at the mean latency.
+    #[serde(default)]
+    pub byte_throughput: f64,
+    pub latency: LatencyStats,
+    pub samples_us: Vec<f64>,
+}
+
+impl RunRecord {
+    /// Records a run on this host at the current git revision.
+    pub fn new(
+        name: impl Into<String>,
+        params: RunParams,
+        stats: &ScanStats,
+        samples: &[Duration],
+    ) -> RunRecord {
+        let samples_us: Vec<f64> = samples.iter().map(|d| d.as_secs_f64() * 1e6).collect();
+        let latency = LatencyStats::from_samples(&samples_us);
+        let per_second = |n: u64| {
+            if latency.mean_us > 0.0 {
+                n as f64 / latency.mean_us * 1e6
+            } else {
+                0.0
+            }
+        };
+        let throughput = per_second(stats.rows);
+        let byte_throughput = per_second(stats.bytes());
+        RunRecord {
+            name: name.into(),
+            timestamp: unix_timestamp(),
+            git_rev: git_revision(),
+            host: HostInfo::collect(),
+            db_options: DbOptionsInfo::current(),
+            params,
+            rows: stats.rows,
+            bytes: stats.bytes(),
+            throughput,
+            byte_throughput,
+            latency,
+            samples_us,
+        }
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The `HEAD` revision of the working directory, with `-dirty` if there are local changes.
+pub fn git_revision() -> Option<String> {
+    let output = Command::new("git")
+        .args(["describe", "--always", "--dirty", "--abbrev=40"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|s| s.trim().to_owned())
+}
+
+/// A row of the CSV export, which has no nested fields.
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    name: &'a str,
+    timestamp: u64,
+    git_rev: &'a str,
+    hostname: &'a str,
+    cpu_num: u32,
+    db_options: &'a str,
+    rows: u64,
+    value_type: &'a str,
+    dataset: &'a str,
+    cache_state: &'a str,
+    seek_mode: &'a str,
+    lock_check: &'a str,
+    variant: &'a str,
+    strategy: &'a str,
+    batch_size: Option<u64>,
+    bytes: u64,
+    throughput: f64,
+    byte_throughput: f64,
+    samples: usize,
+    mean_us: f64,
+    stddev_us: f64,
+    p50_us: f64,
+    p90_us: f64,
+    p99_us: f64,
+}
+
+/// The records of a benchmark or profile run.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResultFile {
+    pub records: Vec<RunRecord>,
+}
+
+impl ResultFile {
+    pub fn read(path: impl AsRef<Path>) -> Result<ResultFile> {
+        let file = File::open(path)?;
+        serde_json::from_reader(file).map_err(|e| Error::Results(e.to_string()))
+    }
+
+    pub fn write_json(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self).map_err(|e| Error::Results(e.to_string()))
+    }
+
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut w = csv::Writer::from_path(path).map_err(|e| Error::Results(e.to_string()))?;
+        for r in &self.records {
+            w.serialize(CsvRow {
+                name: &r.name,
+                timestamp: r.timestamp,
+                git_rev: r.git_rev.as_ref().map_or("", String::as_str),
+                hostname: &r.host.hostname,
+                cpu_num: r.host.cpu_num,
+                db_options: &r.db_options.profile,
+                rows: r.rows,
+                value_type: &r.params.value_type,
+                dataset: &r.params.dataset,
+                cache_state: &r.params.cache_state,
+                seek_mode: &r.params.seek_mode,
+                lock_check: &r.params.lock_check,
+                variant: &r.params.variant,
+                strategy: &r.params.strategy,
+                batch_size: r.params.batch_size,
+                bytes: r.bytes,
+                throughput: r.throughput,
+                byte_throughput: r.byte_throughput,
+                samples: r.latency.samples,
+                mean_us: r.latency.mean_us,
+                stddev_us: r.latency.stddev_us,
+                p50_us: r.latency.p50_us,
+                p90_us: r.latency.p90_us,
+                p99_us: r.latency.p99_us,
+            })
+            .map_err(|e| Error::Results(e.to_string()))?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Writes `{dir}/{prefix}-{timestamp}.json` and the `.csv` beside it, returns the path of
+    /// the JSON file. A file saved in the same second gets a `-{n}` suffix.
+    pub fn save(&self, dir: impl AsRef<Path>, prefix: &str) -> Result<PathBuf> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let stem = format!("{}-{}", prefix, unix_timestamp());
+        let mut path = dir.join(format!("{}.json", stem));
+        let mut n = 0;
+        while path.exists() {
+            n += 1;
+            path = dir.join(format!("{}-{}.json", stem, n));
+        }
+        self.write_json(&path)?;
+        self.write_csv(path.with_extension("csv"))?;
+        Ok(path)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    /// The new run is significantly slower.
+    Regression,
+    /// The new run is significantly faster.
+    Improvement,
+    NoChange,
+}
+
+/// The difference of the mean latencies of two runs of the same task.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub name: String,
+    pub base_mean_us: f64,
+    pub new_mean_us: f64,
+    /// `new / base - 1`.
+    pub change: f64,
+    /// The two-sided p-value of Welch's t-test.
+    pub p_value: f64,
+    pub verdict: Verdict,
+}
+
+/// Compares the latencies of two runs by Welch's t-test. The change is significant if the
+/// p-value is below `alpha` and it is larger than the `noise` ratio, e.g. `0.02`.
+pub fn compare_runs(base: &RunRecord, new: &RunRecord, alpha: f64, noise: f64) -> Comparison {
+    let (m1, v1) = mean_variance(&base.samples_us);
+    let (m2, v2) = mean_variance(&new.samples_us);
+    let p_value = welch_t_test(
+        (m1, v1, base.samples_us.len()),
+        (m2, v2, new.samples_us.len()),
+    );
+    let change = m2 / m1 - 1.0;
+    let verdict = if p_value >= alpha || change.abs() <= noise {
+        Verdict::NoChange
+    } else if change > 0.0 {
+        Verdict::Regression
+    } else {
+        Verdict::Improvement
+    };
+    Comparison {
+        name: new.name.clone(),
+        base_mean_us: m1,
+        new_mean_us: m2,
+        change,
+        p_value,
+        verdict,
+    }
+}
+
+/// Compares the records of the same name and cache state in two result files.
+pub fn compare_files(
+    base: &ResultFile,
+    new: &ResultFile,
+    alpha: f64,
+    noise: f64,
+) -> Vec<Comparison> {
+    new.records
+        .iter()
+        .filter_map(|n| {
+            base.records
+                .iter()
+                .find(|b| b.name == n.name && b.params.cache_state == n.params.cache_state)
+                .map(|b| compare_runs(b, n, alpha, noise))
+        })
+        .collect()
+}
+
+/// The two-sided p-value of Welch's t-test of two samples given by (mean, variance, size).
+fn welch_t_test(a: (f64, f64, usize), b: (f64, f64, usize)) -> f64 {
+    let (m1, v1, n1) = a;
+    let (m2, v2, n2) = b;
+    if n1 < 2 || n2 < 2 {
+        return 1.0;
+    }
+    let (s1, s2) = (v1 / n1 as f64, v2 / n2 as f64);
+    if s1 + s2 == 0.0 {
+        return if m1 == m2 { 1.0 } else { 0.0 };
+    }
+    let t = (m1 - m2) / (s1 + s2).sqrt();
+    let df = (s1 + s2).powi(2) / (s1 * s1 / (n1 - 1) as f64 + s2 * s2 / (n2 - 1) as f64);
+    // P(|T| > |t|) of Student's t distribution.
+    incomplete_beta(df / 2.0, 0.5, df / (df + t * t))
+}
+
+/// The regularized incomplete beta function `I_x(a, b)`, see Numerical Recipes 6.4.
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const EPS: f64 = 1e-12;
+    const TINY: f64 = 1e-300;
+    let clamp = |v: f64| if v.abs() < TINY { TINY } else { v };
+
+    let (qab, qap, qam) = (a + b, a + 1.0, a - 1.0);
+    let mut c = 1.0;
+    let mut d = 1.0 / clamp(1.0 - qab * x / qap);
+    let mut h = d;
+    for m in 1..300 {
+        let m = f64::from(m);
+        let m2 = 2.0 * m;
+        let aa = m * (b - m) * x / ((qam + m2) * (a + m2));
+        d = 1.0 / clamp(1.0 + aa * d);
+        c = clamp(1.0 + aa / c);
+        h *= d * c;
+        let aa = -(a + m) * (qab + m) * x / ((a + m2) * (qap + m2));
+        d = 1.0 / clamp(1.0 + aa * d);
+        c = clamp(1.0 + aa / c);
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < EPS {
+            break;
+        }
+    }
+    h
+}
+
+/// `ln(Γ(x))` by the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEF: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.001_208_650_973_866_179,
+        -0.000_005_395_239_384_953,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut ser = 1.000_000_000_190_015;
+    for (i, c) in COEF.iter().enumerate() {
+        ser += c / (x + 1.0 + i as f64);
+    }
+    -tmp + (2.506_628_274_631_000_5 * ser / x).ln()
+}
+
+#[test]
+fn test_latency_stats() {
+    let samples: Vec<f64> = (1..=100).map(f64::from).collect();
+    let stats = LatencyStats::from_samples(&samples);
+    assert_eq!(stats.samples, 100);
+    assert_eq!((stats.min_us, stats.max_us), (1.0, 100.0));
+    assert_eq!(stats.mean_us, 50.5);
+    assert_eq!(
+        (stats.p50_us, stats.p90_us, stats.p99_us),
+        (50.0, 90.0, 99.0)
+    );
+    assert_eq!(LatencyStats::from_samples(&[]), LatencyStats::default());
+
+    // two-sided 5% and 1% critical values of Student's t distribution.
+    assert!((incomplete_beta(5.0, 0.5, 10.0 / (10.0 + 2.228 * 2.228)) - 0.05).abs() < 1e-3);
+    assert!((incomplete_beta(15.0, 0.5, 30.0 / (30.0 + 2.750 * 2.750)) - 0.01).abs() < 1e-3);
+}
+
+#[test]
+fn test_format_bound() {
+    use crate::{DIST_QT1_KEY, DIST_QT3_KEY};
+
+    assert_eq!(format_bound(&DIST_QT1_KEY), "7:0");
+    assert_eq!(format_bound(&DIST_QT3_KEY), "22:2000");
+    assert_eq!(format_bound(&schema::encode_row_key(-1, 5)), "-1:5");
+    assert_eq!(format_bound(b"t\x80"), "7480");
+}
+
+#[test]
+fn test_compare_result_files() {
+    let record = |name: &str, samples: &[u64]| {
+        let samples: Vec<Duration> = samples.iter().map(|&s| Duration::from_micros(s)).collect();
+        let stats = ScanStats {
+            rows: 1000,
+            write_keys: 1000,
+            write_bytes: 30000,
+            default_keys: 1000,
+            default_bytes: 90000,
+            ..ScanStats::default()
+        };
+        RunRecord::new(name, RunParams::default(), &stats, &samples)
+    };
+    let base = ResultFile {
+        records: vec![
+            record("a", &[100, 102, 98, 101, 99]),
+            record("b", &[100, 102, 98, 101, 99]),
+            record("c", &[100, 102, 98, 101, 99]),
+        ],
+    };
+    let new = ResultFile {
+        records: vec![
+            record("a", &[120, 122, 118, 121, 119]),
+            record("b", &[80, 82, 78, 81, 79]),
+            record("c", &[101, 97, 103, 100, 99]),
+            record("d", &[1]),
+        ],
+    };
+    assert!((new.records[0].throughput - 1e9 / 120.0).abs() < 1e-3);
+    assert!((new.records[0].byte_throughput - 120e9 / 120.0).abs() < 1e-3);
+
+    let temp_dir = tempdir::TempDir::new("results").unwrap();
+    let path = new.save(temp_dir.path(), "test").unwrap();
+    assert_eq!(ResultFile::read(&path).unwrap(), new);
+    assert_ne!(base.save(temp_dir.path(), "test").unwrap(), path);
+    let csv = fs::read_to_string(path.with_extension("csv")).unwrap();
+    assert_eq!(csv.lines().count(), 1 + new.records.len());
+
+    let verdicts: Vec<_> = compare_files(&base, &new, 0.05, 0.02)
+        .into_iter()
+        .map(|c| (c.name, c.verdict))
+        .collect();
+    assert_eq!(
+        verdicts,
+        vec![
+            ("a".to_owned(), Verdict::Regression),
+            ("b".to_owned(), Verdict::Improvement),
+            ("c".to_owned(), Verdict::NoChange),
+        ]
+    );
+}
@@ -0,0 +1,273 @@
+//! Decodes the rows in `CF_DEFAULT`, ported from `process_kv_pair` of TiKV's table scan.
+
+use std::collections::HashMap;
+
+use crate::errors::{Error, Result};
+use crate::schema::{self, ColumnType};
+use crate::tikv_code::datum::{self, Datum};
+use crate::tikv_code::number;
+use crate::tikv_code::time::DateTime;
+
+/// A column to decode from the rows.
+#[derive(Debug, Clone)]
+pub struct ColumnInfo {
+    pub id: i64,
+    pub ty: ColumnType,
+    /// The column is the row handle, which is stored in the key instead of the value.
+    pub is_handle: bool,
+    pub not_null: bool,
+    /// Used when the column is missing in a row.
+    pub default_value: Option<Datum>,
+}
+
+impl ColumnInfo {
+    pub fn new(id: i64, ty: ColumnType) -> ColumnInfo {
+        ColumnInfo {
+            id,
+            ty,
+            is_handle: false,
+            not_null: false,
+            default_value: None,
+        }
+    }
+
+    pub fn handle(mut self, is_handle: bool) -> ColumnInfo {
+        self.is_handle = is_handle;
+        self
+    }
+
+    pub fn not_null(mut self, not_null: bool) -> ColumnInfo {
+        self.not_null = not_null;
+        self
+    }
+
+    pub fn default_value(mut self, default_value: Datum) -> ColumnInfo {
+        self.default_value = Some(default_value);
+        self
+    }
+}
+
+pub struct RowDecoder {
+    schema: Vec<ColumnInfo>,
+    handle_indices: Vec<usize>,
+    column_id_index: HashMap<i64, usize>,
+    is_column_filled: Vec<bool>,
+}
+
+impl RowDecoder {
+    pub fn new(schema: Vec<ColumnInfo>) -> RowDecoder {
+        let handle_indices = (0..schema.len()).filter(|&i| schema[i].is_handle).collect();
+        let column_id_index = schema
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !c.is_handle)
+            .map(|(i, c)| (c.id, i))
+            .collect();
+        let is_column_filled = vec![false; schema.len()];
+        RowDecoder {
+            schema,
+            handle_indices,
+            column_id_index,
+            is_column_filled,
+        }
+    }
+
+    /// Decodes the rows written by `schema::generate_row` with `columns`.
+    pub fn from_column_types(columns: &[ColumnType]) -> RowDecoder {
+        RowDecoder::new(
+            columns
+                .iter()
+                .enumerate()
+                .map(|(i, &ty)| ColumnInfo::new(i as i64 + 1, ty))
+                .collect(),
+        )
+    }
+
+    pub fn columns_len(&self) -> usize {
+        self.schema.len()
+    }
+
+    /// Decodes a row and pushes a datum to each of `columns`, which has one entry per column.
+    ///
+    /// `key` is the row key without ts, which holds the handle. On error, `columns` are left
+    /// as they were.
+    pub fn process_kv_pair(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        columns: &mut [Vec<Datum>],
+    ) -> Result<()> {
+        let res = self.decode_row(key, value, columns);
+        // Only the filled columns are pushed before an error.
+        for (filled, column) in self.is_column_filled.iter_mut().zip(columns.iter_mut()) {
+            if res.is_err() && *filled {
+                column.pop();
+            }
+            // Reset to not-filled, prepare for next function call.
+            *filled = false;
+        }
+        res
+    }
+
+    fn decode_row(&mut self, key: &[u8], value: &[u8], columns: &mut [Vec<Datum>]) -> Result<()> {
+        let columns_len = self.schema.len();
+        debug_assert_eq!(columns.len(), columns_len);
+        let mut decoded_columns = 0;
+
+        if !self.handle_indices.is_empty() {
+            let handle_id = schema::decode_handle(key)?;
+            for handle_index in &self.handle_indices {
+                columns[*handle_index].push(Datum::I64(handle_id));
+                decoded_columns += 1;
+                self.is_column_filled[*handle_index] = true;
+            }
+        }
+
+        if value.is_empty() || (value.len() == 1 && value[0] == datum::NIL_FLAG) {
+            // Do nothing
+        } else {
+            // The layout of value is: [col_id_1, value_1, col_id_2, value_2, ...]
+            // where each element is datum encoded.
+            // The column id datum must be in var i64 type.
+            let mut remaining = value;
+            while !remaining.is_empty() && decoded_columns < columns_len {
+                if remaining[0] != datum::VAR_INT_FLAG {
+                    return Err(Error::Row("column id must be VAR_INT".to_owned()));
+                }
+                remaining = &remaining[1..];
+                let column_id = number::decode_var_i64(&mut remaining)?;
+                let (val, new_remaining) = datum::split_datum(remaining, false)?;
+                if let Some(&index) = self.column_id_index.get(&column_id) {
+                    // A duplicated element in the row is unexpected, TiKV logs it and keeps
+                    // the first one.
+                    if !self.is_column_filled[index] {
+                        columns[index].push(decode_column(val, self.schema[index].ty)?);
+                        decoded_columns += 1;
+                        self.is_column_filled[index] = true;
+                    }
+                }
+                remaining = new_remaining;
+            }
+        }
+
+        // Some fields may be missing in the row, we push corresponding default value to make all
+        // columns in same length, once no NOT NULL column is missing.
+        let missing = (0..columns_len).find(|&i| {
+            !self.is_column_filled[i]
+                && self.schema[i].default_value.is_none()
+                && self.schema[i].not_null
+        });
+        if let Some(i) = missing {
+            return Err(Error::Row(format!(
+                "missing data for NOT NULL column (offset = {})",
+                i
+            )));
+        }
+        for (i, column) in columns.iter_mut().enumerate() {
+            if !self.is_column_filled[i] {
+                column.push(self.schema[i].default_value.clone().unwrap_or(Datum::Null));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes a datum of a column, a time is stored as its packed `u64`.
+fn decode_column(mut val: &[u8], ty: ColumnType) -> Result<Datum> {
+    let datum = datum::decode_datum(&mut val)?;
+    match (ty, datum) {
+        (ColumnType::DateTime, Datum::U64(packed))
+        | (ColumnType::Timestamp, Datum::U64(packed)) => {
+            Ok(Datum::Time(DateTime::from_packed_u64(packed)?))
+        }
+        (_, datum) => Ok(datum),
+    }
+}
+
+#[test]
+fn test_decode_generated_rows() {
+    use crate::drain::{DrainConfig, ValueType};
+    use crate::test_util::TestDb;
+    use crate::tikv_code::CF_DEFAULT;
+    use crate::Key;
+    use rocksdb::SeekKey;
+
+    let types = vec![
+        ColumnType::Int,
+        ColumnType::Double,
+        ColumnType::Bytes(16),
+        ColumnType::Decimal { prec: 20, frac: 4 },
+        ColumnType::DateTime,
+        ColumnType::Timestamp,
+        ColumnType::Duration,
+    ];
+    let cfg = DrainConfig::new(100, ValueType::MiddleValue)
+        .columns(types.clone())
+        .unwrap();
+    let test_db = TestDb::new(&cfg);
+    let db = &test_db.db;
+
+    // the handle, the generated columns, a missing column with a default value and
+    // a missing nullable one.
+    let mut schema = vec![ColumnInfo::new(0, ColumnType::Int).handle(true)];
+    schema.extend((0..types.len()).map(|i| ColumnInfo::new(i as i64 + 1, types[i]).not_null(true)));
+    schema.push(ColumnInfo::new(100, ColumnType::Int).default_value(Datum::I64(7)));
+    schema.push(ColumnInfo::new(101, ColumnType::Double));
+    let mut decoder = RowDecoder::new(schema);
+    let mut columns = vec![vec![]; decoder.columns_len()];
+
+    let mut iter = db.iter_cf(db.cf_handle(CF_DEFAULT).unwrap());
+    iter.seek(SeekKey::Start);
+    let mut rows = 0;
+    while iter.valid() {
+        let row_key = Key::truncate_ts_for(iter.key()).unwrap();
+        decoder
+            .process_kv_pair(row_key, iter.value(), &mut columns)
+            .unwrap();
+        rows += 1;
+        iter.next();
+    }
+
+    assert!(columns.iter().all(|c| c.len() == rows));
+    for (i, ty) in types.iter().enumerate() {
+        let decoded = &columns[i + 1][0];
+        match (ty, decoded) {
+            (ColumnType::Int, Datum::I64(_))
+            | (ColumnType::Double, Datum::F64(_))
+            | (ColumnType::Decimal { .. }, Datum::Dec(..))
+            | (ColumnType::DateTime, Datum::Time(_))
+            | (ColumnType::Timestamp, Datum::Time(_))
+            | (ColumnType::Duration, Datum::Dur(_)) => {}
+            (ColumnType::Bytes(len), Datum::Bytes(bs)) => assert_eq!(bs.len(), *len),
+            _ => panic!("{:?} decoded as {:?}", ty, decoded),
+        }
+    }
+    assert!(columns[types.len() + 1].iter().all(|d| *d == Datum::I64(7)));
+    assert!(columns[types.len() + 2].iter().all(|d| *d == Datum::Null));
+
+    // a NOT NULL column without a default value must be in the row.
+    let mut decoder = RowDecoder::new(vec![ColumnInfo::new(100, ColumnType::Int).not_null(true)]);
+    let mut columns = vec![vec![]];
+    assert!(decoder.process_kv_pair(b"", &[], &mut columns).is_err());
+
+    // a failed row leaves neither a datum nor a filled column behind.
+    let mut decoder = RowDecoder::new(vec![
+        ColumnInfo::new(0, ColumnType::Int).handle(true),
+        ColumnInfo::new(1, ColumnType::Int),
+        ColumnInfo::new(2, ColumnType::Int).not_null(true),
+    ]);
+    let mut columns = vec![vec![]; 3];
+    let key = schema::encode_row_key(1, 5);
+    let partial = schema::generate_row(&[ColumnType::Int], &mut rand::thread_rng());
+    let mut corrupted = partial.clone();
+    corrupted.push(0xff);
+    for value in &[&partial, &corrupted] {
+        assert!(decoder.process_kv_pair(&key, value, &mut columns).is_err());
+        assert!(columns.iter().all(|c| c.is_empty()));
+    }
+    let full = schema::generate_row(&[ColumnType::Int; 2], &mut rand::thread_rng());
+    decoder.process_kv_pair(&key, &full, &mut columns).unwrap();
+    assert_eq!(columns[0], vec![Datum::I64(5)]);
+    assert!(columns.iter().all(|c| c.len() == 1));
+}
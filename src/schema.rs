@@ -2,7 +2,14 @@ use std::io::Write;
 #[allow(unused)]
 use std::{cmp, u8};
 
+use rand::Rng;
+
+use crate::tikv_code::bytes::BytesEncoder;
+use crate::tikv_code::datum::{Datum, DatumEncoder, VAR_INT_FLAG};
+use crate::tikv_code::decimal::{self, Decimal};
 use crate::tikv_code::number::*;
+use crate::tikv_code::time::{self, DateTime, Duration};
+use crate::tikv_code::{Error, Result};
 
 // handle or index id
 pub const ID_LEN: usize = 8;
@@ -51,6 +58,25 @@ pub fn encode_row_key(table_id: i64, handle: i64) -> Vec<u8> {
     key
 }
 
+/// `decode_handle` decodes the record handle of a row key encoded by `encode_row_key`.
+pub fn decode_handle(key: &[u8]) -> Result<i64> {
+    if key.len() < RECORD_ROW_KEY_LEN
+        || !key.starts_with(TABLE_PREFIX)
+        || &key[TABLE_PREFIX_KEY_LEN..PREFIX_LEN] != RECORD_PREFIX_SEP
+    {
+        return Err(Error::invalid_data("record key expected"));
+    }
+    decode_i64(&mut &key[PREFIX_LEN..RECORD_ROW_KEY_LEN])
+}
+
+/// `decode_row_key` decodes the table id and record handle of a row key encoded by
+/// `encode_row_key`.
+pub fn decode_row_key(key: &[u8]) -> Result<(i64, i64)> {
+    let handle = decode_handle(key)?;
+    let table_id = decode_i64(&mut &key[TABLE_PREFIX_LEN..TABLE_PREFIX_KEY_LEN])?;
+    Ok((table_id, handle))
+}
+
 /// `encode_column_key` encodes the table id, row handle and column id into a byte array.
 pub fn encode_column_key(table_id: i64, handle: i64, column_id: i64) -> Vec<u8> {
     let mut key = Vec::with_capacity(RECORD_ROW_KEY_LEN + ID_LEN);
@@ -61,7 +87,7 @@ pub fn encode_column_key(table_id: i64, handle: i64, column_id: i64) -> Vec<u8>
 }
 
 /// 为 write 字段生成数据
-#[derive(PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct WriteFiled {
     pub write_type: WriteType,
     pub start_ts: u64,
@@ -76,20 +102,363 @@ pub enum WriteType {
     Rollback,
 }
 
+const FLAG_PUT: u8 = b'P';
+const FLAG_DELETE: u8 = b'D';
+const FLAG_LOCK: u8 = b'L';
+const FLAG_ROLLBACK: u8 = b'R';
+const SHORT_VALUE_PREFIX: u8 = b'v';
+
+impl WriteType {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            WriteType::Put => FLAG_PUT,
+            WriteType::Delete => FLAG_DELETE,
+            WriteType::Lock => FLAG_LOCK,
+            WriteType::Rollback => FLAG_ROLLBACK,
+        }
+    }
+
+    pub fn from_u8(b: u8) -> Option<WriteType> {
+        match b {
+            FLAG_PUT => Some(WriteType::Put),
+            FLAG_DELETE => Some(WriteType::Delete),
+            FLAG_LOCK => Some(WriteType::Lock),
+            FLAG_ROLLBACK => Some(WriteType::Rollback),
+            _ => None,
+        }
+    }
+}
+
+impl WriteFiled {
+    pub fn new(write_type: WriteType, start_ts: u64, short_value: Option<Vec<u8>>) -> WriteFiled {
+        WriteFiled {
+            write_type,
+            start_ts,
+            short_value,
+        }
+    }
+
+    /// Encodes the value like TiKV: the flag of the write type, the start ts as a var-int,
+    /// then `v`, the length and the short value if there is one.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let short_len = self.short_value.as_ref().map_or(0, |v| v.len() + 2);
+        let mut b = Vec::with_capacity(1 + MAX_VAR_U64_LEN + short_len);
+        b.push(self.write_type.to_u8());
+        b.encode_var_u64(self.start_ts).unwrap();
+        if let Some(ref v) = self.short_value {
+            encode_short_value(&mut b, v);
+        }
+        b
+    }
+}
+
+/// A `CF_WRITE` value decoded without copying the short value.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct WriteRef<'a> {
+    pub write_type: WriteType,
+    pub start_ts: u64,
+    pub short_value: Option<&'a [u8]>,
+}
+
+impl<'a> WriteRef<'a> {
+    /// Decodes a value encoded by `WriteFiled::to_bytes`.
+    pub fn parse(mut b: &'a [u8]) -> Result<WriteRef<'a>> {
+        let flag = read_u8(&mut b)?;
+        let write_type = WriteType::from_u8(flag)
+            .ok_or_else(|| Error::invalid_data(&format!("bad write type {}", flag)))?;
+        let start_ts = decode_var_u64(&mut b)?;
+        let short_value = decode_short_value(b, "write")?;
+        Ok(WriteRef {
+            write_type,
+            start_ts,
+            short_value,
+        })
+    }
+
+    pub fn to_owned(self) -> WriteFiled {
+        WriteFiled::new(
+            self.write_type,
+            self.start_ts,
+            self.short_value.map(|v| v.to_vec()),
+        )
+    }
+}
+
+/// Encodes `v` as the short value of a write or a lock: `v`, the length then the value.
+fn encode_short_value(b: &mut Vec<u8>, v: &[u8]) {
+    assert!(v.len() <= u8::MAX as usize, "short value too long");
+    b.push(SHORT_VALUE_PREFIX);
+    b.push(v.len() as u8);
+    b.extend_from_slice(v);
+}
+
+/// Decodes the short value left at the end of a write or a lock, `what` names the value in
+/// the errors.
+fn decode_short_value<'a>(b: &'a [u8], what: &str) -> Result<Option<&'a [u8]>> {
+    match b.first() {
+        None => Ok(None),
+        Some(&SHORT_VALUE_PREFIX) => {
+            let len = b.get(1).copied().ok_or_else(Error::unexpected_eof)? as usize;
+            let v = b.get(2..2 + len).ok_or_else(Error::unexpected_eof)?;
+            Ok(Some(v))
+        }
+        Some(&prefix) => Err(Error::invalid_data(&format!(
+            "bad {} value prefix {}",
+            what, prefix
+        ))),
+    }
+}
+
+/// The values not longer than this are kept in the lock or the write rather than in
+/// `CF_DEFAULT`.
+pub const SHORT_VALUE_MAX_LEN: usize = 64;
+
+/// A `CF_LOCK` value, the lock a transaction holds on a key until it commits or rolls back.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Lock {
+    pub lock_type: LockType,
+    pub primary: Vec<u8>,
+    /// The start ts of the transaction holding the lock.
+    pub ts: u64,
+    pub ttl: u64,
+    pub short_value: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockType {
+    Put,
+    Delete,
+    Lock,
+    Pessimistic,
+}
+
+const FLAG_PESSIMISTIC: u8 = b'S';
+
+impl LockType {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            LockType::Put => FLAG_PUT,
+            LockType::Delete => FLAG_DELETE,
+            LockType::Lock => FLAG_LOCK,
+            LockType::Pessimistic => FLAG_PESSIMISTIC,
+        }
+    }
+
+    pub fn from_u8(b: u8) -> Option<LockType> {
+        match b {
+            FLAG_PUT => Some(LockType::Put),
+            FLAG_DELETE => Some(LockType::Delete),
+            FLAG_LOCK => Some(LockType::Lock),
+            FLAG_PESSIMISTIC => Some(LockType::Pessimistic),
+            _ => None,
+        }
+    }
+}
+
+impl Lock {
+    pub fn new(
+        lock_type: LockType,
+        primary: Vec<u8>,
+        ts: u64,
+        ttl: u64,
+        short_value: Option<Vec<u8>>,
+    ) -> Lock {
+        Lock {
+            lock_type,
+            primary,
+            ts,
+            ttl,
+            short_value,
+        }
+    }
+
+    /// Encodes the value like TiKV: the flag of the lock type, the primary as compact bytes,
+    /// the ts and the ttl as var-ints, then the short value like `WriteFiled::to_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let short_len = self.short_value.as_ref().map_or(0, |v| v.len() + 2);
+        let mut b = Vec::with_capacity(
+            1 + MAX_VAR_I64_LEN + self.primary.len() + MAX_VAR_U64_LEN * 2 + short_len,
+        );
+        b.push(self.lock_type.to_u8());
+        b.encode_compact_bytes(&self.primary).unwrap();
+        b.encode_var_u64(self.ts).unwrap();
+        b.encode_var_u64(self.ttl).unwrap();
+        if let Some(ref v) = self.short_value {
+            encode_short_value(&mut b, v);
+        }
+        b
+    }
+}
+
+/// A `CF_LOCK` value decoded without copying the primary nor the short value.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LockRef<'a> {
+    pub lock_type: LockType,
+    pub primary: &'a [u8],
+    pub ts: u64,
+    pub ttl: u64,
+    pub short_value: Option<&'a [u8]>,
+}
+
+impl<'a> LockRef<'a> {
+    /// Decodes a value encoded by `Lock::to_bytes`.
+    pub fn parse(mut b: &'a [u8]) -> Result<LockRef<'a>> {
+        let flag = read_u8(&mut b)?;
+        let lock_type = LockType::from_u8(flag)
+            .ok_or_else(|| Error::invalid_data(&format!("bad lock type {}", flag)))?;
+        let primary_len = decode_var_i64(&mut b)?;
+        if primary_len < 0 || primary_len as usize > b.len() {
+            return Err(Error::unexpected_eof());
+        }
+        let (primary, mut b) = b.split_at(primary_len as usize);
+        let ts = decode_var_u64(&mut b)?;
+        let ttl = decode_var_u64(&mut b)?;
+        let short_value = decode_short_value(b, "lock")?;
+        Ok(LockRef {
+            lock_type,
+            primary,
+            ts,
+            ttl,
+            short_value,
+        })
+    }
+
+    pub fn to_owned(self) -> Lock {
+        Lock::new(
+            self.lock_type,
+            self.primary.to_vec(),
+            self.ts,
+            self.ttl,
+            self.short_value.map(|v| v.to_vec()),
+        )
+    }
+}
+
 #[inline]
 fn repeat_vec(repeat_count: usize) -> Vec<u8> {
     [0].repeat(repeat_count)
 }
 
-pub fn generate_write_value() -> Vec<u8> {
-    use std::mem;
-
-    let sz = mem::size_of::<self::WriteFiled>();
-
-    repeat_vec(sz)
+/// 为 write 字段生成数据，不带 short value，值在 `CF_DEFAULT` 的 `start_ts` 版本里
+pub fn generate_write_value(write_type: WriteType, start_ts: u64) -> Vec<u8> {
+    WriteFiled::new(write_type, start_ts, None).to_bytes()
 }
 
 /// 为 default 字段生成数据 (data_size )
 pub fn generate_default_value(data_size: u64) -> Vec<u8> {
     repeat_vec(data_size as usize)
 }
+
+/// The column types the generator can fill a row with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Int,
+    Double,
+    /// Bytes of the given length.
+    Bytes(usize),
+    Decimal {
+        prec: u8,
+        frac: u8,
+    },
+    DateTime,
+    Timestamp,
+    Duration,
+}
+
+/// The unix time of 1970-01-01 to 2038-01-19, the range of a MySQL `TIMESTAMP`.
+const TIMESTAMP_MICROS: (i64, i64) = (0, (1 << 31) * 1_000_000);
+
+impl ColumnType {
+    /// Fails if a value of this type can't be encoded, e.g. a decimal of more than 38 digits.
+    pub fn check(self) -> Result<()> {
+        match self {
+            ColumnType::Decimal { prec, frac } => decimal::check_precision(prec, frac),
+            _ => Ok(()),
+        }
+    }
+
+    /// Generates a random value of this type, which must pass `check`.
+    pub fn random_datum<R: Rng>(self, rng: &mut R) -> Datum {
+        match self {
+            ColumnType::Int => Datum::I64(rng.gen()),
+            ColumnType::Double => Datum::F64(rng.gen_range(-1e9, 1e9)),
+            ColumnType::Bytes(len) => Datum::Bytes((0..len).map(|_| rng.gen()).collect()),
+            ColumnType::Decimal { prec, frac } => {
+                let bound = 10i128.pow(u32::from(prec));
+                Datum::Dec(Decimal::new(rng.gen_range(1 - bound, bound), frac), prec)
+            }
+            ColumnType::DateTime | ColumnType::Timestamp => {
+                let micros = rng.gen_range(TIMESTAMP_MICROS.0, TIMESTAMP_MICROS.1);
+                Datum::Time(DateTime::from_unix_micros(micros).unwrap())
+            }
+            ColumnType::Duration => {
+                let nanos = rng.gen_range(-time::MAX_DURATION_NANOS, time::MAX_DURATION_NANOS);
+                // MySQL keeps microseconds at most.
+                Datum::Dur(Duration::from_nanos(nanos / 1000 * 1000).unwrap())
+            }
+        }
+    }
+}
+
+/// 为 default 字段生成一行数据，列 id 从 1 开始，布局为 [col_id_1, value_1, col_id_2, value_2, ...]
+pub fn generate_row<R: Rng>(columns: &[ColumnType], rng: &mut R) -> Vec<u8> {
+    let mut row = vec![];
+    for (i, ty) in columns.iter().enumerate() {
+        row.write_all(&[VAR_INT_FLAG]).unwrap();
+        row.encode_var_i64(i as i64 + 1).unwrap();
+        row.encode_datum(&ty.random_datum(rng), false).unwrap();
+    }
+    row
+}
+
+#[test]
+fn test_write_codec() {
+    let writes = vec![
+        WriteFiled::new(WriteType::Put, 0, None),
+        WriteFiled::new(WriteType::Delete, 49_999, None),
+        WriteFiled::new(WriteType::Lock, u64::MAX, Some(vec![])),
+        WriteFiled::new(WriteType::Rollback, 300, Some(b"short".to_vec())),
+    ];
+    for w in writes {
+        let b = w.to_bytes();
+        assert_eq!(WriteRef::parse(&b).unwrap().to_owned(), w);
+    }
+    assert_eq!(generate_write_value(WriteType::Put, 7), b"P\x07".to_vec());
+
+    assert!(WriteRef::parse(b"").is_err());
+    assert!(WriteRef::parse(b"X\x07").is_err());
+    assert!(WriteRef::parse(b"P").is_err());
+    assert!(WriteRef::parse(b"P\x07v\x05abc").is_err());
+    assert!(WriteRef::parse(b"P\x07x").is_err());
+}
+
+#[test]
+fn test_lock_codec() {
+    let locks = vec![
+        Lock::new(
+            LockType::Put,
+            b"pk".to_vec(),
+            1,
+            3000,
+            Some(b"short".to_vec()),
+        ),
+        Lock::new(LockType::Delete, vec![], u64::MAX, 0, None),
+        Lock::new(LockType::Lock, vec![7; 300], 49_999, u64::MAX, Some(vec![])),
+        Lock::new(LockType::Pessimistic, b"k".to_vec(), 300, 20_000, None),
+    ];
+    for l in locks {
+        let b = l.to_bytes();
+        assert_eq!(LockRef::parse(&b).unwrap().to_owned(), l);
+    }
+    assert_eq!(
+        Lock::new(LockType::Put, b"k".to_vec(), 7, 3, None).to_bytes(),
+        b"P\x02k\x07\x03".to_vec()
+    );
+
+    assert!(LockRef::parse(b"").is_err());
+    assert!(LockRef::parse(b"R\x02k\x07\x03").is_err());
+    assert!(LockRef::parse(b"P\x04k\x07\x03").is_err());
+    assert!(LockRef::parse(b"P\x02k\x07").is_err());
+    assert!(LockRef::parse(b"P\x02k\x07\x03v\x05abc").is_err());
+    assert!(LockRef::parse(b"P\x02k\x07\x03x").is_err());
+}
@@ -0,0 +1,514 @@
+//! Scan strategies and the registry the benchmark and the `profile` binary discover them from.
+//!
+//! A new strategy implements `ScanStrategy` and adds a `StrategyEntry` to `BUILTIN`, the
+//! harnesses sweep the values of its parameters and name its results by `ScanStrategy::id`.
+
+use std::collections::HashMap;
+
+use crate::access::{
+    default_only_scan, lookup_scan, pipelined_scan, prefetch_scan, write_only_scan, LookupMode,
+};
+use crate::errors::Result;
+use crate::mvcc::mvcc_scan;
+use crate::prefetch::PrefetchScanner;
+use crate::{forward_batch_scan, forward_scan, ScanStats, Scanner, ScannerConfig};
+
+/// A way to scan the rows of a `Scanner`.
+pub trait ScanStrategy {
+    /// The name shared by every parameter value, e.g. `forward_batch_scan`.
+    fn name(&self) -> &'static str;
+
+    /// The parameter values, in the order of the `ParamSpec`s of the entry.
+    fn params(&self) -> Vec<(&'static str, u64)> {
+        vec![]
+    }
+
+    /// Whether the strategy yields a row for every record of `CF_WRITE` like `forward_scan`,
+    /// rather than a row for every user key visible at the read ts.
+    fn reads_every_version(&self) -> bool {
+        true
+    }
+
+    /// Whether the strategy checks `CF_LOCK` if `ScannerConfig::lock_check` is on, the others
+    /// fail with `Error::Unsupported`.
+    fn checks_locks(&self) -> bool {
+        false
+    }
+
+    /// The config of the scanners the strategy runs on, given the config of the harness.
+    fn scanner_config(&self, cfg: &ScannerConfig) -> ScannerConfig {
+        cfg.clone()
+    }
+
+    /// Scans at most `limit` rows, `write_cache` is a buffer the strategy may keep rows in.
+    fn run(
+        &self,
+        scanner: &mut Scanner,
+        limit: u64,
+        write_cache: &mut Vec<u8>,
+    ) -> Result<ScanStats>;
+
+    /// The name followed by the parameter values, e.g. `forward_batch_scan_64`.
+    fn id(&self) -> String {
+        let mut id = self.name().to_owned();
+        for (_, value) in self.params() {
+            id.push_str(&format!("_{}", value));
+        }
+        id
+    }
+
+    fn param(&self, name: &str) -> Option<u64> {
+        self.params().into_iter().find(|p| p.0 == name).map(|p| p.1)
+    }
+}
+
+/// `forward_scan`.
+pub struct ForwardScan;
+
+impl ScanStrategy for ForwardScan {
+    fn name(&self) -> &'static str {
+        "forward_scan"
+    }
+
+    fn checks_locks(&self) -> bool {
+        true
+    }
+
+    fn run(&self, scanner: &mut Scanner, limit: u64, _: &mut Vec<u8>) -> Result<ScanStats> {
+        forward_scan(scanner, limit)
+    }
+}
+
+/// `forward_batch_scan`.
+pub struct ForwardBatchScan {
+    pub batch_size: u64,
+}
+
+impl ScanStrategy for ForwardBatchScan {
+    fn name(&self) -> &'static str {
+        "forward_batch_scan"
+    }
+
+    fn checks_locks(&self) -> bool {
+        true
+    }
+
+    fn params(&self) -> Vec<(&'static str, u64)> {
+        vec![("batch_size", self.batch_size)]
+    }
+
+    fn run(
+        &self,
+        scanner: &mut Scanner,
+        limit: u64,
+        write_cache: &mut Vec<u8>,
+    ) -> Result<ScanStats> {
+        forward_batch_scan(scanner, self.batch_size, limit, write_cache)
+    }
+}
+
+/// `write_only_scan`, which reads only `CF_WRITE`.
+pub struct WriteOnlyScan;
+
+impl ScanStrategy for WriteOnlyScan {
+    fn name(&self) -> &'static str {
+        "write_only_scan"
+    }
+
+    fn run(&self, scanner: &mut Scanner, limit: u64, _: &mut Vec<u8>) -> Result<ScanStats> {
+        write_only_scan(scanner, limit)
+    }
+}
+
+/// `default_only_scan`, which reads only `CF_DEFAULT`.
+pub struct DefaultOnlyScan;
+
+impl ScanStrategy for DefaultOnlyScan {
+    fn name(&self) -> &'static str {
+        "default_only_scan"
+    }
+
+    fn run(&self, scanner: &mut Scanner, limit: u64, _: &mut Vec<u8>) -> Result<ScanStats> {
+        default_only_scan(scanner, limit)
+    }
+}
+
+/// `prefetch_scan`.
+pub struct PrefetchScan {
+    pub distance: u64,
+}
+
+impl ScanStrategy for PrefetchScan {
+    fn name(&self) -> &'static str {
+        "prefetch_scan"
+    }
+
+    fn params(&self) -> Vec<(&'static str, u64)> {
+        vec![("distance", self.distance)]
+    }
+
+    fn run(&self, scanner: &mut Scanner, limit: u64, _: &mut Vec<u8>) -> Result<ScanStats> {
+        prefetch_scan(scanner, self.distance, limit)
+    }
+}
+
+/// `pipelined_scan`.
+pub struct PipelinedScan {
+    pub chunk_rows: u64,
+}
+
+impl ScanStrategy for PipelinedScan {
+    fn name(&self) -> &'static str {
+        "pipelined_scan"
+    }
+
+    fn params(&self) -> Vec<(&'static str, u64)> {
+        vec![("chunk_rows", self.chunk_rows)]
+    }
+
+    fn run(&self, scanner: &mut Scanner, limit: u64, _: &mut Vec<u8>) -> Result<ScanStats> {
+        pipelined_scan(scanner, self.chunk_rows, limit)
+    }
+}
+
+/// `lookup_scan`, named `get_lookup_scan` or `seek_lookup_scan` by the mode.
+pub struct LookupScan {
+    pub mode: LookupMode,
+}
+
+impl ScanStrategy for LookupScan {
+    fn name(&self) -> &'static str {
+        match self.mode {
+            LookupMode::Get => "get_lookup_scan",
+            LookupMode::Seek => "seek_lookup_scan",
+        }
+    }
+
+    fn run(&self, scanner: &mut Scanner, limit: u64, _: &mut Vec<u8>) -> Result<ScanStats> {
+        lookup_scan(scanner, self.mode, limit)
+    }
+}
+
+/// `mvcc_scan`, which yields the visible version of every user key.
+pub struct MvccScan;
+
+impl ScanStrategy for MvccScan {
+    fn name(&self) -> &'static str {
+        "mvcc_scan"
+    }
+
+    fn reads_every_version(&self) -> bool {
+        false
+    }
+
+    fn checks_locks(&self) -> bool {
+        true
+    }
+
+    fn run(&self, scanner: &mut Scanner, limit: u64, _: &mut Vec<u8>) -> Result<ScanStats> {
+        mvcc_scan(scanner, limit)
+    }
+}
+
+/// `PrefetchScanner`, on scanners with a readahead of `readahead_kb` KB, 0 for the default.
+pub struct PrefetchScannerScan {
+    pub slot_rows: u64,
+    pub readahead_kb: u64,
+}
+
+impl ScanStrategy for PrefetchScannerScan {
+    fn name(&self) -> &'static str {
+        "prefetch_scanner"
+    }
+
+    fn params(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("slot_rows", self.slot_rows),
+            ("readahead_kb", self.readahead_kb),
+        ]
+    }
+
+    fn scanner_config(&self, cfg: &ScannerConfig) -> ScannerConfig {
+        cfg.clone()
+            .readahead_size(self.readahead_kb as usize * 1024)
+    }
+
+    fn run(&self, scanner: &mut Scanner, limit: u64, _: &mut Vec<u8>) -> Result<ScanStats> {
+        PrefetchScanner::new()
+            .slot_rows(self.slot_rows as usize)
+            .scan(scanner, limit)
+    }
+}
+
+/// A parameter of a strategy.
+#[derive(Debug, Clone, Copy)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    /// The values swept unless a harness is told otherwise.
+    pub values: &'static [u64],
+}
+
+/// Builds the strategies of a name.
+#[derive(Clone, Copy)]
+pub struct StrategyEntry {
+    pub name: &'static str,
+    pub params: &'static [ParamSpec],
+    /// Builds the strategy from a value of every parameter, in the order of `params`.
+    pub build: fn(&[u64]) -> Box<dyn ScanStrategy>,
+}
+
+impl StrategyEntry {
+    /// Builds a strategy for every combination of the parameter values. The values of a
+    /// parameter in `overrides` replace its default values.
+    pub fn sweep(&self, overrides: &HashMap<String, Vec<u64>>) -> Vec<Box<dyn ScanStrategy>> {
+        let mut combinations = vec![vec![]];
+        for spec in self.params {
+            let values = overrides
+                .get(spec.name)
+                .map_or(spec.values, |v| v.as_slice());
+            combinations = combinations
+                .into_iter()
+                .flat_map(|c: Vec<u64>| {
+                    values.iter().map(move |&v| {
+                        let mut c = c.clone();
+                        c.push(v);
+                        c
+                    })
+                })
+                .collect();
+        }
+        combinations.iter().map(|c| (self.build)(c)).collect()
+    }
+}
+
+/// The strategies of this crate, in the order the harnesses run them.
+const BUILTIN: &[StrategyEntry] = &[
+    StrategyEntry {
+        name: "forward_scan",
+        params: &[],
+        build: |_| Box::new(ForwardScan),
+    },
+    StrategyEntry {
+        name: "forward_batch_scan",
+        params: &[ParamSpec {
+            name: "batch_size",
+            values: &[64, 128, 256],
+        }],
+        build: |v| Box::new(ForwardBatchScan { batch_size: v[0] }),
+    },
+    StrategyEntry {
+        name: "write_only_scan",
+        params: &[],
+        build: |_| Box::new(WriteOnlyScan),
+    },
+    StrategyEntry {
+        name: "default_only_scan",
+        params: &[],
+        build: |_| Box::new(DefaultOnlyScan),
+    },
+    StrategyEntry {
+        name: "prefetch_scan",
+        params: &[ParamSpec {
+            name: "distance",
+            values: &[16, 128],
+        }],
+        build: |v| Box::new(PrefetchScan { distance: v[0] }),
+    },
+    StrategyEntry {
+        name: "pipelined_scan",
+        params: &[ParamSpec {
+            name: "chunk_rows",
+            values: &[256],
+        }],
+        build: |v| Box::new(PipelinedScan { chunk_rows: v[0] }),
+    },
+    StrategyEntry {
+        name: "get_lookup_scan",
+        params: &[],
+        build: |_| {
+            Box::new(LookupScan {
+                mode: LookupMode::Get,
+            })
+        },
+    },
+    StrategyEntry {
+        name: "seek_lookup_scan",
+        params: &[],
+        build: |_| {
+            Box::new(LookupScan {
+                mode: LookupMode::Seek,
+            })
+        },
+    },
+    StrategyEntry {
+        name: "prefetch_scanner",
+        params: &[
+            ParamSpec {
+                name: "slot_rows",
+                values: &[64],
+            },
+            ParamSpec {
+                name: "readahead_kb",
+                values: &[0, 2048],
+            },
+        ],
+        build: |v| {
+            Box::new(PrefetchScannerScan {
+                slot_rows: v[0],
+                readahead_kb: v[1],
+            })
+        },
+    },
+    StrategyEntry {
+        name: "mvcc_scan",
+        params: &[],
+        build: |_| Box::new(MvccScan),
+    },
+];
+
+#[derive(Clone, Default)]
+pub struct StrategyRegistry {
+    entries: Vec<StrategyEntry>,
+}
+
+impl StrategyRegistry {
+    /// An empty registry.
+    pub fn new() -> StrategyRegistry {
+        StrategyRegistry::default()
+    }
+
+    /// Every strategy of this crate.
+    pub fn builtin() -> StrategyRegistry {
+        StrategyRegistry {
+            entries: BUILTIN.to_vec(),
+        }
+    }
+
+    /// Registers the entry, replacing the one of the same name.
+    pub fn register(&mut self, entry: StrategyEntry) {
+        match self.entries.iter_mut().find(|e| e.name == entry.name) {
+            Some(e) => *e = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    pub fn entries(&self) -> &[StrategyEntry] {
+        &self.entries
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.entries.iter().map(|e| e.name).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&StrategyEntry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    /// Every strategy of every entry with the default parameter values.
+    pub fn sweep_all(&self) -> Vec<Box<dyn ScanStrategy>> {
+        let overrides = HashMap::new();
+        self.entries
+            .iter()
+            .flat_map(|e| e.sweep(&overrides))
+            .collect()
+    }
+
+    /// Builds the strategy of an id given by `ScanStrategy::id`.
+    pub fn parse_id(&self, id: &str) -> Option<Box<dyn ScanStrategy>> {
+        self.entries.iter().find_map(|e| {
+            let rest = id.strip_prefix(e.name)?;
+            let values = rest
+                .split('_')
+                .skip(1)
+                .map(|v| v.parse().ok())
+                .collect::<Option<Vec<u64>>>()?;
+            if (!rest.is_empty() && !rest.starts_with('_')) || values.len() != e.params.len() {
+                return None;
+            }
+            Some((e.build)(&values))
+        })
+    }
+}
+
+#[test]
+fn test_strategy_registry() {
+    use crate::errors::Error;
+    use crate::test_util::TestDb;
+    use crate::{LockCheck, ScannerConfig};
+
+    let registry = StrategyRegistry::builtin();
+    let ids: Vec<_> = registry.sweep_all().iter().map(|s| s.id()).collect();
+    assert_eq!(
+        ids,
+        vec![
+            "forward_scan",
+            "forward_batch_scan_64",
+            "forward_batch_scan_128",
+            "forward_batch_scan_256",
+            "write_only_scan",
+            "default_only_scan",
+            "prefetch_scan_16",
+            "prefetch_scan_128",
+            "pipelined_scan_256",
+            "get_lookup_scan",
+            "seek_lookup_scan",
+            "prefetch_scanner_64_0",
+            "prefetch_scanner_64_2048",
+            "mvcc_scan"
+        ]
+    );
+    let mut overrides = HashMap::new();
+    overrides.insert("batch_size".to_owned(), vec![7]);
+    let swept = registry
+        .get("forward_batch_scan")
+        .unwrap()
+        .sweep(&overrides);
+    assert_eq!(swept.len(), 1);
+    assert_eq!(swept[0].param("batch_size"), Some(7));
+
+    for id in &ids {
+        assert_eq!(&registry.parse_id(id).unwrap().id(), id);
+    }
+    assert!(registry.parse_id("forward_scan_64").is_none());
+    assert!(registry.parse_id("forward_batch_scan").is_none());
+    assert!(registry.parse_id("forward_scanner").is_none());
+    let prefetch = registry.parse_id("prefetch_scanner_16_512").unwrap();
+    assert_eq!(prefetch.param("readahead_kb"), Some(512));
+    assert_eq!(
+        prefetch
+            .scanner_config(&ScannerConfig::default())
+            .readahead_size,
+        512 * 1024
+    );
+
+    // Every strategy reads the same rows, of both column families unless it says otherwise.
+    // The MVCC reads yield a single version of the keys written more than once.
+    let db = TestDb::with_rows(300);
+    let mut write_cache = vec![];
+    let forward = forward_scan(&mut db.scanner(&ScannerConfig::default()), 250).unwrap();
+    assert_eq!(forward.rows, 250);
+    for strategy in registry.sweep_all() {
+        let cfg = strategy.scanner_config(&ScannerConfig::default());
+        let stats = strategy
+            .run(&mut db.scanner(&cfg), 250, &mut write_cache)
+            .unwrap();
+        if !strategy.reads_every_version() {
+            assert!(stats.rows > 0 && stats.rows <= forward.rows);
+            continue;
+        }
+        assert_eq!(stats.rows, forward.rows, "{}", strategy.id());
+        if !strategy.name().ends_with("_only_scan") {
+            assert_eq!(stats, forward, "{}", strategy.id());
+        }
+    }
+
+    // The strategies either check the locks or refuse to scan without checking them.
+    for strategy in registry.sweep_all() {
+        let cfg = strategy.scanner_config(&ScannerConfig::default().lock_check(LockCheck::Fail));
+        match strategy.run(&mut db.scanner(&cfg), 250, &mut write_cache) {
+            Ok(stats) => assert!(strategy.checks_locks() && stats.rows > 0),
+            Err(Error::Unsupported(_)) => assert!(!strategy.checks_locks()),
+            Err(e) => panic!("{} failed: {}", strategy.id(), e),
+        }
+    }
+}
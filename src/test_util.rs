@@ -0,0 +1,47 @@
+//! The datasets the tests scan.
+
+use std::sync::Arc;
+
+use rocksdb::{Writable, DB};
+use tempdir::TempDir;
+
+use crate::drain::{drain_data_with_config, DrainConfig, ValueType};
+use crate::gen_db::{default_test_db_with_path, get_cf_handle};
+use crate::tikv_code::key::Key;
+use crate::{Scanner, ScannerConfig, LOWEST_KEY_STR};
+
+/// A db in a temp dir, which is removed with it.
+pub(crate) struct TestDb {
+    pub db: Arc<DB>,
+    _dir: TempDir,
+}
+
+impl TestDb {
+    /// Writes the dataset of `cfg`.
+    pub fn new(cfg: &DrainConfig) -> TestDb {
+        let dir = TempDir::new("data").unwrap();
+        let mut db = default_test_db_with_path(dir.path()).unwrap();
+        drain_data_with_config(&mut db, cfg).unwrap();
+        TestDb {
+            db: Arc::new(db),
+            _dir: dir,
+        }
+    }
+
+    /// Writes `rows` rows of middle values, like `drain_data`.
+    pub fn with_rows(rows: u64) -> TestDb {
+        TestDb::new(&DrainConfig::new(rows, ValueType::MiddleValue))
+    }
+
+    pub fn scanner(&self, cfg: &ScannerConfig) -> Scanner {
+        Scanner::new(self.db.clone(), cfg.clone()).unwrap()
+    }
+
+    /// Puts a row of `cf` at the lowest user key and `ts`, where `drain_data` never writes,
+    /// e.g. to break the key layout.
+    pub fn put_stray_row(&self, cf: &str, ts: u64, value: &[u8]) {
+        let key = Key::from_encoded(LOWEST_KEY_STR.to_vec()).append_ts(ts);
+        let cf = get_cf_handle(&self.db, cf).unwrap();
+        self.db.put_cf(cf, key.as_encoded(), value).unwrap();
+    }
+}
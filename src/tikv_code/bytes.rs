@@ -1,13 +1,10 @@
 // Copyright 2016 TiKV Project Authors. Licensed under Apache-2.0.
 
-use byteorder::ReadBytesExt;
-use quick_error;
+use byteorder::{BigEndian, ByteOrder};
+use std::io::{BufRead, Read, Write};
 
-use std::io::{BufRead, Write};
-
-use crate::tikv_code::number::{self, NumberEncoder};
-use std::io;
-use std::io::ErrorKind;
+use crate::tikv_code::number::{self, NumberDecoder, NumberEncoder};
+use crate::tikv_code::{BytesSlice, Error, Result};
 use std::ptr;
 
 const ENC_GROUP_SIZE: usize = 8;
@@ -15,49 +12,6 @@ const ENC_MARKER: u8 = b'\xff';
 const ENC_ASC_PADDING: [u8; ENC_GROUP_SIZE] = [0; ENC_GROUP_SIZE];
 const ENC_DESC_PADDING: [u8; ENC_GROUP_SIZE] = [!0; ENC_GROUP_SIZE];
 
-pub type BytesSlice<'a> = &'a [u8];
-
-#[inline]
-pub fn read_slice<'a>(data: &mut BytesSlice<'a>, size: usize) -> Result<BytesSlice<'a>> {
-    if data.len() >= size {
-        let buf = &data[0..size];
-        *data = &data[size..];
-        Ok(buf)
-    } else {
-        Err(Error::unexpected_eof())
-    }
-}
-
-quick_error! {
-    #[derive(Debug)]
-    pub enum Error {
-        Io(err: io::Error) {
-            from()
-            cause(err)
-            description(err.description())
-        }
-        KeyLength {description("bad format key(length)")}
-        KeyPadding {description("bad format key(padding)")}
-        KeyNotFound {description("key not found")}
-    }
-}
-
-impl Error {
-    pub fn maybe_clone(&self) -> Option<Error> {
-        match *self {
-            Error::KeyLength => Some(Error::KeyLength),
-            Error::KeyPadding => Some(Error::KeyPadding),
-            Error::KeyNotFound => Some(Error::KeyNotFound),
-            Error::Io(_) => None,
-        }
-    }
-    pub fn unexpected_eof() -> Error {
-        Error::Io(io::Error::new(ErrorKind::UnexpectedEof, "eof"))
-    }
-}
-
-pub type Result<T> = std::result::Result<T, Error>;
-
 /// Returns the maximum encoded bytes size.
 pub fn max_encoded_bytes_size(n: usize) -> usize {
     (n / ENC_GROUP_SIZE + 1) * (ENC_GROUP_SIZE + 1)
@@ -158,25 +112,38 @@ pub fn encoded_compact_len(mut encoded: &[u8]) -> usize {
     vn + (encoded.as_ptr() as usize - last_encoded)
 }
 
-pub trait CompactBytesFromFileDecoder: BufRead {
-    /// Decodes bytes which are encoded by `encode_compact_bytes` before.
-    fn decode_compact_bytes(&mut self) -> Result<Vec<u8>> {
-        let mut var_data = Vec::with_capacity(number::MAX_VAR_I64_LEN);
-        while var_data.len() < number::MAX_VAR_U64_LEN {
-            let b = self.read_u8()?;
-            var_data.push(b);
-            if b < 0x80 {
-                break;
+/// Reads the bytes written by `BytesEncoder` from a stream.
+pub trait BytesDecoder: NumberDecoder {
+    /// Reads bytes which are encoded by `encode_bytes` before.
+    fn decode_bytes(&mut self, desc: bool) -> Result<Vec<u8>> {
+        let flip: u64 = if desc { !0 } else { 0 };
+        let mut key = Vec::with_capacity(ENC_GROUP_SIZE);
+        let mut chunk = [0; ENC_GROUP_SIZE + 1];
+        loop {
+            self.read_exact(&mut chunk)?;
+            if decode_group(&chunk, flip, &mut key)? {
+                return Ok(key);
             }
         }
-        let vn = number::decode_var_i64(&mut var_data.as_slice()).unwrap() as usize;
-        let mut data = vec![0; vn];
-        self.read_exact(&mut data)?;
+    }
+
+    /// Reads bytes which are encoded by `encode_compact_bytes` before.
+    fn decode_compact_bytes(&mut self) -> Result<Vec<u8>> {
+        let vn = self.decode_var_i64()?;
+        if vn < 0 {
+            return Err(Error::invalid_data("negative bytes length"));
+        }
+        // do not trust the length to preallocate, the stream may be corrupted.
+        let mut data = vec![];
+        Read::take(self, vn as u64).read_to_end(&mut data)?;
+        if data.len() as u64 != vn as u64 {
+            return Err(Error::unexpected_eof());
+        }
         Ok(data)
     }
 }
 
-impl<T: BufRead> CompactBytesFromFileDecoder for T {}
+impl<T: BufRead> BytesDecoder for T {}
 
 /// Gets the first encoded bytes' length in memcomparable-encoded data.
 ///
@@ -323,3 +290,212 @@ pub fn decode_bytes_in_place(data: &mut Vec<u8>, desc: bool) -> Result<()> {
         }
     }
 }
+
+/// Decodes bytes which are encoded by `encode_bytes` before and appends them to `buf`,
+/// so the caller can reuse `buf` across keys.
+///
+/// Unlike `decode_bytes`, every group is handled as a single `u64` word: the padding
+/// is checked with one mask and the payload is copied as a whole. `data` is advanced
+/// only if the decoding succeeds, and `buf` is left untouched on error.
+#[inline]
+pub fn decode_bytes_into(data: &mut BytesSlice<'_>, buf: &mut Vec<u8>) -> Result<()> {
+    decode_bytes_by_word(data, false, buf)
+}
+
+/// Like `decode_bytes_into`, but for bytes which are encoded by `encode_bytes_desc`.
+#[inline]
+pub fn decode_bytes_desc_into(data: &mut BytesSlice<'_>, buf: &mut Vec<u8>) -> Result<()> {
+    decode_bytes_by_word(data, true, buf)
+}
+
+/// Decodes one `ENC_GROUP_SIZE + 1` bytes group into `buf`, returns whether it is the last one.
+/// `flip` is all ones for descending groups, which flips them back so both orders share the
+/// same checks.
+#[inline(always)]
+fn decode_group(chunk: &[u8], flip: u64, buf: &mut Vec<u8>) -> Result<bool> {
+    let word = BigEndian::read_u64(&chunk[..ENC_GROUP_SIZE]) ^ flip;
+    let pad_size = (ENC_MARKER - (chunk[ENC_GROUP_SIZE] ^ flip as u8)) as usize;
+    if pad_size == 0 {
+        buf.extend_from_slice(&word.to_be_bytes());
+        return Ok(false);
+    }
+    // the padding lies in the lowest `pad_size` bytes of the word.
+    if pad_size > ENC_GROUP_SIZE || word & (!0 >> ((ENC_GROUP_SIZE - pad_size) * 8)) != 0 {
+        return Err(Error::KeyPadding);
+    }
+    buf.extend_from_slice(&word.to_be_bytes()[..ENC_GROUP_SIZE - pad_size]);
+    Ok(true)
+}
+
+#[inline(always)]
+fn decode_bytes_by_word(data: &mut BytesSlice<'_>, desc: bool, buf: &mut Vec<u8>) -> Result<()> {
+    let chunk_len = ENC_GROUP_SIZE + 1;
+    let flip: u64 = if desc { !0 } else { 0 };
+    let origin_len = buf.len();
+    let mut offset = 0;
+    for chunk in data.chunks_exact(chunk_len) {
+        offset += chunk_len;
+        match decode_group(chunk, flip, buf) {
+            Ok(false) => continue,
+            Ok(true) => {
+                // data will point to following unencoded bytes, maybe timestamp
+                *data = &data[offset..];
+                return Ok(());
+            }
+            Err(e) => {
+                buf.truncate(origin_len);
+                return Err(e);
+            }
+        }
+    }
+    buf.truncate(origin_len);
+    Err(Error::unexpected_eof())
+}
+
+#[test]
+fn test_decode_bytes_into_matches_decode_bytes() {
+    use rand::prelude::*;
+
+    let check = |encoded: &[u8], desc: bool| {
+        let mut expected_data = encoded;
+        let expected = decode_bytes(&mut expected_data, desc);
+
+        let mut data = encoded;
+        let mut buf = b"prefix".to_vec();
+        let res = if desc {
+            decode_bytes_desc_into(&mut data, &mut buf)
+        } else {
+            decode_bytes_into(&mut data, &mut buf)
+        };
+        match (expected, res) {
+            (Ok(key), Ok(())) => {
+                assert_eq!(&buf[..6], b"prefix");
+                assert_eq!(&buf[6..], key.as_slice(), "{:?} {}", encoded, desc);
+                assert_eq!(data, expected_data);
+            }
+            (Err(expected), Err(e)) => {
+                assert_eq!(format!("{:?}", expected), format!("{:?}", e));
+                assert_eq!(buf, b"prefix");
+                assert_eq!(data, encoded);
+            }
+            (expected, res) => panic!(
+                "{:?} {}: expect {:?}, got {:?}",
+                encoded, desc, expected, res
+            ),
+        }
+    };
+
+    // every marker against payloads with different padding lengths.
+    for payload_len in 0..=ENC_GROUP_SIZE {
+        for &pad in &[0, !0, 0x5a] {
+            for marker in 0..=255 {
+                let mut chunk = vec![0x11; payload_len];
+                chunk.resize(ENC_GROUP_SIZE, pad);
+                chunk.push(marker);
+                check(&chunk, false);
+                check(&chunk, true);
+            }
+        }
+    }
+
+    let mut rng = thread_rng();
+    for _ in 0..2000 {
+        let mut key = vec![0; rng.gen_range(0, 100)];
+        rng.fill(&mut key[..]);
+        for &desc in &[false, true] {
+            let mut encoded = encode_order_bytes(&key, desc);
+            // trailing bytes, like the timestamp.
+            for _ in 0..rng.gen_range(0, 10) {
+                encoded.push(rng.gen());
+            }
+            check(&encoded, desc);
+            check(&encoded[..rng.gen_range(0, encoded.len())], desc);
+
+            let i = rng.gen_range(0, encoded.len());
+            encoded[i] = rng.gen();
+            check(&encoded, desc);
+        }
+    }
+}
+
+#[test]
+fn test_stream_decoder() {
+    use std::io::BufReader;
+
+    let keys: Vec<Vec<u8>> = (0..20).map(|n| (0..n).collect()).collect();
+    let mut encoded = vec![];
+    for (i, key) in keys.iter().enumerate() {
+        encoded.encode_i64(-(i as i64)).unwrap();
+        encoded.encode_u64_desc(i as u64).unwrap();
+        encoded.encode_var_u64(std::u64::MAX >> i).unwrap();
+        encoded.encode_var_i64(-(i as i64)).unwrap();
+        encoded.encode_f64_desc(i as f64 / 3.0).unwrap();
+        encoded.encode_u32_le(i as u32).unwrap();
+        encoded.encode_bytes(key, i % 2 == 0).unwrap();
+        encoded.encode_compact_bytes(key).unwrap();
+    }
+
+    // a tiny buffer makes the values cross the buffer boundaries.
+    let mut reader = BufReader::with_capacity(3, encoded.as_slice());
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(reader.decode_i64().unwrap(), -(i as i64));
+        assert_eq!(reader.decode_u64_desc().unwrap(), i as u64);
+        assert_eq!(reader.decode_var_u64().unwrap(), std::u64::MAX >> i);
+        assert_eq!(reader.decode_var_i64().unwrap(), -(i as i64));
+        assert_eq!(reader.decode_f64_desc().unwrap(), i as f64 / 3.0);
+        assert_eq!(reader.decode_u32_le().unwrap(), i as u32);
+        assert_eq!(&reader.decode_bytes(i % 2 == 0).unwrap(), key);
+        assert_eq!(&reader.decode_compact_bytes().unwrap(), key);
+    }
+    assert!(reader.decode_u16().is_err());
+
+    let truncated = &encode_bytes(b"truncated")[..10];
+    assert!(BufReader::new(truncated).decode_bytes(false).is_err());
+    assert!([0xff; 12].as_ref().decode_var_u64().is_err());
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn test_memcomparable_bytes_codec(
+        a in proptest::collection::vec(proptest::num::u8::ANY, 0..64),
+        b in proptest::collection::vec(proptest::num::u8::ANY, 0..64),
+        suffix in proptest::collection::vec(proptest::num::u8::ANY, 0..16),
+    ) {
+        for &desc in &[false, true] {
+            let (mut ea, eb) = (encode_order_bytes(&a, desc), encode_order_bytes(&b, desc));
+            prop_assert_eq!(ea.len(), max_encoded_bytes_size(a.len()));
+            prop_assert_eq!(ea.cmp(&eb), if desc { b.cmp(&a) } else { a.cmp(&b) });
+
+            prop_assert_eq!(decode_bytes(&mut ea.as_slice(), desc).unwrap(), a.clone());
+            let mut in_place = ea.clone();
+            decode_bytes_in_place(&mut in_place, desc).unwrap();
+            prop_assert_eq!(&in_place, &a);
+
+            // decoding stops at the end of the encoded bytes, before the suffix.
+            let encoded_len = ea.len();
+            ea.extend_from_slice(&suffix);
+            prop_assert_eq!(encoded_bytes_len(&ea, desc), encoded_len);
+            let mut data = ea.as_slice();
+            decode_bytes(&mut data, desc).unwrap();
+            prop_assert_eq!(data, suffix.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_compact_bytes_codec(
+        a in proptest::collection::vec(proptest::num::u8::ANY, 0..300),
+        suffix in proptest::collection::vec(proptest::num::u8::ANY, 0..16),
+    ) {
+        let mut encoded = vec![];
+        encoded.encode_compact_bytes(&a).unwrap();
+        let encoded_len = encoded.len();
+        encoded.extend_from_slice(&suffix);
+        prop_assert_eq!(encoded_compact_len(&encoded), encoded_len);
+
+        let mut data = encoded.as_slice();
+        prop_assert_eq!(decode_compact_bytes(&mut data).unwrap(), a.clone());
+        prop_assert_eq!(data, suffix.as_slice());
+        prop_assert_eq!(encoded.as_slice().decode_compact_bytes().unwrap(), a);
+    }
+}
@@ -3,3 +3,4 @@
 pub type CfName = &'static str;
 pub const CF_DEFAULT: CfName = "default";
 pub const CF_WRITE: CfName = "write";
+pub const CF_LOCK: CfName = "lock";
@@ -0,0 +1,202 @@
+// Copyright 2016 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! TiDB datum encoding: every value is prefixed by a flag byte telling its kind.
+//!
+//! The comparable form is used in keys, the other one in row values where var-ints and
+//! compact bytes save space.
+
+use crate::tikv_code::bytes::{self, BytesEncoder};
+use crate::tikv_code::decimal::{self, Decimal, DecimalEncoder};
+use crate::tikv_code::number;
+use crate::tikv_code::time::{DateTime, Duration};
+use crate::tikv_code::{read_slice, BytesSlice, Error, Result};
+
+pub const NIL_FLAG: u8 = 0;
+pub const BYTES_FLAG: u8 = 1;
+pub const COMPACT_BYTES_FLAG: u8 = 2;
+pub const INT_FLAG: u8 = 3;
+pub const UINT_FLAG: u8 = 4;
+pub const FLOAT_FLAG: u8 = 5;
+pub const DECIMAL_FLAG: u8 = 6;
+pub const DURATION_FLAG: u8 = 7;
+pub const VAR_INT_FLAG: u8 = 8;
+pub const VAR_UINT_FLAG: u8 = 9;
+pub const JSON_FLAG: u8 = 10;
+pub const MAX_FLAG: u8 = 250;
+
+/// The encoded `NULL`.
+pub const DATUM_DATA_NULL: &[u8; 1] = &[NIL_FLAG];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datum {
+    Null,
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Bytes(Vec<u8>),
+    /// A decimal with the precision of its column.
+    Dec(Decimal, u8),
+    /// Stored as the packed `u64`, so it is decoded as `U64` unless the column type is known.
+    Time(DateTime),
+    Dur(Duration),
+}
+
+pub trait DatumEncoder: BytesEncoder {
+    /// Writes `datum` with its flag, `comparable` chooses the form used in keys.
+    fn encode_datum(&mut self, datum: &Datum, comparable: bool) -> Result<()> {
+        match *datum {
+            Datum::Null => self.write_all(DATUM_DATA_NULL)?,
+            Datum::I64(v) if comparable => {
+                self.write_all(&[INT_FLAG])?;
+                self.encode_i64(v)?;
+            }
+            Datum::I64(v) => {
+                self.write_all(&[VAR_INT_FLAG])?;
+                self.encode_var_i64(v)?;
+            }
+            Datum::U64(v) if comparable => {
+                self.write_all(&[UINT_FLAG])?;
+                self.encode_u64(v)?;
+            }
+            Datum::U64(v) => {
+                self.write_all(&[VAR_UINT_FLAG])?;
+                self.encode_var_u64(v)?;
+            }
+            Datum::F64(v) => {
+                self.write_all(&[FLOAT_FLAG])?;
+                self.encode_f64(v)?;
+            }
+            Datum::Bytes(ref bs) if comparable => {
+                self.write_all(&[BYTES_FLAG])?;
+                self.encode_bytes(bs, false)?;
+            }
+            Datum::Bytes(ref bs) => {
+                self.write_all(&[COMPACT_BYTES_FLAG])?;
+                self.encode_compact_bytes(bs)?;
+            }
+            Datum::Dec(ref d, prec) => {
+                self.write_all(&[DECIMAL_FLAG])?;
+                self.encode_decimal(d, prec, d.frac())?;
+            }
+            Datum::Time(t) => {
+                self.write_all(&[UINT_FLAG])?;
+                self.encode_u64(t.to_packed_u64())?;
+            }
+            Datum::Dur(d) => {
+                self.write_all(&[DURATION_FLAG])?;
+                self.encode_i64(d.to_nanos())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: BytesEncoder> DatumEncoder for T {}
+
+/// Decodes a datum encoded by `encode_datum` in either form.
+pub fn decode_datum(data: &mut BytesSlice<'_>) -> Result<Datum> {
+    let flag = number::read_u8(data)?;
+    let datum = match flag {
+        NIL_FLAG => Datum::Null,
+        INT_FLAG => Datum::I64(number::decode_i64(data)?),
+        VAR_INT_FLAG => Datum::I64(number::decode_var_i64(data)?),
+        UINT_FLAG => Datum::U64(number::decode_u64(data)?),
+        VAR_UINT_FLAG => Datum::U64(number::decode_var_u64(data)?),
+        FLOAT_FLAG => Datum::F64(number::decode_f64(data)?),
+        BYTES_FLAG => Datum::Bytes(bytes::decode_bytes(data, false)?),
+        COMPACT_BYTES_FLAG => Datum::Bytes(decode_compact_bytes(data)?),
+        DECIMAL_FLAG => {
+            let prec = data.first().cloned().ok_or_else(Error::unexpected_eof)?;
+            Datum::Dec(decimal::decode_decimal(data)?, prec)
+        }
+        DURATION_FLAG => Datum::Dur(Duration::from_nanos(number::decode_i64(data)?)?),
+        _ => return Err(Error::invalid_data("unsupported datum flag")),
+    };
+    Ok(datum)
+}
+
+/// Like `bytes::decode_compact_bytes`, but fails instead of panicking on corrupted data.
+fn decode_compact_bytes(data: &mut BytesSlice<'_>) -> Result<Vec<u8>> {
+    let vn = number::decode_var_i64(data)?;
+    if vn < 0 {
+        return Err(Error::invalid_data("negative bytes length"));
+    }
+    read_slice(data, vn as usize).map(|bs| bs.to_vec())
+}
+
+/// Splits the first datum, including its flag, from `data` and returns it with the rest.
+///
+/// `desc` tells whether the comparable bytes are encoded in descending order.
+pub fn split_datum(data: &[u8], desc: bool) -> Result<(&[u8], &[u8])> {
+    if data.is_empty() {
+        return Err(Error::unexpected_eof());
+    }
+    let payload = &data[1..];
+    let len = match data[0] {
+        NIL_FLAG | MAX_FLAG => 0,
+        INT_FLAG | UINT_FLAG | FLOAT_FLAG | DURATION_FLAG => number::U64_SIZE,
+        BYTES_FLAG => bytes::encoded_bytes_len(payload, desc),
+        COMPACT_BYTES_FLAG => {
+            let mut rest = payload;
+            let vn = number::decode_var_i64(&mut rest)?;
+            if vn < 0 {
+                return Err(Error::invalid_data("negative bytes length"));
+            }
+            payload.len() - rest.len() + vn as usize
+        }
+        VAR_INT_FLAG | VAR_UINT_FLAG => {
+            let mut rest = payload;
+            number::decode_var_u64(&mut rest)?;
+            payload.len() - rest.len()
+        }
+        DECIMAL_FLAG => decimal::encoded_decimal_len(payload)?,
+        _ => return Err(Error::invalid_data("unsupported datum flag")),
+    };
+    if len > payload.len() {
+        return Err(Error::unexpected_eof());
+    }
+    Ok(data.split_at(1 + len))
+}
+
+#[test]
+fn test_datum_codec() {
+    let datums = vec![
+        Datum::Null,
+        Datum::I64(-42),
+        Datum::U64(std::u64::MAX),
+        Datum::F64(-0.5),
+        Datum::Bytes(b"t_r_datum".to_vec()),
+        Datum::Dec(Decimal::new(-123_456, 3), 10),
+        Datum::Dur(Duration::from_nanos(-3_600_000_000_000).unwrap()),
+    ];
+    for &comparable in &[false, true] {
+        let mut encoded = vec![];
+        for d in &datums {
+            encoded.encode_datum(d, comparable).unwrap();
+        }
+
+        let (mut data, mut rest) = (encoded.as_slice(), encoded.as_slice());
+        for d in &datums {
+            let (datum, next) = split_datum(rest, false).unwrap();
+            assert_eq!(&decode_datum(&mut data).unwrap(), d);
+            assert_eq!(data, next);
+            assert_eq!(&decode_datum(&mut &datum[..]).unwrap(), d);
+            rest = next;
+        }
+        assert!(rest.is_empty());
+    }
+
+    // a time is stored as its packed form.
+    let t = DateTime::new(2019, 8, 31, 0, 0, 0, 0).unwrap();
+    let mut encoded = vec![];
+    encoded.encode_datum(&Datum::Time(t), false).unwrap();
+    assert_eq!(
+        decode_datum(&mut encoded.as_slice()).unwrap(),
+        Datum::U64(t.to_packed_u64())
+    );
+    assert!(split_datum(&encoded[..5], false).is_err());
+
+    // a duration out of range, the comparable form of `i64::MIN`.
+    let encoded = [DURATION_FLAG, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert!(decode_datum(&mut &encoded[..]).is_err());
+}
@@ -0,0 +1,269 @@
+// Copyright 2016 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! MySQL binary decimal encoding, see `decimal2bin` in MySQL's `strings/decimal.c`.
+//!
+//! Only precisions up to 38 digits are supported, so a decimal fits in an `i128`.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::tikv_code::number::{self, NumberEncoder};
+use crate::tikv_code::{read_slice, BytesSlice, Error, Result};
+
+/// The largest precision that fits in an `i128`.
+pub const MAX_PRECISION: u8 = 38;
+/// The largest fraction digits allowed by MySQL.
+pub const MAX_FRACTION: u8 = 30;
+
+const DIGITS_PER_WORD: u8 = 9;
+const WORD_SIZE: usize = 4;
+const WORD_BASE: u128 = 1_000_000_000;
+/// The bytes needed to store `n` digits of a word.
+const DIG2BYTES: [usize; DIGITS_PER_WORD as usize + 1] = [0, 1, 1, 2, 2, 3, 3, 4, 4, 4];
+
+/// A fixed point number, `value / 10^frac`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Decimal {
+    value: i128,
+    frac: u8,
+}
+
+impl Decimal {
+    pub fn new(value: i128, frac: u8) -> Decimal {
+        Decimal { value, frac }
+    }
+
+    /// The unscaled value.
+    pub fn value(&self) -> i128 {
+        self.value
+    }
+
+    /// The number of fraction digits.
+    pub fn frac(&self) -> u8 {
+        self.frac
+    }
+
+    /// Returns the same number with `frac` fraction digits, it fails if digits would be lost.
+    pub fn rescale(&self, frac: u8) -> Result<Decimal> {
+        if frac < self.frac {
+            let base = pow10(self.frac - frac) as i128;
+            if self.value % base != 0 {
+                return Err(Error::invalid_data("decimal loses digits on rescale"));
+            }
+            return Ok(Decimal::new(self.value / base, frac));
+        }
+        self.value
+            .checked_mul(pow10(frac - self.frac) as i128)
+            .map(|value| Decimal::new(value, frac))
+            .ok_or_else(|| Error::invalid_data("decimal overflow"))
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let abs = self.value.unsigned_abs();
+        let base = pow10(self.frac);
+        if self.value < 0 {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", abs / base)?;
+        if self.frac > 0 {
+            write!(f, ".{:0width$}", abs % base, width = self.frac as usize)?;
+        }
+        Ok(())
+    }
+}
+
+#[inline]
+fn pow10(n: u8) -> u128 {
+    10u128.pow(u32::from(n))
+}
+
+pub(crate) fn check_precision(prec: u8, frac: u8) -> Result<()> {
+    if prec == 0 || prec > MAX_PRECISION || frac > prec || frac > MAX_FRACTION {
+        return Err(Error::invalid_data("unsupported decimal precision"));
+    }
+    Ok(())
+}
+
+/// Gets the size of a decimal with `prec` digits, `frac` of which are fraction digits,
+/// in binary form.
+pub fn decimal_bin_size(prec: u8, frac: u8) -> usize {
+    let intg = prec - frac;
+    let word_size = |digits: u8| {
+        (digits / DIGITS_PER_WORD) as usize * WORD_SIZE
+            + DIG2BYTES[(digits % DIGITS_PER_WORD) as usize]
+    };
+    word_size(intg) + word_size(frac)
+}
+
+/// Writes the lowest `size` bytes of `word` in big endian order.
+#[inline]
+fn write_word(buf: &mut Vec<u8>, word: u128, size: usize) {
+    buf.extend_from_slice(&(word as u32).to_be_bytes()[WORD_SIZE - size..]);
+}
+
+#[inline]
+fn read_word(data: &[u8], digits: u8) -> Result<u128> {
+    let word = data.iter().fold(0u128, |w, &b| w << 8 | u128::from(b));
+    if word >= pow10(digits) {
+        return Err(Error::invalid_data("bad format decimal"));
+    }
+    Ok(word)
+}
+
+/// Encodes `d` in binary form, which is memcomparable for the same `prec` and `frac`.
+fn encode_decimal_bin(d: &Decimal, prec: u8, frac: u8, buf: &mut Vec<u8>) -> Result<()> {
+    check_precision(prec, frac)?;
+    if d.frac > frac {
+        return Err(Error::invalid_data(
+            "decimal has more fraction digits than its type",
+        ));
+    }
+    let d = d.rescale(frac)?;
+    let intg = prec - frac;
+    let abs = d.value.unsigned_abs();
+    let (intg_part, frac_part) = (abs / pow10(frac), abs % pow10(frac));
+    if intg_part >= pow10(intg) {
+        return Err(Error::invalid_data("decimal overflow"));
+    }
+
+    let start = buf.len();
+    let (intg_words, lead) = (intg / DIGITS_PER_WORD, intg % DIGITS_PER_WORD);
+    write_word(
+        buf,
+        intg_part / pow10(intg_words * DIGITS_PER_WORD),
+        DIG2BYTES[lead as usize],
+    );
+    for i in (0..intg_words).rev() {
+        write_word(
+            buf,
+            intg_part / pow10(i * DIGITS_PER_WORD) % WORD_BASE,
+            WORD_SIZE,
+        );
+    }
+    let (frac_words, trail) = (frac / DIGITS_PER_WORD, frac % DIGITS_PER_WORD);
+    for i in 1..=frac_words {
+        let word = frac_part / pow10(frac - i * DIGITS_PER_WORD) % WORD_BASE;
+        write_word(buf, word, WORD_SIZE);
+    }
+    write_word(buf, frac_part % pow10(trail), DIG2BYTES[trail as usize]);
+
+    let encoded = &mut buf[start..];
+    if d.value < 0 {
+        for b in encoded.iter_mut() {
+            *b = !*b;
+        }
+    }
+    encoded[0] ^= 0x80;
+    Ok(())
+}
+
+/// Decodes a decimal encoded by `encode_decimal_bin`.
+fn decode_decimal_bin(data: &mut BytesSlice<'_>, prec: u8, frac: u8) -> Result<Decimal> {
+    check_precision(prec, frac)?;
+    let mut bin = read_slice(data, decimal_bin_size(prec, frac))?.to_vec();
+    let negative = bin[0] & 0x80 == 0;
+    bin[0] ^= 0x80;
+    if negative {
+        for b in bin.iter_mut() {
+            *b = !*b;
+        }
+    }
+
+    let mut rest = bin.as_slice();
+    let mut next_word = |digits: u8| -> Result<u128> {
+        let size = DIG2BYTES[digits as usize];
+        let word = read_word(&rest[..size], digits)?;
+        rest = &rest[size..];
+        Ok(word)
+    };
+
+    let intg = prec - frac;
+    let mut value = next_word(intg % DIGITS_PER_WORD)?;
+    for _ in 0..intg / DIGITS_PER_WORD {
+        value = value * WORD_BASE + next_word(DIGITS_PER_WORD)?;
+    }
+    for _ in 0..frac / DIGITS_PER_WORD {
+        value = value * WORD_BASE + next_word(DIGITS_PER_WORD)?;
+    }
+    let trail = frac % DIGITS_PER_WORD;
+    value = value * pow10(trail) + next_word(trail)?;
+
+    let value = value as i128;
+    Ok(Decimal::new(if negative { -value } else { value }, frac))
+}
+
+pub trait DecimalEncoder: NumberEncoder {
+    /// Writes `prec`, `frac` and then the binary form of `d`, like the datum payload in TiDB.
+    fn encode_decimal(&mut self, d: &Decimal, prec: u8, frac: u8) -> Result<()> {
+        let mut buf = Vec::with_capacity(2 + decimal_bin_size(prec, frac));
+        buf.push(prec);
+        buf.push(frac);
+        encode_decimal_bin(d, prec, frac, &mut buf)?;
+        self.write_all(&buf).map_err(From::from)
+    }
+}
+
+impl<T: NumberEncoder + ?Sized> DecimalEncoder for T {}
+
+/// Decodes a decimal encoded by `encode_decimal`.
+pub fn decode_decimal(data: &mut BytesSlice<'_>) -> Result<Decimal> {
+    let prec = number::read_u8(data)?;
+    let frac = number::read_u8(data)?;
+    decode_decimal_bin(data, prec, frac)
+}
+
+/// Gets the length of the decimal encoded by `encode_decimal` in the head of `data`.
+pub fn encoded_decimal_len(data: &[u8]) -> Result<usize> {
+    if data.len() < 2 {
+        return Err(Error::unexpected_eof());
+    }
+    check_precision(data[0], data[1])?;
+    Ok(2 + decimal_bin_size(data[0], data[1]))
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn test_decimal_codec(
+        prec in 1u8..=MAX_PRECISION,
+        frac_seed: u8,
+        a: i128,
+        b: i128,
+    ) {
+        let frac = frac_seed % (prec.min(MAX_FRACTION) + 1);
+        let bound = pow10(prec) as i128;
+        let (a, b) = (Decimal::new(a % bound, frac), Decimal::new(b % bound, frac));
+
+        let (mut ea, mut eb) = (vec![], vec![]);
+        ea.encode_decimal(&a, prec, frac).unwrap();
+        eb.encode_decimal(&b, prec, frac).unwrap();
+        prop_assert_eq!(ea.len(), encoded_decimal_len(&ea).unwrap());
+        prop_assert_eq!(ea.cmp(&eb), a.value().cmp(&b.value()));
+
+        let mut data = ea.as_slice();
+        prop_assert_eq!(decode_decimal(&mut data).unwrap(), a);
+        prop_assert!(data.is_empty());
+    }
+}
+
+#[test]
+fn test_decimal_bin() {
+    // the examples of 1234567890.1234 in decimal(14, 4) from MySQL's `decimal2bin`.
+    let mut buf = vec![];
+    encode_decimal_bin(&Decimal::new(12_345_678_901_234, 4), 14, 4, &mut buf).unwrap();
+    assert_eq!(buf, vec![0x81, 0x0D, 0xFB, 0x38, 0xD2, 0x04, 0xD2]);
+    buf.clear();
+    encode_decimal_bin(&Decimal::new(-12_345_678_901_234, 4), 14, 4, &mut buf).unwrap();
+    assert_eq!(buf, vec![0x7E, 0xF2, 0x04, 0xC7, 0x2D, 0xFB, 0x2D]);
+    assert_eq!(
+        Decimal::new(-12_345_678_901_234, 4).to_string(),
+        "-1234567890.1234"
+    );
+    assert_eq!(Decimal::new(5, 3).to_string(), "0.005");
+
+    // overflow, extra fraction digits and bad digits are rejected.
+    assert!(encode_decimal_bin(&Decimal::new(1000, 0), 3, 0, &mut buf).is_err());
+    assert!(encode_decimal_bin(&Decimal::new(1, 5), 10, 4, &mut buf).is_err());
+    assert!(decode_decimal_bin(&mut [0xFF, 0xFF].as_ref(), 4, 0).is_err());
+}
@@ -1,7 +1,8 @@
 use crate::tikv_code::bytes;
 use crate::tikv_code::bytes::*;
 use crate::tikv_code::number::{self, NumberEncoder};
-use byteorder::{ByteOrder, NativeEndian};
+use crate::tikv_code::{Error, Result};
+use byteorder::{BigEndian, ByteOrder, NativeEndian};
 use hex::ToHex;
 use std::fmt;
 use std::fmt::Display;
@@ -16,7 +17,7 @@ use std::fmt::Formatter;
 ///
 /// Orthogonal to binary representation, keys may or may not embed a timestamp,
 /// but this information is transparent to this type, the caller must use it
-/// consistently.
+/// consistently. Prefer `TimestampedKey` when the key is known to embed one.
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Key(Vec<u8>);
 
@@ -58,79 +59,72 @@ impl Key {
         self.0
     }
 
+    /// Decodes the raw representation of this key.
+    #[inline]
+    pub fn to_raw(&self) -> Result<Vec<u8>> {
+        bytes::decode_bytes(&mut self.0.as_slice(), false)
+    }
+
     /// Creates a new key by appending a `u64` timestamp to this key.
     #[inline]
-    pub fn append_ts(self, ts: u64) -> Key {
+    pub fn append_ts(self, ts: u64) -> TimestampedKey {
         let mut encoded = self.0;
         encoded.encode_u64_desc(ts).unwrap();
-        Key(encoded)
+        TimestampedKey(Key(encoded))
     }
 
     /// Gets the timestamp contained in this key.
     ///
-    /// Preconditions: the caller must ensure this is actually a timestamped
-    /// key.
+    /// Returns `Error::KeyLength` if the key is too short to contain a timestamp.
     #[inline]
-    pub fn decode_ts(&self) -> u64 {
+    pub fn decode_ts(&self) -> Result<u64> {
         Self::decode_ts_from(&self.0)
     }
 
     /// Creates a new key by truncating the timestamp from this key.
     ///
-    /// Preconditions: the caller must ensure this is actually a timestamped key.
+    /// Returns `Error::KeyLength` if the key is too short to contain a timestamp.
     #[inline]
-    pub fn truncate_ts(mut self) -> Key {
+    pub fn truncate_ts(mut self) -> Result<Key> {
         let len = self.0.len();
         if len < number::U64_SIZE {
-            // TODO: IMHO, this should be an assertion failure instead of
-            // returning an error. If this happens, it indicates a bug in
-            // the caller module, have to make code change to fix it.
-            //
-            // Even if it passed the length check, it still could be buggy,
-            // a better way is to introduce a type `TimestampedKey`, and
-            // functions to convert between `TimestampedKey` and `Key`.
-            // `TimestampedKey` is in a higher (MVCC) layer, while `Key` is
-            // in the core storage engine layer.
-            panic!("反正有问题");
-        } else {
-            self.0.truncate(len - number::U64_SIZE);
-            self
+            return Err(Error::KeyLength);
         }
+        self.0.truncate(len - number::U64_SIZE);
+        Ok(self)
     }
 
     /// Split a ts encoded key, return the user key and timestamp.
     #[inline]
-    pub fn split_on_ts_for(key: &[u8]) -> (&[u8], u64) {
+    pub fn split_on_ts_for(key: &[u8]) -> Result<(&[u8], u64)> {
         if key.len() < number::U64_SIZE {
-            //            Err(codec::Error::KeyLength)
-            panic!("反正有问题");
-        } else {
-            let pos = key.len() - number::U64_SIZE;
-            let k = &key[..pos];
-            let mut ts = &key[pos..];
-            (k, number::decode_u64_desc(&mut ts).unwrap())
+            return Err(Error::KeyLength);
         }
+        let pos = key.len() - number::U64_SIZE;
+        let k = &key[..pos];
+        let mut ts = &key[pos..];
+        Ok((k, number::decode_u64_desc(&mut ts)?))
     }
 
     /// Extract the user key from a ts encoded key.
     #[inline]
-    pub fn truncate_ts_for(key: &[u8]) -> &[u8] {
+    pub fn truncate_ts_for(key: &[u8]) -> Result<&[u8]> {
         let len = key.len();
         if len < number::U64_SIZE {
-            panic!("反正有问题");
+            return Err(Error::KeyLength);
         }
-        &key[..key.len() - number::U64_SIZE]
+        Ok(&key[..key.len() - number::U64_SIZE])
     }
 
     /// Decode the timestamp from a ts encoded key.
     #[inline]
-    pub fn decode_ts_from(key: &[u8]) -> u64 {
+    pub fn decode_ts_from(key: &[u8]) -> Result<u64> {
         let len = key.len();
         if len < number::U64_SIZE {
-            panic!("反正有问题");
+            return Err(Error::KeyLength);
         }
         let mut ts = &key[len - number::U64_SIZE..];
-        number::decode_u64_desc(&mut ts).unwrap()
+        number::decode_u64_desc(&mut ts)
     }
 
     /// Whether the user key part of a ts encoded key `ts_encoded_key` equals to the encoded
@@ -178,3 +172,135 @@ impl Display for Key {
         self.0.write_hex_upper(f)
     }
 }
+
+/// A key with a `u64` timestamp appended, i.e. the keys stored in `CF_WRITE` and
+/// `CF_DEFAULT`.
+///
+/// `TimestampedKey` is in a higher (MVCC) layer, while `Key` is in the core
+/// storage engine layer. It always holds at least the timestamp, so the
+/// timestamp related functions can't fail.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct TimestampedKey(Key);
+
+impl TimestampedKey {
+    /// Creates a timestamped key from encoded bytes vector, which must end with a
+    /// timestamp.
+    #[inline]
+    pub fn from_encoded(encoded_key: Vec<u8>) -> Result<TimestampedKey> {
+        if encoded_key.len() < number::U64_SIZE {
+            return Err(Error::KeyLength);
+        }
+        Ok(TimestampedKey(Key(encoded_key)))
+    }
+
+    /// Creates a timestamped key from encoded bytes slice, which must end with a
+    /// timestamp.
+    #[inline]
+    pub fn from_encoded_slice(encoded_key: &[u8]) -> Result<TimestampedKey> {
+        TimestampedKey::from_encoded(encoded_key.to_vec())
+    }
+
+    /// Gets the encoded representation of this key.
+    #[inline]
+    pub fn as_encoded(&self) -> &Vec<u8> {
+        self.0.as_encoded()
+    }
+
+    /// Gets and moves the encoded representation of this key.
+    #[inline]
+    pub fn into_encoded(self) -> Vec<u8> {
+        self.0.into_encoded()
+    }
+
+    /// Gets the encoded user key part of this key.
+    #[inline]
+    pub fn user_key(&self) -> &[u8] {
+        let encoded = self.as_encoded();
+        &encoded[..encoded.len() - number::U64_SIZE]
+    }
+
+    /// Gets the timestamp contained in this key.
+    #[inline]
+    pub fn decode_ts(&self) -> u64 {
+        let encoded = self.as_encoded();
+        !BigEndian::read_u64(&encoded[encoded.len() - number::U64_SIZE..])
+    }
+
+    /// Creates the user key by truncating the timestamp from this key.
+    #[inline]
+    pub fn truncate_ts(self) -> Key {
+        self.split().0
+    }
+
+    /// Splits this key into the user key and the timestamp.
+    #[inline]
+    pub fn split(self) -> (Key, u64) {
+        let ts = self.decode_ts();
+        let mut encoded = (self.0).0;
+        let len = encoded.len();
+        encoded.truncate(len - number::U64_SIZE);
+        (Key(encoded), ts)
+    }
+}
+
+impl Display for TimestampedKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[test]
+fn test_key_too_short_for_ts() {
+    let short = [1u8; number::U64_SIZE - 1];
+    assert!(Key::decode_ts_from(&short).is_err());
+    assert!(Key::truncate_ts_for(&short).is_err());
+    assert!(Key::split_on_ts_for(&short).is_err());
+    assert!(Key::from_encoded_slice(&short).truncate_ts().is_err());
+    assert!(TimestampedKey::from_encoded_slice(&short).is_err());
+}
+
+#[test]
+fn test_timestamped_key() {
+    let user_key = Key::from_raw(b"t_r_key");
+    let key = user_key.clone().append_ts(42);
+    assert_eq!(key.decode_ts(), 42);
+    assert_eq!(key.user_key(), user_key.as_encoded().as_slice());
+    assert_eq!(Key::split_on_ts_for(key.as_encoded()).unwrap().1, 42);
+
+    let parsed = TimestampedKey::from_encoded_slice(key.as_encoded()).unwrap();
+    assert_eq!(parsed, key);
+    let (k, ts) = parsed.split();
+    assert_eq!((k.to_raw().unwrap(), ts), (b"t_r_key".to_vec(), 42));
+    assert_eq!(key.truncate_ts(), user_key);
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn test_key_ts_order(
+        a in proptest::collection::vec(proptest::num::u8::ANY, 0..32),
+        b in proptest::collection::vec(proptest::num::u8::ANY, 0..32),
+        ts_a: u64,
+        ts_b: u64,
+    ) {
+        let key_a = Key::from_raw(&a);
+        prop_assert_eq!(key_a.to_raw().unwrap(), a.clone());
+
+        let ka = key_a.clone().append_ts(ts_a);
+        prop_assert_eq!(ka.decode_ts(), ts_a);
+        prop_assert_eq!(Key::decode_ts_from(ka.as_encoded()).unwrap(), ts_a);
+        prop_assert!(Key::is_user_key_eq(ka.as_encoded(), key_a.as_encoded()));
+        prop_assert_eq!(ka.clone().truncate_ts(), key_a.clone());
+
+        // newer versions of the same user key sort first.
+        let newer = key_a.append_ts(ts_b);
+        prop_assert_eq!(ka.cmp(&newer), ts_b.cmp(&ts_a));
+
+        // the user key decides the order of different keys whatever their ts is.
+        let kb = Key::from_raw(&b).append_ts(ts_b);
+        if a != b {
+            prop_assert_eq!(ka.cmp(&kb), a.cmp(&b));
+            prop_assert!(!Key::is_user_key_eq(ka.as_encoded(), kb.user_key()));
+        }
+    }
+}
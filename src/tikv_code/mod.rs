@@ -8,7 +8,62 @@ pub mod number;
 pub mod key;
 
 #[allow(unused)]
-mod bytes;
+pub mod bytes;
+
+pub mod datum;
+pub mod decimal;
+pub mod time;
 
 pub use crate::tikv_code::cfg::*;
 pub use crate::tikv_code::constexpr::*;
+
+use std::io::{self, ErrorKind};
+
+// The codec types below are shared by `number`, `bytes` and `key`.
+
+pub type BytesSlice<'a> = &'a [u8];
+
+#[allow(unused)]
+#[inline]
+pub fn read_slice<'a>(data: &mut BytesSlice<'a>, size: usize) -> Result<BytesSlice<'a>> {
+    if data.len() >= size {
+        let buf = &data[0..size];
+        *data = &data[size..];
+        Ok(buf)
+    } else {
+        Err(Error::unexpected_eof())
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Io(err: io::Error) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        KeyLength {description("bad format key(length)")}
+        KeyPadding {description("bad format key(padding)")}
+        KeyNotFound {description("key not found")}
+    }
+}
+
+impl Error {
+    pub fn maybe_clone(&self) -> Option<Error> {
+        match *self {
+            Error::KeyLength => Some(Error::KeyLength),
+            Error::KeyPadding => Some(Error::KeyPadding),
+            Error::KeyNotFound => Some(Error::KeyNotFound),
+            Error::Io(_) => None,
+        }
+    }
+    pub fn unexpected_eof() -> Error {
+        Error::Io(io::Error::new(ErrorKind::UnexpectedEof, "eof"))
+    }
+    pub fn invalid_data(msg: &str) -> Error {
+        Error::Io(io::Error::new(ErrorKind::InvalidData, msg))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
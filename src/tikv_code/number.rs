@@ -1,51 +1,10 @@
 // Copyright 2016 TiKV Project Authors. Licensed under Apache-2.0.
 
-use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
-use std::io::{self, ErrorKind, Write};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, BufRead, ErrorKind, Write};
 use std::mem;
 
-pub type BytesSlice<'a> = &'a [u8];
-
-#[inline]
-pub fn read_slice<'a>(data: &mut BytesSlice<'a>, size: usize) -> Result<BytesSlice<'a>> {
-    if data.len() >= size {
-        let buf = &data[0..size];
-        *data = &data[size..];
-        Ok(buf)
-    } else {
-        Err(Error::unexpected_eof())
-    }
-}
-
-quick_error! {
-    #[derive(Debug)]
-    pub enum Error {
-        Io(err: io::Error) {
-            from()
-            cause(err)
-            description(err.description())
-        }
-        KeyLength {description("bad format key(length)")}
-        KeyPadding {description("bad format key(padding)")}
-        KeyNotFound {description("key not found")}
-    }
-}
-
-impl Error {
-    pub fn maybe_clone(&self) -> Option<Error> {
-        match *self {
-            Error::KeyLength => Some(Error::KeyLength),
-            Error::KeyPadding => Some(Error::KeyPadding),
-            Error::KeyNotFound => Some(Error::KeyNotFound),
-            Error::Io(_) => None,
-        }
-    }
-    pub fn unexpected_eof() -> Error {
-        Error::Io(io::Error::new(ErrorKind::UnexpectedEof, "eof"))
-    }
-}
-
-pub type Result<T> = std::result::Result<T, Error>;
+use crate::tikv_code::{BytesSlice, Error, Result};
 
 const SIGN_MARK: u64 = 0x8000000000000000;
 pub const MAX_VAR_I64_LEN: usize = 10;
@@ -184,6 +143,112 @@ pub trait NumberEncoder: Write {
 
 impl<T: Write> NumberEncoder for T {}
 
+/// Reads the values written by `NumberEncoder` from a stream, so large dumps can be
+/// decoded without loading them into memory first.
+pub trait NumberDecoder: BufRead {
+    /// Reads the value encoded by `encode_i64`.
+    fn decode_i64(&mut self) -> Result<i64> {
+        self.decode_u64().map(order_decode_i64)
+    }
+
+    /// Reads the value encoded by `encode_i64_desc`.
+    fn decode_i64_desc(&mut self) -> Result<i64> {
+        self.decode_u64_desc().map(order_decode_i64)
+    }
+
+    /// Reads the value encoded by `encode_u64`.
+    fn decode_u64(&mut self) -> Result<u64> {
+        self.read_u64::<BigEndian>().map_err(From::from)
+    }
+
+    /// Reads the value encoded by `encode_u64_desc`.
+    fn decode_u64_desc(&mut self) -> Result<u64> {
+        self.decode_u64().map(|v| !v)
+    }
+
+    /// Reads the value encoded by `encode_u32`.
+    fn decode_u32(&mut self) -> Result<u32> {
+        self.read_u32::<BigEndian>().map_err(From::from)
+    }
+
+    /// Reads the value encoded by `encode_u16`.
+    fn decode_u16(&mut self) -> Result<u16> {
+        self.read_u16::<BigEndian>().map_err(From::from)
+    }
+
+    /// Reads the value encoded by `encode_var_i64`.
+    fn decode_var_i64(&mut self) -> Result<i64> {
+        let mut bytes = [0; MAX_VAR_U64_LEN];
+        let len = read_var_bytes(self, &mut bytes)?;
+        decode_var_i64(&mut &bytes[..len])
+    }
+
+    /// Reads the value encoded by `encode_var_u64`.
+    fn decode_var_u64(&mut self) -> Result<u64> {
+        let mut bytes = [0; MAX_VAR_U64_LEN];
+        let len = read_var_bytes(self, &mut bytes)?;
+        decode_var_u64(&mut &bytes[..len])
+    }
+
+    /// Reads the value encoded by `encode_f64`.
+    fn decode_f64(&mut self) -> Result<f64> {
+        self.decode_u64().map(order_decode_f64)
+    }
+
+    /// Reads the value encoded by `encode_f64_desc`.
+    fn decode_f64_desc(&mut self) -> Result<f64> {
+        self.decode_u64_desc().map(order_decode_f64)
+    }
+
+    /// Reads `u16` numbers in little endian order.
+    fn decode_u16_le(&mut self) -> Result<u16> {
+        self.read_u16::<LittleEndian>().map_err(From::from)
+    }
+
+    /// Reads `u32` numbers in little endian order.
+    fn decode_u32_le(&mut self) -> Result<u32> {
+        self.read_u32::<LittleEndian>().map_err(From::from)
+    }
+
+    /// Reads `i32` numbers in little endian order.
+    fn decode_i32_le(&mut self) -> Result<i32> {
+        self.read_i32::<LittleEndian>().map_err(From::from)
+    }
+
+    /// Reads `f64` numbers in little endian order.
+    fn decode_f64_le(&mut self) -> Result<f64> {
+        self.read_f64::<LittleEndian>().map_err(From::from)
+    }
+
+    /// Reads `i64` numbers in little endian order.
+    fn decode_i64_le(&mut self) -> Result<i64> {
+        self.read_i64::<LittleEndian>().map_err(From::from)
+    }
+
+    /// Reads `u64` numbers in little endian order.
+    fn decode_u64_le(&mut self) -> Result<u64> {
+        self.read_u64::<LittleEndian>().map_err(From::from)
+    }
+}
+
+impl<T: BufRead> NumberDecoder for T {}
+
+/// Reads the bytes of a var-int, up to and including its last byte, into `bytes`.
+/// The number of bytes read is returned.
+fn read_var_bytes<R: BufRead + ?Sized>(
+    reader: &mut R,
+    bytes: &mut [u8; MAX_VAR_U64_LEN],
+) -> Result<usize> {
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = reader.read_u8()?;
+        if *b < 0x80 {
+            return Ok(i + 1);
+        }
+    }
+    // let the slice decoder report the overflow.
+    Ok(MAX_VAR_U64_LEN)
+}
+
 #[inline]
 fn read_num_bytes<T, F>(size: usize, data: &mut &[u8], f: F) -> Result<T>
 where
@@ -354,3 +419,101 @@ pub fn read_u8(data: &mut BytesSlice<'_>) -> Result<u8> {
         Err(Error::unexpected_eof())
     }
 }
+
+/// Checks that `encode` round trips both values and keeps (or, for `desc`, reverses)
+/// their order in the encoded bytes.
+#[cfg(test)]
+fn check_number_codec<T, E, D>(
+    a: T,
+    b: T,
+    desc: bool,
+    encode: E,
+    decode: D,
+) -> std::result::Result<(), proptest::test_runner::TestCaseError>
+where
+    T: PartialOrd + Copy + std::fmt::Debug,
+    E: Fn(&mut Vec<u8>, T) -> Result<()>,
+    D: Fn(&mut BytesSlice<'_>) -> Result<T>,
+{
+    let (mut ea, mut eb) = (vec![], vec![]);
+    encode(&mut ea, a).unwrap();
+    encode(&mut eb, b).unwrap();
+
+    let mut data = ea.as_slice();
+    prop_assert_eq!(decode(&mut data).unwrap(), a);
+    prop_assert!(data.is_empty());
+    prop_assert_eq!(decode(&mut eb.as_slice()).unwrap(), b);
+
+    if a < b {
+        prop_assert_eq!(ea < eb, !desc);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn test_fixed_number_codec(a: i64, b: i64, ua: u64, ub: u64) {
+        check_number_codec(a, b, false, |buf, v| buf.encode_i64(v), decode_i64)?;
+        check_number_codec(a, b, true, |buf, v| buf.encode_i64_desc(v), decode_i64_desc)?;
+        check_number_codec(ua, ub, false, |buf, v| buf.encode_u64(v), decode_u64)?;
+        check_number_codec(ua, ub, true, |buf, v| buf.encode_u64_desc(v), decode_u64_desc)?;
+        let (ua32, ub32) = (ua as u32, ub as u32);
+        check_number_codec(ua32, ub32, false, |buf, v| buf.encode_u32(v), decode_u32)?;
+        let (ua16, ub16) = (ua as u16, ub as u16);
+        check_number_codec(ua16, ub16, false, |buf, v| buf.encode_u16(v), decode_u16)?;
+    }
+
+    #[test]
+    fn test_f64_codec(a in proptest::num::f64::ANY, b in proptest::num::f64::ANY) {
+        // NaN has no order and does not equal itself, so it is only round-tripped by its bits.
+        for &v in &[a, b, f64::NAN, -f64::NAN] {
+            let mut buf = vec![];
+            buf.encode_f64(v).unwrap();
+            prop_assert_eq!(decode_f64(&mut buf.as_slice()).unwrap().to_bits(), v.to_bits());
+            buf.clear();
+            buf.encode_f64_desc(v).unwrap();
+            prop_assert_eq!(decode_f64_desc(&mut buf.as_slice()).unwrap().to_bits(), v.to_bits());
+        }
+        prop_assume!(!a.is_nan() && !b.is_nan());
+        check_number_codec(a, b, false, |buf, v| buf.encode_f64(v), decode_f64)?;
+        check_number_codec(a, b, true, |buf, v| buf.encode_f64_desc(v), decode_f64_desc)?;
+    }
+
+    #[test]
+    fn test_var_number_codec(i: i64, u: u64) {
+        let mut buf = vec![];
+        buf.encode_var_i64(i).unwrap();
+        prop_assert!(buf.len() <= MAX_VAR_I64_LEN);
+        prop_assert_eq!(decode_var_i64(&mut buf.as_slice()).unwrap(), i);
+
+        buf.clear();
+        buf.encode_var_u64(u).unwrap();
+        prop_assert!(buf.len() <= MAX_VAR_U64_LEN);
+        // a truncated encoding must never decode.
+        prop_assert!(decode_var_u64(&mut &buf[..buf.len() - 1]).is_err());
+        let mut data = buf.as_slice();
+        prop_assert_eq!(decode_var_u64(&mut data).unwrap(), u);
+        prop_assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_little_endian_codec(i: i64, u: u64, f in proptest::num::f64::ANY) {
+        let mut buf = vec![];
+        buf.encode_u16_le(u as u16).unwrap();
+        buf.encode_u32_le(u as u32).unwrap();
+        buf.encode_i32_le(i as i32).unwrap();
+        buf.encode_f64_le(f).unwrap();
+        buf.encode_i64_le(i).unwrap();
+        buf.encode_u64_le(u).unwrap();
+
+        let mut data = buf.as_slice();
+        prop_assert_eq!(decode_u16_le(&mut data).unwrap(), u as u16);
+        prop_assert_eq!(decode_u32_le(&mut data).unwrap(), u as u32);
+        prop_assert_eq!(decode_i32_le(&mut data).unwrap(), i as i32);
+        prop_assert_eq!(decode_f64_le(&mut data).unwrap().to_bits(), f.to_bits());
+        prop_assert_eq!(decode_i64_le(&mut data).unwrap(), i);
+        prop_assert_eq!(decode_u64_le(&mut data).unwrap(), u);
+        prop_assert!(data.is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright 2016 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! MySQL datetime, timestamp and duration values in the forms TiDB stores them:
+//! a datetime or timestamp (in UTC) is packed into a `u64`, a duration is `i64` nanoseconds.
+//! Both keep the order of the values when encoded by `encode_u64` and `encode_i64`.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::tikv_code::{Error, Result};
+
+const MICROS_PER_SEC: i64 = 1_000_000;
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+const SECS_PER_DAY: i64 = 86_400;
+
+/// The days between 0000-03-01 and 1970-01-01.
+const UNIX_EPOCH_DAYS: i64 = 719_468;
+
+/// A MySQL `DATETIME` or `TIMESTAMP`, zero dates like `0000-00-00` are allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    micro: u32,
+}
+
+impl DateTime {
+    pub fn new(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+        micro: u32,
+    ) -> Result<DateTime> {
+        if year > 9999
+            || month > 12
+            || day > 31
+            || hour > 23
+            || minute > 59
+            || second > 59
+            || i64::from(micro) >= MICROS_PER_SEC
+        {
+            return Err(Error::invalid_data("invalid datetime"));
+        }
+        Ok(DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            micro,
+        })
+    }
+
+    /// Packs the datetime into a `u64` like TiDB's `ToPackedUint`.
+    pub fn to_packed_u64(&self) -> u64 {
+        let ymd = (u64::from(self.year) * 13 + u64::from(self.month)) << 5 | u64::from(self.day);
+        let hms = u64::from(self.hour) << 12 | u64::from(self.minute) << 6 | u64::from(self.second);
+        (ymd << 17 | hms) << 24 | u64::from(self.micro)
+    }
+
+    /// Unpacks the datetime packed by `to_packed_u64`.
+    pub fn from_packed_u64(packed: u64) -> Result<DateTime> {
+        let ymdhms = packed >> 24;
+        let (ymd, hms) = (ymdhms >> 17, ymdhms & ((1 << 17) - 1));
+        let ym = ymd >> 5;
+        if ym / 13 > u64::from(u16::MAX) {
+            return Err(Error::invalid_data("invalid datetime"));
+        }
+        DateTime::new(
+            (ym / 13) as u16,
+            (ym % 13) as u8,
+            (ymd & 0x1f) as u8,
+            (hms >> 12) as u8,
+            (hms >> 6 & 0x3f) as u8,
+            (hms & 0x3f) as u8,
+            (packed & ((1 << 24) - 1)) as u32,
+        )
+    }
+
+    /// Builds the UTC datetime of microseconds since the unix epoch, as TiDB stores a timestamp.
+    pub fn from_unix_micros(micros: i64) -> Result<DateTime> {
+        let secs = micros.div_euclid(MICROS_PER_SEC);
+        let (days, secs_of_day) = (secs.div_euclid(SECS_PER_DAY), secs.rem_euclid(SECS_PER_DAY));
+
+        // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + UNIX_EPOCH_DAYS;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        if !(0..=9999).contains(&year) {
+            return Err(Error::invalid_data("datetime out of range"));
+        }
+
+        DateTime::new(
+            year as u16,
+            month as u8,
+            day as u8,
+            (secs_of_day / 3600) as u8,
+            (secs_of_day / 60 % 60) as u8,
+            (secs_of_day % 60) as u8,
+            micros.rem_euclid(MICROS_PER_SEC) as u32,
+        )
+    }
+
+    /// Gets the microseconds since the unix epoch, taking the datetime as UTC.
+    pub fn to_unix_micros(&self) -> Result<i64> {
+        if self.month == 0 || self.day == 0 {
+            return Err(Error::invalid_data("zero date has no unix time"));
+        }
+        // days from civil
+        let (month, day) = (i64::from(self.month), i64::from(self.day));
+        let year = i64::from(self.year) - if month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - UNIX_EPOCH_DAYS;
+
+        let secs = days * SECS_PER_DAY
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second);
+        Ok(secs * MICROS_PER_SEC + i64::from(self.micro))
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )?;
+        if self.micro > 0 {
+            write!(f, ".{:06}", self.micro)?;
+        }
+        Ok(())
+    }
+}
+
+/// The largest MySQL `TIME`, 838:59:59.
+pub const MAX_DURATION_NANOS: i64 = (838 * 3600 + 59 * 60 + 59) * NANOS_PER_SEC;
+
+/// A MySQL `TIME`, stored as nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Duration {
+    nanos: i64,
+}
+
+impl Duration {
+    pub fn from_nanos(nanos: i64) -> Result<Duration> {
+        if nanos.unsigned_abs() > MAX_DURATION_NANOS as u64 {
+            return Err(Error::invalid_data("duration out of range"));
+        }
+        Ok(Duration { nanos })
+    }
+
+    pub fn to_nanos(&self) -> i64 {
+        self.nanos
+    }
+}
+
+impl Display for Duration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.nanos < 0 {
+            write!(f, "-")?;
+        }
+        let nanos = self.nanos.abs();
+        let secs = nanos / NANOS_PER_SEC;
+        write!(
+            f,
+            "{:02}:{:02}:{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )?;
+        let micros = nanos % NANOS_PER_SEC / 1000;
+        if micros > 0 {
+            write!(f, ".{:06}", micros)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_time_codec() {
+    let t = DateTime::new(2019, 8, 31, 23, 59, 58, 123_456).unwrap();
+    assert_eq!(t.to_string(), "2019-08-31 23:59:58.123456");
+    assert_eq!(DateTime::from_packed_u64(t.to_packed_u64()).unwrap(), t);
+    assert_eq!(t.to_unix_micros().unwrap(), 1_567_295_998_123_456);
+    assert_eq!(
+        DateTime::from_unix_micros(1_567_295_998_123_456).unwrap(),
+        t
+    );
+
+    // the packed form keeps the order of datetimes, the zero date sorts first.
+    let mut times = vec![DateTime::new(0, 0, 0, 0, 0, 0, 0).unwrap()];
+    let mut micros = -86_400_000_000;
+    while micros < 4_000_000_000_000_000 {
+        times.push(DateTime::from_unix_micros(micros).unwrap());
+        assert_eq!(times.last().unwrap().to_unix_micros().unwrap(), micros);
+        micros += micros.abs() / 2 + 7_654_321_987;
+    }
+    for w in times.windows(2) {
+        assert!(w[0] < w[1]);
+        assert!(w[0].to_packed_u64() < w[1].to_packed_u64());
+    }
+    assert!(DateTime::new(2019, 13, 1, 0, 0, 0, 0).is_err());
+    assert!(DateTime::from_unix_micros(std::i64::MAX).is_err());
+
+    let d = Duration::from_nanos(-(838 * 3600 + 5) * NANOS_PER_SEC - 1_000).unwrap();
+    assert_eq!(d.to_string(), "-838:00:05.000001");
+    assert!(Duration::from_nanos(MAX_DURATION_NANOS + 1).is_err());
+    assert!(Duration::from_nanos(std::i64::MIN).is_err());
+}